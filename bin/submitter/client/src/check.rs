@@ -0,0 +1,389 @@
+//! Offline integrity check of the on-disk databases. Run it while the submitter is stopped.
+
+use anyhow::Result;
+use ethers::types::{Address, U256};
+use primitives::{
+    func::block_number_convert_to_h256,
+    storage::Storage,
+    traits::StataTrait,
+    types::{BlocksStateData, Chain, Event, ProfitStateData, ProfitStatistics},
+};
+use serde::{Deserialize, Serialize};
+use state::{Keccak256Hasher, State, H256};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+use txs::{
+    funcs::get_one_block_txs_hash,
+    rocks_db::TxsRocksDB,
+    sled_db::{BlockTxsCountDB, ContractBlockInfoDB, ProfitStatisticsDB, PrunedTxsDB},
+};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RootCheck {
+    pub stored: H256,
+    pub recomputed: H256,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BlockChainCheck {
+    pub blocks: u64,
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+    // Blocks missing between the first and the last archived block.
+    pub gaps: Vec<u64>,
+    // Blocks whose root is not `into_chain` of the previous block.
+    pub broken_links: Vec<u64>,
+    // Blocks whose txs hash does not match the txs in `TxsRocksDB`.
+    pub txs_hash_mismatches: Vec<u64>,
+    // Whether the last block's profit root is the current profit root.
+    pub last_profit_root_ok: bool,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxsCountMismatch {
+    pub block_number: u64,
+    pub count: u64,
+    pub txs: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxsCountCheck {
+    pub checked: u64,
+    // Blocks whose next block info is missing, so the txs range is unknown, or whose txs are
+    // pruned.
+    pub skipped: u64,
+    pub mismatches: Vec<TxsCountMismatch>,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitStatisticsMismatch {
+    pub address: Address,
+    pub chain_id: u64,
+    pub token: Address,
+    pub stored: Option<ProfitStatistics>,
+    pub replayed: Option<ProfitStatistics>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitStatisticsCheck {
+    pub checked: u64,
+    // Pruned txs can not be replayed, so the statistics are not checked.
+    pub skipped: bool,
+    pub mismatches: Vec<ProfitStatisticsMismatch>,
+    pub ok: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct CheckReport {
+    pub profit_root: RootCheck,
+    pub blocks_root: RootCheck,
+    pub block_chain: BlockChainCheck,
+    pub txs_counts: TxsCountCheck,
+    pub profit_statistics: ProfitStatisticsCheck,
+    pub ok: bool,
+}
+
+pub fn check_db(
+    profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    storage: Arc<Storage>,
+    txs_db: Arc<TxsRocksDB>,
+) -> Result<CheckReport> {
+    let profit_state = profit_state.read().unwrap();
+    let blocks_state = blocks_state.read().unwrap();
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let block_txs_count_db = BlockTxsCountDB::new(storage.clone())?;
+    let profit_statistics_db = ProfitStatisticsDB::new(storage.clone())?;
+    let pruned_txs_db = PrunedTxsDB::new(storage.clone())?;
+    let pruned_through = pruned_txs_db.get_pruned_through()?;
+
+    let mut report = CheckReport::default();
+
+    let stored = profit_state.try_get_root()?;
+    let recomputed = profit_state.try_compute_root_from_leaves()?;
+    report.profit_root = RootCheck {
+        stored,
+        recomputed,
+        ok: stored == recomputed,
+    };
+    let stored = blocks_state.try_get_root()?;
+    let recomputed = blocks_state.try_compute_root_from_leaves()?;
+    report.blocks_root = RootCheck {
+        stored,
+        recomputed,
+        ok: stored == recomputed,
+    };
+
+    // Replayed statistics, keyed like `ProfitStatisticsDB`.
+    let mut replayed: BTreeMap<(Address, u64, Address), ProfitStatistics> = BTreeMap::new();
+
+    let mut blocks: Vec<BlocksStateData> = blocks_state
+        .try_get_leaves()?
+        .into_iter()
+        .map(|(k, v)| {
+            if k != block_number_convert_to_h256(v.block_num) {
+                report.block_chain.broken_links.push(v.block_num);
+            }
+            v
+        })
+        .collect();
+    blocks.sort_by_key(|b| b.block_num);
+    report.block_chain.blocks = blocks.len() as u64;
+    report.block_chain.first_block = blocks.first().map(|b| b.block_num);
+    report.block_chain.last_block = blocks.last().map(|b| b.block_num);
+    report.block_chain.last_profit_root_ok = blocks.last().map_or(true, |b| {
+        H256::from(b.profit_root) == report.profit_root.stored
+    });
+    let mut last_block: Option<BlocksStateData> = None;
+    for block in blocks {
+        let block_num = block.block_num;
+        // The first archived block is chained to an empty block.
+        let last = match last_block.take() {
+            Some(last) => {
+                for gap in last.block_num + 1..block_num {
+                    report.block_chain.gaps.push(gap);
+                }
+                if last.block_num + 1 == block_num {
+                    last
+                } else {
+                    blocks_state
+                        .try_get(block_number_convert_to_h256(block_num.saturating_sub(1)))?
+                }
+            }
+            None => BlocksStateData::default(),
+        };
+        let mut chained = BlocksStateData {
+            txs: block.txs,
+            block_num,
+            profit_root: block.profit_root,
+            ..Default::default()
+        };
+        chained.into_chain(last);
+        if chained.root != block.root {
+            report.block_chain.broken_links.push(block_num);
+        }
+
+        // Txs archived with this block, as in `submit_root`.
+        let now_block_info = block_info_db.get_block_info(block_num)?;
+        let last_block_info = block_info_db.get_block_info(block_num.saturating_sub(1))?;
+        if let Some(tx_hashes) = pruned_txs_db.get_hashes(block_num)? {
+            if H256::from(block.txs) != get_one_block_txs_hash(tx_hashes) {
+                report.block_chain.txs_hash_mismatches.push(block_num);
+            }
+        } else if let (Some(now_block_info), Some(last_block_info)) =
+            (now_block_info, last_block_info)
+        {
+            let txs = txs_db.get_txs_by_timestamp_range(
+                last_block_info.storage.block_timestamp,
+                now_block_info.storage.block_timestamp,
+            )?;
+            let mut tx_hashes = vec![];
+            for (tx, profit) in txs {
+                if profit.profit == U256::zero() {
+                    continue;
+                }
+                let chain_id = profit.chain_id;
+                let token = profit.token;
+                replayed
+                    .entry((profit.maker_address, chain_id, token))
+                    .or_default()
+                    .total_withdrawn += profit.profit;
+                replayed
+                    .entry((profit.dealer_address, chain_id, token))
+                    .or_default()
+                    .total_profit += profit.profit;
+                tx_hashes.push(tx.target_id);
+            }
+            if H256::from(block.txs) != get_one_block_txs_hash(tx_hashes) {
+                report.block_chain.txs_hash_mismatches.push(block_num);
+            }
+        }
+        last_block = Some(block);
+    }
+    report.block_chain.ok = report.block_chain.gaps.is_empty()
+        && report.block_chain.broken_links.is_empty()
+        && report.block_chain.txs_hash_mismatches.is_empty()
+        && report.block_chain.last_profit_root_ok;
+
+    // Events are counted when the block info is crawled, as in `crawl_block_info`.
+    for block_info in block_info_db.iter_block_infos() {
+        for e in block_info?.events {
+            match e {
+                Event::Withdraw(w_e) => {
+                    replayed
+                        .entry((w_e.address, w_e.chain_id, w_e.token_address))
+                        .or_default()
+                        .total_withdrawn += w_e.balance;
+                }
+                Event::Deposit(d_e) => {
+                    replayed
+                        .entry((d_e.address, d_e.chain_id, d_e.token_address))
+                        .or_default()
+                        .total_deposit += d_e.balance;
+                }
+            }
+        }
+    }
+
+    for item in block_txs_count_db.iter_counts() {
+        let (block_num, count) = item?;
+        // Txs of block `n` are archived with block `n + 1`.
+        if pruned_through.map_or(false, |b| block_num < b) {
+            report.txs_counts.skipped += 1;
+            continue;
+        }
+        // Txs of block `n` are in [timestamp of `n`, timestamp of `n + 1`).
+        let (now_block_info, next_block_info) = match (
+            block_info_db.get_block_info(block_num)?,
+            block_info_db.get_block_info(block_num + 1)?,
+        ) {
+            (Some(now), Some(next)) => (now, next),
+            _ => {
+                report.txs_counts.skipped += 1;
+                continue;
+            }
+        };
+        let txs = txs_db
+            .get_txs_by_timestamp_range(
+                now_block_info.storage.block_timestamp,
+                next_block_info.storage.block_timestamp,
+            )?
+            .len() as u64;
+        report.txs_counts.checked += 1;
+        if txs != count {
+            report.txs_counts.mismatches.push(TxsCountMismatch {
+                block_number: block_num,
+                count,
+                txs,
+            });
+        }
+    }
+    report.txs_counts.mismatches.sort_by_key(|m| m.block_number);
+    report.txs_counts.ok = report.txs_counts.mismatches.is_empty();
+
+    let mut stored: BTreeMap<(Address, u64, Address), ProfitStatistics> = match pruned_through {
+        Some(_) => {
+            report.profit_statistics.skipped = true;
+            replayed.clear();
+            BTreeMap::new()
+        }
+        None => profit_statistics_db
+            .get_all_profit_statistics()?
+            .into_iter()
+            .collect(),
+    };
+    for (key, replayed) in replayed {
+        let stored = stored.remove(&key);
+        report.profit_statistics.checked += 1;
+        if stored.as_ref() != Some(&replayed) {
+            report
+                .profit_statistics
+                .mismatches
+                .push(ProfitStatisticsMismatch {
+                    address: key.0,
+                    chain_id: key.1,
+                    token: key.2,
+                    stored,
+                    replayed: Some(replayed),
+                });
+        }
+    }
+    for (key, stored) in stored {
+        report.profit_statistics.checked += 1;
+        report
+            .profit_statistics
+            .mismatches
+            .push(ProfitStatisticsMismatch {
+                address: key.0,
+                chain_id: key.1,
+                token: key.2,
+                stored: Some(stored),
+                replayed: None,
+            });
+    }
+    report.profit_statistics.ok = report.profit_statistics.mismatches.is_empty();
+
+    report.ok = report.profit_root.ok
+        && report.blocks_root.ok
+        && report.block_chain.ok
+        && report.txs_counts.ok
+        && report.profit_statistics.ok;
+    Ok(report)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use txs::fixtures::{archived_db, DEALER, TOKEN};
+
+    #[test]
+    fn test_check_db() {
+        let (profit_state, blocks_state, storage, txs_db) = archived_db("./db_check");
+        let check = || {
+            check_db(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                txs_db.clone(),
+            )
+            .unwrap()
+        };
+        let report = check();
+        assert!(report.ok, "{:?}", report);
+        assert_eq!(report.block_chain.blocks, 3);
+        assert_eq!(report.txs_counts.checked, 2);
+
+        // One more tx counted for block 1 than saved.
+        BlockTxsCountDB::new(storage.clone())
+            .unwrap()
+            .increase_count(1, 1)
+            .unwrap();
+        // Block 3 archived with other txs.
+        let key = block_number_convert_to_h256(3);
+        let mut block = blocks_state.read().unwrap().try_get(key).unwrap();
+        block.txs = [9u8; 32];
+        blocks_state
+            .write()
+            .unwrap()
+            .try_update_all(vec![(key, block)])
+            .unwrap();
+        // Profit of the dealer counted twice.
+        let profit_statistics_db = ProfitStatisticsDB::new(storage.clone()).unwrap();
+        profit_statistics_db
+            .update_total_profit(DEALER, 1, TOKEN, U256::from(7))
+            .unwrap();
+
+        let report = check();
+        assert!(!report.ok);
+        assert_eq!(
+            report.txs_counts.mismatches,
+            vec![TxsCountMismatch {
+                block_number: 1,
+                count: 2,
+                txs: 1,
+            }]
+        );
+        assert_eq!(report.block_chain.txs_hash_mismatches, vec![3]);
+        assert_eq!(report.profit_statistics.mismatches.len(), 1);
+        let mismatch = &report.profit_statistics.mismatches[0];
+        assert_eq!(
+            (mismatch.address, mismatch.chain_id, mismatch.token),
+            (DEALER, 1, TOKEN)
+        );
+        assert_eq!(
+            mismatch.stored.as_ref().map(|s| s.total_profit),
+            Some(U256::from(19))
+        );
+        assert_eq!(
+            mismatch.replayed.as_ref().map(|s| s.total_profit),
+            Some(U256::from(12))
+        );
+        // The blocks root still matches its leaves.
+        assert!(report.blocks_root.ok);
+    }
+}
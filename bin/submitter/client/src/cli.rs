@@ -1,6 +1,13 @@
 use super::{
-    rpc::{DebugApiServerImpl, SubmitterApiServerImpl},
-    Args,
+    check::check_db,
+    replay::replay,
+    rest::RestLayer,
+    rpc::{
+        discover_module, DebugApiServerImpl, SubmitterApiServerImpl, SubmitterPubSubApiServerImpl,
+    },
+    server::{load_debug_token, start_debug_rpc_server, start_rpc_server},
+    snapshot::{export_state, import_state},
+    Args, Command,
 };
 use anyhow::Result;
 use clap::Parser;
@@ -8,27 +15,35 @@ use contract::{run as contract_run, SubmitterContract};
 use dialoguer::Password;
 use dotenv::dotenv;
 use ethers::{prelude::*, signers::LocalWallet};
-use jsonrpsee::{server::ServerBuilder, Methods};
-use lazy_static::lazy_static;
-use primitives::env::get_start_block;
+use jsonrpsee::Methods;
+use primitives::env::{
+    get_mainnet_rpc_urls, get_rpc_rest_enabled, get_start_block, get_txs_retain_roots,
+};
 use primitives::{
-    env::get_chains_info_source_url,
+    constants::SUBMITTER_EVENTS_CAPACITY,
     func::chain_token_address_convert_to_h256,
-    traits::{DebugApiServer, StataTrait, SubmitterApiServer},
-    types::{BlockInfo, BlocksStateData, ProfitStateData},
+    openrpc::{DEBUG_OPENRPC, SUBMITTER_OPENRPC},
+    storage::{Storage, BLOCKS_STATE_PREFIX, PROFIT_STATE_PREFIX},
+    traits::{DebugApiServer, StataTrait, SubmitterApiServer, SubmitterPubSubApiServer},
+    types::{BlockInfo, BlocksStateData, ProfitStateData, SubmitterEvent},
 };
-use sled;
-use state::{Keccak256Hasher, Open, OptimisticTransactionDB, State, H256};
+use state::{Keccak256Hasher, State, H256};
 use std::{
     str::FromStr,
     sync::{Arc, RwLock},
 };
-use tokio::sync::OnceCell;
 use tracing::{event, Level};
 use tracing_appender::rolling::daily;
 use txs::rocks_db::TxsRocksDB;
-use txs::{funcs::SupportChains, Submitter};
-use utils::vec_unique;
+use txs::{
+    checkpoint::{create_checkpoint, list_checkpoints, restore_checkpoint, STORE_DIR},
+    load_support_set,
+    prune::prune_txs,
+    schema::{check_and_migrate, SCHEMA_VERSION},
+    stats::TxStatsDB,
+    withdraw::build_withdraw_call,
+    Submitter,
+};
 
 pub struct JsonRpcServer {
     pub mothods: Methods,
@@ -80,11 +95,6 @@ impl<'a>
     }
 }
 
-lazy_static! {
-    static ref PROFIT_STATE_DB_PATH: OnceCell<String> = OnceCell::new();
-    static ref BLOCKS_STATE_DB_PATH: OnceCell<String> = OnceCell::new();
-}
-
 pub async fn run() -> Result<()> {
     dotenv().ok();
 
@@ -104,20 +114,162 @@ pub async fn run() -> Result<()> {
         args.db_path
     );
 
-    let profit_state_db_path = format!("{}/profit", args.db_path);
-    let blocks_state_db_path = format!("{}/blocks", args.db_path);
+    // The store must not be open while it is replaced.
+    if let Some(Command::RestoreCheckpoint { name }) = &args.command {
+        let info = restore_checkpoint(&args.db_path, name)?;
+        println!("checkpoint restored: {:?}", info);
+        return Ok(());
+    }
 
-    PROFIT_STATE_DB_PATH.set(profit_state_db_path).unwrap();
-    BLOCKS_STATE_DB_PATH.set(blocks_state_db_path).unwrap();
-    assert_ne!(
-        PROFIT_STATE_DB_PATH
-            .get()
-            .expect("profit state db' path not set"),
-        BLOCKS_STATE_DB_PATH
-            .get()
-            .expect("blocks state db' path not set"),
+    let storage_path = format!("{}/{}", args.db_path, STORE_DIR);
+    let storage = Arc::new(Storage::open(&storage_path)?);
+    event!(Level::INFO, "Storage is opened! path: {:?}", storage_path);
+    let schema_version = check_and_migrate(&storage, &args.db_path)?;
+    event!(
+        Level::INFO,
+        "Db schema version: {:?}, opened at version {:?}.",
+        SCHEMA_VERSION,
+        schema_version,
     );
 
+    let profit_state = Arc::new(RwLock::new(
+        State::<'_, Keccak256Hasher, ProfitStateData>::new(PROFIT_STATE_PREFIX, storage.clone()),
+    ));
+    let blocks_state = Arc::new(RwLock::new(
+        State::<'_, Keccak256Hasher, BlocksStateData>::new(BLOCKS_STATE_PREFIX, storage.clone()),
+    ));
+    let txs_db = Arc::new(TxsRocksDB::new(storage.clone())?);
+
+    let mut start_block = get_start_block();
+    match args.command {
+        Some(Command::ExportState { out, block }) => {
+            let header = export_state(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                &out,
+                block,
+            )?;
+            println!("state exported: {:?}", header);
+            return Ok(());
+        }
+        Some(Command::ImportState {
+            file,
+            verify_onchain,
+        }) => {
+            let header = import_state(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                &file,
+                verify_onchain,
+            )
+            .await?;
+            println!("state imported: {:?}", header);
+            event!(
+                Level::INFO,
+                "Resume from Block #{:?}, set START_BLOCK to {:?} for later restarts.",
+                header.block_number + 1,
+                header.block_number,
+            );
+            start_block = header.block_number;
+        }
+        Some(Command::CheckDb) => {
+            let report = check_db(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                txs_db.clone(),
+            )?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.ok {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Replay {
+            from,
+            to,
+            out,
+            saved_profit,
+        }) => {
+            let out = out.unwrap_or(format!("{}/replay", args.db_path));
+            let report = replay(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                txs_db.clone(),
+                &out,
+                from..=to,
+                saved_profit,
+            )?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.ok {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::PruneTxs { retain_roots }) => {
+            let report = prune_txs(
+                &storage,
+                &txs_db,
+                &blocks_state,
+                retain_roots.unwrap_or(get_txs_retain_roots()),
+                0,
+            )?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+        Some(Command::WithdrawCall {
+            address,
+            tokens,
+            amounts,
+        }) => {
+            let tokens = tokens
+                .iter()
+                .map(|t| parse_chain_token(t))
+                .collect::<Result<Vec<(u64, Address)>>>()?;
+            let amounts = if amounts.is_empty() {
+                None
+            } else {
+                Some(
+                    amounts
+                        .iter()
+                        .map(|a| U256::from_dec_str(a))
+                        .collect::<std::result::Result<Vec<U256>, _>>()?,
+                )
+            };
+            let call = build_withdraw_call(
+                &Provider::<Http>::try_from(get_mainnet_rpc_urls()[0].clone())?,
+                &storage,
+                &profit_state,
+                &blocks_state,
+                &txs::sled_db::ProfitChangesDB::new(storage.clone())?,
+                address,
+                tokens,
+                amounts,
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&call)?);
+            if call.simulation_error.is_some() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Checkpoint) => {
+            let info = create_checkpoint(&storage, &profit_state, &blocks_state, &args.db_path)?;
+            println!("checkpoint created: {:?}", info);
+            return Ok(());
+        }
+        Some(Command::ListCheckpoints) => {
+            let checkpoints = list_checkpoints(&args.db_path)?;
+            println!("{}", serde_json::to_string_pretty(&checkpoints)?);
+            return Ok(());
+        }
+        Some(Command::RestoreCheckpoint { .. }) => unreachable!(),
+        None => {}
+    }
+
     let private_key: String;
     if args.no_private_key {
         event!(Level::WARN, "No private key, can not submit root.");
@@ -133,41 +285,6 @@ pub async fn run() -> Result<()> {
     let wallet = Arc::new(LocalWallet::from_str(&private_key)?);
     event!(Level::INFO, "The wallet is created.");
 
-    let profit_state = Arc::new(RwLock::new(
-        State::<'_, Keccak256Hasher, ProfitStateData>::new(
-            PROFIT_STATE_DB_PATH
-                .get()
-                .expect("profit state db' path not set")
-                .as_ref(),
-            OptimisticTransactionDB::open_default(
-                PROFIT_STATE_DB_PATH
-                    .get()
-                    .expect("profit state db' path not set"),
-            )?,
-        ),
-    ));
-    event!(
-        Level::INFO,
-        "Profit state's db is created! path is: {:?}",
-        PROFIT_STATE_DB_PATH.get().unwrap()
-    );
-    let blocks_state = Arc::new(RwLock::new(
-        State::<'_, Keccak256Hasher, BlocksStateData>::new(
-            BLOCKS_STATE_DB_PATH
-                .get()
-                .expect("blocks state db' path not set")
-                .as_ref(),
-            OptimisticTransactionDB::open_default(
-                BLOCKS_STATE_DB_PATH.get().expect("state db' path not set"),
-            )?,
-        ),
-    ));
-    event!(
-        Level::INFO,
-        "Blocks state's db is created! path: {:?}",
-        BLOCKS_STATE_DB_PATH.get().unwrap()
-    );
-
     let client = Client::new(
         wallet.clone(),
         rpc_server_port,
@@ -176,48 +293,64 @@ pub async fn run() -> Result<()> {
     );
     event!(Level::INFO, "The client is created.");
 
-    let server = ServerBuilder::new()
-        .build(format!("127.0.0.1:{}", client.rpc_server_port))
-        .await?;
-    let addr = server.local_addr()?;
-    let sled_db = Arc::new(sled::open(args.db_path.clone()).unwrap());
-    let user_tokens_db = Arc::new(txs::sled_db::UserTokensDB::new(sled_db.clone()).unwrap());
+    let user_tokens_db = Arc::new(txs::sled_db::UserTokensDB::new(storage.clone()).unwrap());
     let profit_statistics_db =
-        Arc::new(txs::sled_db::ProfitStatisticsDB::new(sled_db.clone()).unwrap());
-    let txs_db = Arc::new(TxsRocksDB::new(args.db_path.clone()).unwrap());
+        Arc::new(txs::sled_db::ProfitStatisticsDB::new(storage.clone()).unwrap());
+    let missed_txs_db = Arc::new(txs::sled_db::MissedTxsDB::new(storage.clone()).unwrap());
+    let support_set_db = Arc::new(txs::sled_db::SupportSetDB::new(storage.clone()).unwrap());
+    let profit_changes_db = Arc::new(txs::sled_db::ProfitChangesDB::new(storage.clone()).unwrap());
+    let (events, _) = tokio::sync::broadcast::channel::<SubmitterEvent>(SUBMITTER_EVENTS_CAPACITY);
+    let submitter_api = SubmitterApiServerImpl {
+        state: profit_state.clone(),
+        blocks_state: blocks_state.clone(),
+        user_tokens_db: user_tokens_db.clone(),
+        profit_statistics_db: profit_statistics_db.clone(),
+        txs_db: txs_db.clone(),
+        missed_txs_db: missed_txs_db.clone(),
+        support_set_db: support_set_db.clone(),
+        profit_changes_db: profit_changes_db.clone(),
+        storage: storage.clone(),
+        provider: Provider::<Http>::try_from(get_mainnet_rpc_urls()[0].clone())?,
+        tx_stats_db: Arc::new(TxStatsDB::new(storage.clone())?),
+    };
+    rpc_server.add_mothod(submitter_api.clone().into_rpc())?;
     rpc_server.add_mothod(
-        SubmitterApiServerImpl {
-            state: profit_state.clone(),
-            blocks_state: blocks_state.clone(),
-            user_tokens_db: user_tokens_db.clone(),
-            profit_statistics_db: profit_statistics_db.clone(),
-            txs_db: txs_db.clone(),
+        SubmitterPubSubApiServerImpl {
+            events: events.clone(),
         }
         .into_rpc(),
     )?;
+    rpc_server.add_mothod(discover_module(SUBMITTER_OPENRPC)?)?;
+    let rest = get_rpc_rest_enabled().then(|| RestLayer {
+        api: Arc::new(submitter_api),
+    });
+    let addr = start_rpc_server(client.rpc_server_port, rpc_server.mothods.clone(), rest).await?;
+    event!(Level::INFO, "Rpc server start at: {:?}", addr);
+
+    // The debug methods can rewrite the state, so they are only served on their own listener.
     if args.debug {
-        rpc_server.add_mothod(
-            DebugApiServerImpl {
-                state: profit_state.clone(),
-                user_tokens_db: user_tokens_db.clone(),
-            }
-            .into_rpc(),
-        )?;
+        let token = load_debug_token(&args.db_path)?;
+        let mut debug_methods: Methods = DebugApiServerImpl {
+            state: profit_state.clone(),
+            blocks_state: blocks_state.clone(),
+            user_tokens_db: user_tokens_db.clone(),
+            storage: storage.clone(),
+            db_path: args.db_path.clone(),
+        }
+        .into_rpc()
+        .into();
+        debug_methods.merge(discover_module(DEBUG_OPENRPC)?)?;
+        let debug_addr = start_debug_rpc_server(args.debug_rpc_port, debug_methods, token).await?;
+        event!(Level::INFO, "Debug rpc server start at: {:?}", debug_addr);
         // tokio::spawn(insert_profit_by_count(100_0000, profit_state.clone()));
     }
 
-    let server_handle = server.start(rpc_server.mothods.clone())?;
-
-    event!(Level::INFO, "Rpc server start at: {:?}", addr);
-    tokio::spawn(server_handle.stopped());
-
-    let start_block_num1 = Arc::new(tokio::sync::RwLock::new(get_start_block()));
+    let start_block_num1 = Arc::new(tokio::sync::RwLock::new(start_block));
     let (s, _r) = tokio::sync::broadcast::channel::<BlockInfo>(100);
-    let support_chains_crawler = SupportChains::new(get_chains_info_source_url());
-    let tokens: Arc<Vec<Address>> = Arc::new(vec_unique::<Address>(
-        support_chains_crawler.get_mainnet_support_tokens().await?,
-    ));
-    println!("support tokens: {:?}", tokens.clone());
+    let support_set = load_support_set(storage.clone())?;
+    println!("support tokens: {:?}", support_set.tokens.clone());
+    let tokens: Arc<tokio::sync::RwLock<Vec<Address>>> =
+        Arc::new(tokio::sync::RwLock::new(support_set.tokens));
     let contract = Arc::new(
         SubmitterContract::new(
             s.clone(),
@@ -233,15 +366,16 @@ pub async fn run() -> Result<()> {
         event!(Level::INFO, "contract start");
     });
 
-    let start_block_num = Arc::new(RwLock::new(get_start_block()));
+    let start_block_num = Arc::new(RwLock::new(start_block));
     let submitter = Submitter::new(
         profit_state.clone(),
         blocks_state.clone(),
         contract.clone(),
         start_block_num.clone(),
-        sled_db.clone(),
+        storage.clone(),
         txs_db.clone(),
         args.db_path,
+        events,
     );
     tokio::spawn(async move {
         submitter.run().await.unwrap();
@@ -255,6 +389,14 @@ pub async fn run() -> Result<()> {
     Ok(())
 }
 
+// `chain_id:token`
+pub(crate) fn parse_chain_token(s: &str) -> Result<(u64, Address)> {
+    let (chain_id, token) = s
+        .split_once(':')
+        .ok_or(anyhow::anyhow!("{} is not chain_id:token", s))?;
+    Ok((chain_id.parse()?, Address::from_str(token)?))
+}
+
 #[allow(dead_code)]
 async fn insert_profit_by_count(
     count: u64,
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 #[command(name = "submitter")]
@@ -8,8 +8,14 @@ pub struct Args {
     pub rpc_port: u16,
     #[arg(long, default_value_t = String::from("db"), help = "state db's path")]
     pub db_path: String,
-    #[arg(long, default_value_t = false, help = "debug mode")]
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "debug mode, serve the debug rpc on `debug_rpc_port`"
+    )]
     pub debug: bool,
+    #[arg(long, default_value_t = 50002, help = "debug rpc server's port")]
+    pub debug_rpc_port: u16,
     // #[arg(long, default_value_t = 9754418, help = "start block")]
     // pub start_block: u64,
     #[arg(
@@ -18,4 +24,78 @@ pub struct Args {
         help = "do not use password. can not submit root."
     )]
     pub no_private_key: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Export the state at the last archived block to a snapshot file.
+    ExportState {
+        #[arg(long, help = "snapshot file's path")]
+        out: String,
+        #[arg(long, help = "expected last archived block")]
+        block: Option<u64>,
+    },
+    /// Import a snapshot file into an empty db, then resume sync from the next block.
+    ImportState {
+        #[arg(long, help = "snapshot file's path")]
+        file: String,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "also check the profit root against the on-chain root"
+        )]
+        verify_onchain: bool,
+    },
+    /// Check that the state trees, the txs db and the key-value trees are consistent, and print a
+    /// JSON report. Exits with 1 if any check fails.
+    CheckDb,
+    /// Re-archive blocks from the saved block infos and txs into a new directory, and compare
+    /// them with the archived blocks. Exits with 1 if any block differs.
+    Replay {
+        #[arg(long, help = "first block to replay")]
+        from: u64,
+        #[arg(long, help = "last block to replay")]
+        to: u64,
+        #[arg(long, help = "replay db's path, `{db_path}/replay` by default")]
+        out: Option<String>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "use the profit saved with each tx instead of recalculating it"
+        )]
+        saved_profit: bool,
+    },
+    /// Prune the txs of blocks covered by `retain_roots` newer submitted roots, compact the db
+    /// and print a JSON report.
+    PruneTxs {
+        #[arg(
+            long,
+            help = "submitted roots to keep the txs of, `TXS_RETAIN_ROOTS` by default"
+        )]
+        retain_roots: Option<u64>,
+    },
+    /// Print a ready-to-sign withdraw of `address` against the last submitted root, simulated with
+    /// `eth_call`. Exits with 1 if the simulation reverts.
+    WithdrawCall {
+        #[arg(long, help = "address to withdraw the profit of")]
+        address: ethers::types::Address,
+        #[arg(long = "token", help = "`chain_id:token` to withdraw, repeatable")]
+        tokens: Vec<String>,
+        #[arg(
+            long = "amount",
+            help = "amount of each token in wei, the whole balance by default"
+        )]
+        amounts: Vec<String>,
+    },
+    /// Checkpoint the db into `{db_path}/checkpoints`, tagged with the last archived block.
+    Checkpoint,
+    /// List the checkpoints, oldest first.
+    ListCheckpoints,
+    /// Verify a checkpoint and switch the db to it. The replaced data is moved aside.
+    RestoreCheckpoint {
+        #[arg(long, help = "checkpoint's name")]
+        name: String,
+    },
 }
@@ -1,6 +1,11 @@
+mod check;
 mod cli;
 mod command;
+mod replay;
+mod rest;
 mod rpc;
+mod server;
+mod snapshot;
 
 pub use cli::run;
-pub use command::Args;
+pub use command::{Args, Command};
@@ -0,0 +1,252 @@
+//! Re-execute archived blocks from the local inputs into a fresh state directory, and compare the
+//! resulting blocks with the archived ones. Run it while the submitter is stopped.
+
+use anyhow::{anyhow, Result};
+use primitives::{
+    func::{block_number_convert_to_h256, chain_token_address_convert_to_h256},
+    storage::{Storage, BLOCKS_STATE_PREFIX, PROFIT_STATE_PREFIX},
+    traits::StataTrait,
+    types::{BlocksStateData, Debt, ProfitStateData},
+};
+use serde::{Deserialize, Serialize};
+use state::{Keccak256Hasher, State, H256};
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    sync::{Arc, RwLock},
+};
+use tracing::{event, Level};
+use txs::{
+    archive::Archiver,
+    rocks_db::TxsRocksDB,
+    schema::check_and_migrate,
+    sled_db::{ContractBlockInfoDB, MakerProfitDB, ProfitChangesDB, PrunedTxsDB},
+};
+
+// Leaves are written to the fresh profit state in batches of this size.
+const SEED_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ReplayMismatch {
+    pub block_number: u64,
+    pub archived: BlocksStateData,
+    pub replayed: BlocksStateData,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ReplayReport {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub replayed: u64,
+    pub mismatches: Vec<ReplayMismatch>,
+    pub ok: bool,
+}
+
+/// The profit leaves before `block_num`: the current leaves with the changes of every later
+/// archived block reverted.
+fn profit_leaves_before(
+    profit_state: &State<'static, Keccak256Hasher, ProfitStateData>,
+    profit_changes_db: &ProfitChangesDB,
+    block_num: u64,
+    last_block_num: u64,
+) -> Result<Vec<(H256, ProfitStateData)>> {
+    let diffs = profit_changes_db
+        .get_state_diff(block_num - 1, last_block_num)?
+        .ok_or(anyhow!(
+            "changes of Block #{:} - #{:} are not tracked, replay from an earlier block",
+            block_num,
+            last_block_num
+        ))?;
+    let mut leaves: HashMap<H256, ProfitStateData> =
+        profit_state.try_get_leaves()?.into_iter().collect();
+    for diff in diffs {
+        let mut old = ProfitStateData {
+            token: diff.token,
+            token_chain_id: diff.chain_id,
+            balance: diff.old_balance,
+            debt: diff.old_debt,
+        };
+        old.try_clear().map_err(|e| anyhow!(e))?;
+        let path = chain_token_address_convert_to_h256(diff.chain_id, diff.token, diff.address);
+        leaves.insert(path, old);
+    }
+    Ok(leaves
+        .into_iter()
+        .filter(|(_, v)| v != &ProfitStateData::default())
+        .collect())
+}
+
+/// Replay `blocks` into `out`. Unless `saved_profit` is set, the profit of each tx is
+/// recalculated from the saved fee ratios.
+pub fn replay(
+    profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    storage: Arc<Storage>,
+    txs_db: Arc<TxsRocksDB>,
+    out: &str,
+    blocks: RangeInclusive<u64>,
+    saved_profit: bool,
+) -> Result<ReplayReport> {
+    let (from_block, to_block) = (*blocks.start(), *blocks.end());
+    if from_block == 0 || from_block > to_block {
+        return Err(anyhow!(
+            "invalid block range #{} - #{}",
+            from_block,
+            to_block
+        ));
+    }
+    if let Some(pruned_through) = PrunedTxsDB::new(storage.clone())?.get_pruned_through()? {
+        if from_block <= pruned_through {
+            return Err(anyhow!(
+                "txs of Block #{} and earlier are pruned, replay from a later block",
+                pruned_through
+            ));
+        }
+    }
+    if std::fs::read_dir(out).map_or(false, |mut d| d.next().is_some()) {
+        return Err(anyhow!("{} is not empty, replay into a new directory", out));
+    }
+
+    let archived = |block_num: u64| -> Result<BlocksStateData> {
+        Ok(blocks_state
+            .read()
+            .unwrap()
+            .try_get(block_number_convert_to_h256(block_num))?)
+    };
+    let last_block_num = blocks_state
+        .read()
+        .unwrap()
+        .try_get_leaves()?
+        .into_iter()
+        .map(|(_, b)| b.block_num)
+        .max()
+        .unwrap_or_default();
+    if to_block > last_block_num {
+        return Err(anyhow!(
+            "Block #{} is not archived, the last archived block is #{}",
+            to_block,
+            last_block_num
+        ));
+    }
+
+    let replay_storage = Arc::new(Storage::open(out)?);
+    check_and_migrate(&replay_storage, out)?;
+    let replay_profit_state = Arc::new(RwLock::new(State::new(
+        PROFIT_STATE_PREFIX,
+        replay_storage.clone(),
+    )));
+    let replay_blocks_state = Arc::new(RwLock::new(State::new(
+        BLOCKS_STATE_PREFIX,
+        replay_storage.clone(),
+    )));
+
+    // Start from the state before `from_block`, unless it is the first archived block.
+    let last_block = archived(from_block - 1)?;
+    if last_block != BlocksStateData::default() {
+        let leaves = profit_leaves_before(
+            &profit_state.read().unwrap(),
+            &ProfitChangesDB::new(storage.clone())?,
+            from_block,
+            last_block_num,
+        )?;
+        let mut state = replay_profit_state.write().unwrap();
+        for chunk in leaves.chunks(SEED_BATCH_SIZE) {
+            state.try_update_all(chunk.to_vec())?;
+        }
+        if state.try_get_root()? != H256::from(last_block.profit_root) {
+            return Err(anyhow!(
+                "can not rebuild the profit state before Block #{}",
+                from_block
+            ));
+        }
+        replay_blocks_state.write().unwrap().try_update_all(vec![(
+            block_number_convert_to_h256(from_block - 1),
+            last_block,
+        )])?;
+    }
+
+    let mut archiver = Archiver::new(
+        replay_storage,
+        txs_db,
+        replay_profit_state,
+        replay_blocks_state,
+    )?;
+    if !saved_profit {
+        archiver = archiver.with_recalculated_profit(MakerProfitDB::new(storage.clone())?);
+    }
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+
+    let mut report = ReplayReport {
+        from_block,
+        to_block,
+        ..Default::default()
+    };
+    let mut last_block_info = block_info_db
+        .get_block_info(from_block - 1)?
+        .ok_or(anyhow!("Block #{} info is not saved", from_block - 1))?;
+    for block_num in from_block..=to_block {
+        let block_info = block_info_db
+            .get_block_info(block_num)?
+            .ok_or(anyhow!("Block #{} info is not saved", block_num))?;
+        let replayed = archiver.archive_block(block_info.clone(), &last_block_info)?;
+        let archived = archived(block_num)?;
+        if replayed != archived {
+            event!(
+                Level::WARN,
+                "Block #{:?}. - replayed block differs. archived: {:?}, replayed: {:?}",
+                block_num,
+                archived,
+                replayed,
+            );
+            report.mismatches.push(ReplayMismatch {
+                block_number: block_num,
+                archived,
+                replayed,
+            });
+        }
+        report.replayed += 1;
+        last_block_info = block_info;
+    }
+    report.ok = report.mismatches.is_empty();
+    Ok(report)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use txs::fixtures::{archived_db, test_tx};
+
+    #[test]
+    fn test_replay() {
+        let (profit_state, blocks_state, storage, txs_db) = archived_db("./db_replay");
+        let replay_into = |out: &str| {
+            let _ = std::fs::remove_dir_all(out);
+            replay(
+                profit_state.clone(),
+                blocks_state.clone(),
+                storage.clone(),
+                txs_db.clone(),
+                out,
+                2..=3,
+                true,
+            )
+            .unwrap()
+        };
+
+        // Replayed from the state before block 2, as archived.
+        let report = replay_into("./db_replay_out");
+        assert!(report.ok, "{:?}", report);
+        assert_eq!(report.replayed, 2);
+
+        // The tx archived with block 3 was saved with another profit since.
+        txs_db.insert_txs(vec![test_tx(21, 8)]).unwrap();
+        let report = replay_into("./db_replay_out_changed");
+        assert!(!report.ok);
+        assert_eq!(report.replayed, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.block_number, 3);
+        assert_eq!(mismatch.archived.txs, mismatch.replayed.txs);
+        assert_ne!(mismatch.archived.profit_root, mismatch.replayed.profit_root);
+    }
+}
@@ -0,0 +1,366 @@
+//! A REST gateway over the submitter rpc, served on the rpc port with `RPC_REST_ENABLED`.
+//!
+//! `GET /v1/...` requests are answered by `SubmitterApiServerImpl`, everything else is passed on
+//! to the json-rpc server. Addresses, hashes and roots are `0x` hex, balances and other amounts
+//! decimal strings, chain ids and block numbers numbers. Errors are `{"error": message}`, with 404
+//! for a block that is not archived or an account without profit in the tokens, and 400 for bad
+//! parameters.
+//!
+//! - `GET /v1/root`
+//! - `GET /v1/blocks/{block_num}`
+//! - `GET /v1/accounts/{address}/profits[?tokens=chain_id:token,...][&block={block_num}]`
+//! - `GET /v1/accounts/{address}/proof?tokens=chain_id:token,...[&compiled=true]`
+
+use super::{cli::parse_chain_token, rpc::SubmitterApiServerImpl};
+use ethers::{
+    types::{Address, U256},
+    utils::hex,
+};
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use jsonrpsee::types::{error::INVALID_PARAMS_CODE, ErrorObjectOwned};
+use primitives::{
+    constants::{ACCOUNT_NOT_EXISTS_CODE, BLOCK_NOT_ARCHIVED_CODE, STATE_ERROR_CODE},
+    traits::SubmitterApiServer,
+    types::{BlocksStateData, ProfitBalance},
+};
+use serde_json::{json, Value};
+use state::Keccak256Hasher;
+use std::{
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{BoxError, Layer, Service};
+use url::form_urlencoded;
+
+pub const REST_PREFIX: &str = "/v1/";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Root,
+    Block(u64),
+    Profits(Address),
+    Proof(Address),
+}
+
+fn route(path: &str) -> Option<Route> {
+    let segments: Vec<&str> = path
+        .strip_prefix(REST_PREFIX)?
+        .trim_end_matches('/')
+        .split('/')
+        .collect();
+    match segments.as_slice() {
+        ["root"] => Some(Route::Root),
+        ["blocks", n] => n.parse().ok().map(Route::Block),
+        ["accounts", a, "profits"] => Address::from_str(a).ok().map(Route::Profits),
+        ["accounts", a, "proof"] => Address::from_str(a).ok().map(Route::Proof),
+        _ => None,
+    }
+}
+
+/// The decoded value of `key` in the query string.
+fn query_param(query: Option<&str>, key: &str) -> Option<String> {
+    form_urlencoded::parse(query?.as_bytes())
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.into_owned())
+}
+
+fn parse_tokens(tokens: &str) -> anyhow::Result<Vec<(u64, Address)>> {
+    let mut parsed: Vec<(u64, Address)> = vec![];
+    for t in tokens.split(',').filter(|t| !t.is_empty()) {
+        let t = parse_chain_token(t)?;
+        if !parsed.contains(&t) {
+            parsed.push(t);
+        }
+    }
+    Ok(parsed)
+}
+
+fn to_hex(bytes: impl AsRef<[u8]>) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn to_dec(amount: U256) -> String {
+    amount.to_string()
+}
+
+fn block_json(block: &BlocksStateData) -> Value {
+    json!({
+        "block_num": block.block_num,
+        "root": to_hex(block.root),
+        "txs": to_hex(block.txs),
+        "profit_root": to_hex(block.profit_root),
+    })
+}
+
+fn balance_json(balance: &ProfitBalance) -> Value {
+    json!({
+        "chain_id": balance.chain_id,
+        "token": to_hex(balance.token),
+        "balance": to_dec(balance.balance),
+        "debt": to_dec(balance.debt),
+    })
+}
+
+enum RestError {
+    BadRequest(String),
+    NotFound(String),
+    Api(ErrorObjectOwned),
+}
+
+impl From<ErrorObjectOwned> for RestError {
+    fn from(e: ErrorObjectOwned) -> Self {
+        RestError::Api(e)
+    }
+}
+
+impl From<anyhow::Error> for RestError {
+    fn from(e: anyhow::Error) -> Self {
+        RestError::BadRequest(e.to_string())
+    }
+}
+
+/// The status of an error of the submitter api.
+fn api_status(e: &ErrorObjectOwned) -> StatusCode {
+    match e.code() {
+        BLOCK_NOT_ARCHIVED_CODE | ACCOUNT_NOT_EXISTS_CODE => StatusCode::NOT_FOUND,
+        INVALID_PARAMS_CODE => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn no_profit(address: Address) -> RestError {
+    RestError::Api(ErrorObjectOwned::owned(
+        ACCOUNT_NOT_EXISTS_CODE,
+        format!("{:?} has no profit in the tokens", address),
+        None::<bool>,
+    ))
+}
+
+async fn handle(
+    api: &SubmitterApiServerImpl<'static>,
+    route: Route,
+    query: Option<&str>,
+) -> Result<Value, RestError> {
+    match route {
+        Route::Root => Ok(json!({ "root": format!("0x{}", api.get_root().await?) })),
+        Route::Block(block_num) => {
+            let block = api.get_profit_root_by_block_num(block_num).await?;
+            if block == BlocksStateData::default() {
+                return Err(RestError::NotFound(format!(
+                    "Block #{:} is not archived",
+                    block_num
+                )));
+            }
+            Ok(block_json(&block))
+        }
+        Route::Profits(address) => {
+            let tokens = match query_param(query, "tokens") {
+                Some(t) => parse_tokens(&t)?,
+                None => api.user_tokens_db.get_tokens(address).map_err(|e| {
+                    RestError::Api(ErrorObjectOwned::owned(
+                        STATE_ERROR_CODE,
+                        format!("error: get tokens err: {:?}", e),
+                        None::<bool>,
+                    ))
+                })?,
+            };
+            if let Some(block_num) = query_param(query, "block") {
+                let block_num: u64 = block_num
+                    .parse()
+                    .map_err(|_| RestError::BadRequest(format!("bad block {}", block_num)))?;
+                let profit = api.get_profit_info_at(address, tokens, block_num).await?;
+                if profit.balances.is_empty() {
+                    return Err(no_profit(address));
+                }
+                return Ok(json!({
+                    "block_num": profit.block_num,
+                    "profit_root": to_hex(profit.profit_root),
+                    "balances": profit.balances.iter().map(balance_json).collect::<Vec<_>>(),
+                }));
+            }
+            let profits = api.get_profit_info(address, tokens).await?;
+            if profits.is_empty() {
+                return Err(no_profit(address));
+            }
+            Ok(Value::Array(
+                profits
+                    .into_iter()
+                    .map(|p| {
+                        json!({
+                            "chain_id": p.token_chain_id,
+                            "token": to_hex(p.token),
+                            "balance": to_dec(p.balance),
+                            "debt": to_dec(p.debt),
+                            "total_profit": to_dec(p.total_profit),
+                            "total_withdrawn": to_dec(p.total_withdrawn),
+                        })
+                    })
+                    .collect(),
+            ))
+        }
+        Route::Proof(address) => {
+            let tokens = parse_tokens(&query_param(query, "tokens").unwrap_or_default())?;
+            if tokens.is_empty() {
+                return Err(RestError::BadRequest("tokens are required".to_string()));
+            }
+            let compiled = query_param(query, "compiled").map_or(false, |c| c == "true");
+            let keys = tokens.into_iter().map(|(c, t)| (c, t, address)).collect();
+            let proofs = api.get_profit_proofs(keys, Some(compiled)).await?;
+            // The siblings are hashed the way the FeeManager contract takes them.
+            let leaves: Vec<Value> = match proofs.multiproof {
+                Some(_) => proofs.leaves.iter().map(balance_json).collect(),
+                None => proofs
+                    .proofs
+                    .iter()
+                    .map(|p| {
+                        json!({
+                            "chain_id": p.token.token_chain_id,
+                            "token": to_hex(p.token.token),
+                            "balance": to_dec(p.token.balance),
+                            "debt": to_dec(p.token.debt),
+                            "path": to_hex(p.path),
+                            "leave_bitmap": to_hex(p.leave_bitmap),
+                            "siblings": p
+                                .siblings
+                                .iter()
+                                .map(|s| to_hex(s.hash::<Keccak256Hasher>().as_slice()))
+                                .collect::<Vec<_>>(),
+                            "start_index": p.no1_merge_value.0,
+                            "first_zero_bits": to_hex(p.no1_merge_value.1.as_slice()),
+                        })
+                    })
+                    .collect(),
+            };
+            Ok(json!({
+                "root": to_hex(proofs.root),
+                "address": to_hex(address),
+                "leaves": leaves,
+                "multiproof": proofs.multiproof.map(to_hex),
+            }))
+        }
+    }
+}
+
+fn response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Answers `GET /v1/...` with `api`, and passes the other requests on.
+#[derive(Clone)]
+pub struct RestLayer {
+    pub api: Arc<SubmitterApiServerImpl<'static>>,
+}
+
+impl<S> Layer<S> for RestLayer {
+    type Service = Rest<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Rest {
+            inner,
+            api: self.api.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rest<S> {
+    inner: S,
+    api: Arc<SubmitterApiServerImpl<'static>>,
+}
+
+impl<S> Service<Request<Body>> for Rest<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if !req.uri().path().starts_with(REST_PREFIX) {
+            let fut = self.inner.call(req);
+            return Box::pin(async move { fut.await.map_err(Into::into) });
+        }
+        let api = self.api.clone();
+        Box::pin(async move {
+            if req.method() != Method::GET {
+                return Ok(response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    json!({ "error": "only GET is supported" }),
+                ));
+            }
+            let route = match route(req.uri().path()) {
+                Some(r) => r,
+                None => {
+                    return Ok(response(
+                        StatusCode::NOT_FOUND,
+                        json!({ "error": format!("no route for {}", req.uri().path()) }),
+                    ))
+                }
+            };
+            Ok(match handle(&api, route, req.uri().query()).await {
+                Ok(body) => response(StatusCode::OK, body),
+                Err(RestError::BadRequest(e)) => {
+                    response(StatusCode::BAD_REQUEST, json!({ "error": e }))
+                }
+                Err(RestError::NotFound(e)) => {
+                    response(StatusCode::NOT_FOUND, json!({ "error": e }))
+                }
+                Err(RestError::Api(e)) => response(api_status(&e), json!({ "error": e.message() })),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let address = "0x0000000000000000000000000000000000000022";
+        assert_eq!(route("/v1/root"), Some(Route::Root));
+        assert_eq!(route("/v1/blocks/12/"), Some(Route::Block(12)));
+        assert_eq!(
+            route(&format!("/v1/accounts/{}/proof", address)),
+            Some(Route::Proof(Address::from_low_u64_be(0x22)))
+        );
+        assert_eq!(route("/v1/accounts/0x22/profits"), None);
+        assert_eq!(route("/v1/blocks/latest"), None);
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Some("compiled=true&tokens=1%3A0x0000000000000000000000000000000000000021,1:0x0000000000000000000000000000000000000021");
+        assert_eq!(
+            parse_tokens(&query_param(query, "tokens").unwrap()).unwrap(),
+            vec![(1, Address::from_low_u64_be(0x21))]
+        );
+        assert_eq!(query_param(query, "compiled").as_deref(), Some("true"));
+        assert_eq!(query_param(query, "block"), None);
+        assert!(parse_tokens("0x21").is_err());
+    }
+
+    #[test]
+    fn test_api_status() {
+        let status = |code: i32| api_status(&ErrorObjectOwned::owned(code, "", None::<bool>));
+        assert_eq!(status(BLOCK_NOT_ARCHIVED_CODE), StatusCode::NOT_FOUND);
+        assert_eq!(status(ACCOUNT_NOT_EXISTS_CODE), StatusCode::NOT_FOUND);
+        assert_eq!(status(INVALID_PARAMS_CODE), StatusCode::BAD_REQUEST);
+        assert_eq!(status(STATE_ERROR_CODE), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
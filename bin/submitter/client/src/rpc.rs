@@ -2,28 +2,41 @@
 
 use async_trait::async_trait;
 use ethers::{
+    providers::{Http, Provider},
     types::{Address, U256},
     utils::hex,
 };
 use jsonrpsee::{
-    core::RpcResult,
-    types::{ErrorObject, ErrorObjectOwned},
+    core::{RpcResult, SubscriptionResult},
+    types::{error::INVALID_PARAMS_CODE, ErrorObject, ErrorObjectOwned},
+    PendingSubscriptionSink, RpcModule, SubscriptionMessage,
 };
 use primitives::{
     constants::*,
     error::Error as StateError,
     func::*,
-    traits::{DebugApiServer, StataTrait, SubmitterApiServer},
+    retry::{retry_metrics, EndpointMetrics},
+    storage::Storage,
+    traits::{DebugApiServer, StataTrait, SubmitterApiServer, SubmitterPubSubApiServer},
     types::*,
 };
+use serde::Serialize;
 use state::{Keccak256Hasher, SmtValue, State, H256};
 use std::{
+    collections::HashSet,
     str::FromStr,
     sync::{Arc, RwLock},
 };
+use tokio::sync::broadcast::{error::RecvError, Receiver, Sender};
 use txs::{
-    rocks_db::TxsRocksDB,
-    sled_db::{ProfitStatisticsDB, UserTokensDB},
+    audit::get_tx_audit,
+    checkpoint::{create_checkpoint, list_checkpoints},
+    get_profit_at, get_state_diff, list_balances,
+    rocks_db::{TxIndex, TxsRocksDB},
+    sled_db::{MissedTxsDB, ProfitChangesDB, ProfitStatisticsDB, SupportSetDB, UserTokensDB},
+    stats::TxStatsDB,
+    withdraw::build_withdraw_call,
+    BlockNotArchived,
 };
 use utils::get_no1_merge_value;
 
@@ -35,7 +48,21 @@ impl From<JsonRpcError> for ErrorObjectOwned {
     }
 }
 
+/// `BLOCK_NOT_ARCHIVED_CODE` if the block asked for is not archived, else `STATE_ERROR_CODE`.
+fn state_error_code(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<BlockNotArchived>() {
+        Some(_) => BLOCK_NOT_ARCHIVED_CODE,
+        None => STATE_ERROR_CODE,
+    }
+}
+
 pub const RWLOCK_WRITE_ERROR_CODE: i32 = 888;
+pub const MAX_LIST_BALANCES_LIMIT: u64 = 1000;
+pub const DEFAULT_TXS_PAGE_LIMIT: u64 = 100;
+pub const MAX_TXS_PAGE_LIMIT: u64 = 1000;
+pub const MAX_PROOF_KEYS: usize = 1000;
+pub const DEFAULT_TOP_DEALERS_LIMIT: u64 = 10;
+pub const MAX_TOP_DEALERS_LIMIT: u64 = 1000;
 // pub const PARAMETER_ERROR_CODE: i32 = 889;
 
 impl From<StateError> for JsonRpcError {
@@ -61,17 +88,31 @@ impl From<StateError> for JsonRpcError {
     }
 }
 
+#[derive(Clone)]
 pub struct SubmitterApiServerImpl<'a> {
     pub state: Arc<RwLock<State<'a, Keccak256Hasher, ProfitStateData>>>,
     pub blocks_state: Arc<RwLock<State<'a, Keccak256Hasher, BlocksStateData>>>,
     pub user_tokens_db: Arc<UserTokensDB>,
     pub profit_statistics_db: Arc<ProfitStatisticsDB>,
     pub txs_db: Arc<TxsRocksDB>,
+    pub missed_txs_db: Arc<MissedTxsDB>,
+    pub support_set_db: Arc<SupportSetDB>,
+    pub profit_changes_db: Arc<ProfitChangesDB>,
+    pub storage: Arc<Storage>,
+    pub provider: Provider<Http>,
+    pub tx_stats_db: Arc<TxStatsDB>,
+}
+
+pub struct SubmitterPubSubApiServerImpl {
+    pub events: Sender<SubmitterEvent>,
 }
 
 pub struct DebugApiServerImpl<'a> {
     pub state: Arc<RwLock<State<'a, Keccak256Hasher, ProfitStateData>>>,
+    pub blocks_state: Arc<RwLock<State<'a, Keccak256Hasher, BlocksStateData>>>,
     pub user_tokens_db: Arc<UserTokensDB>,
+    pub storage: Arc<Storage>,
+    pub db_path: String,
 }
 
 #[async_trait]
@@ -136,6 +177,144 @@ impl DebugApiServer for DebugApiServerImpl<'static> {
             .map_err(|e| Into::<JsonRpcError>::into(e))?;
         Ok(root)
     }
+
+    async fn create_checkpoint(&self) -> RpcResult<CheckpointInfo> {
+        create_checkpoint(
+            &self.storage,
+            &self.state,
+            &self.blocks_state,
+            &self.db_path,
+        )
+        .map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: create checkpoint err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
+    async fn list_checkpoints(&self) -> RpcResult<Vec<CheckpointInfo>> {
+        list_checkpoints(&self.db_path).map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: list checkpoints err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+}
+
+impl SubmitterApiServerImpl<'static> {
+    /// Proofs of the leaves at `keys`, duplicates dropped, read from one snapshot.
+    fn get_profit_proofs_of(
+        &self,
+        keys: Vec<(u64, Address, Address)>,
+        compiled: bool,
+    ) -> RpcResult<ProfitProofs> {
+        if keys.len() > MAX_PROOF_KEYS {
+            return Err(ErrorObject::owned(
+                INVALID_PARAMS_CODE,
+                format!("error: at most {} keys in one request.", MAX_PROOF_KEYS),
+                None::<bool>,
+            ));
+        }
+        let mut seen = HashSet::new();
+        let unique: Vec<(u64, Address, Address)> =
+            keys.into_iter().filter(|k| seen.insert(*k)).collect();
+        let paths: Vec<H256> = unique
+            .iter()
+            .map(|(c, t, a)| chain_token_address_convert_to_h256(*c, *t, *a))
+            .collect();
+        let state = self.state.read().map_err(|_| {
+            ErrorObject::owned(
+                RWLOCK_READ_ERROR_CODE,
+                format!("error: state read error."),
+                None::<bool>,
+            )
+        })?;
+        let state_proofs = state
+            .try_get_proofs(paths.clone(), compiled)
+            .map_err(|e| Into::<JsonRpcError>::into(e))?;
+        drop(state);
+
+        let root: [u8; 32] = state_proofs.root.into();
+        let mut proofs = ProfitProofs {
+            root,
+            multiproof: state_proofs.compiled,
+            ..Default::default()
+        };
+        if compiled {
+            proofs.leaves = unique
+                .iter()
+                .zip(paths)
+                .zip(state_proofs.leaves)
+                .map(|(((chain_id, token, address), key), leaf)| ProfitBalance {
+                    key,
+                    chain_id: *chain_id,
+                    token: *token,
+                    address: Some(*address),
+                    balance: leaf.balance,
+                    debt: leaf.debt,
+                })
+                .collect();
+            return Ok(proofs);
+        }
+        for ((path, leaf), (leave_bitmap, siblings)) in paths
+            .into_iter()
+            .zip(state_proofs.leaves)
+            .zip(state_proofs.paths)
+        {
+            let no1_merge_value =
+                get_no1_merge_value(path, SmtValue::new(leaf.clone()).unwrap(), leave_bitmap);
+            proofs.proofs.push(ProfitProof {
+                path: path.into(),
+                leave_bitmap: leave_bitmap.into(),
+                token: leaf,
+                siblings,
+                root,
+                no1_merge_value,
+            });
+        }
+        Ok(proofs)
+    }
+
+    fn get_txs_by_index(
+        &self,
+        index: TxIndex,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage> {
+        self.txs_db
+            .get_txs_by_index(
+                &index,
+                from,
+                to,
+                cursor,
+                limit
+                    .unwrap_or(DEFAULT_TXS_PAGE_LIMIT)
+                    .clamp(1, MAX_TXS_PAGE_LIMIT) as usize,
+            )
+            .map_err(|e| {
+                ErrorObject::owned(
+                    STATE_ERROR_CODE,
+                    format!("error: get txs by {:?} err: {:?}", index, e),
+                    None::<bool>,
+                )
+            })
+    }
+}
+
+/// `rpc.discover`, answered with the OpenRPC `document` of the served namespace.
+pub fn discover_module(document: &'static str) -> anyhow::Result<RpcModule<()>> {
+    let document: serde_json::Value = serde_json::from_str(document)?;
+    let mut module = RpcModule::new(());
+    module.register_method("rpc.discover", move |_, _| {
+        Ok::<_, ErrorObjectOwned>(document.clone())
+    })?;
+    Ok(module)
 }
 
 fn u64_to_ethereum_address(input: u64) -> Address {
@@ -147,6 +326,44 @@ fn u64_to_ethereum_address(input: u64) -> Address {
     Address::from_str(&address_str).expect("Failed to parse Ethereum address")
 }
 
+/// Accept the subscription and push the items `f` maps each event to, until the subscriber or the
+/// submitter is gone. A subscriber that falls behind is closed with an error, so it knows to
+/// resync with the queries and subscribe again.
+async fn pipe_events<T, F>(
+    pending: PendingSubscriptionSink,
+    mut events: Receiver<SubmitterEvent>,
+    f: F,
+) -> SubscriptionResult
+where
+    T: Serialize,
+    F: Fn(SubmitterEvent) -> Vec<T>,
+{
+    let sink = pending.accept().await?;
+    loop {
+        tokio::select! {
+            _ = sink.closed() => break,
+            event = events.recv() => match event {
+                Ok(event) => {
+                    for item in f(event) {
+                        if sink.send(SubscriptionMessage::from_json(&item)?).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    return Err(format!(
+                        "subscriber lagged behind, {} events missed. resync and subscribe again",
+                        n
+                    )
+                    .into());
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl SubmitterApiServer for SubmitterApiServerImpl<'static> {
     async fn get_profit_info(
@@ -201,12 +418,45 @@ impl SubmitterApiServer for SubmitterApiServerImpl<'static> {
         self.get_profit_info(user, tokens).await
     }
 
+    async fn get_profit_info_at(
+        &self,
+        user: Address,
+        tokens: Vec<(u64, Address)>,
+        block_num: u64,
+    ) -> RpcResult<ProfitAt> {
+        get_profit_at(
+            &self.state,
+            &self.blocks_state,
+            &self.profit_changes_db,
+            block_num,
+            user,
+            tokens,
+        )
+        .map_err(|e| {
+            ErrorObject::owned(
+                state_error_code(&e),
+                format!("error: get profit info at block err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
     async fn get_profit_by_tx_hash(&self, tx_hash: H256) -> RpcResult<Option<CrossTxProfit>> {
         self.txs_db.get_profit_by_tx_hash(tx_hash).map_err(|_| {
             ErrorObject::owned(1111, format!("error: get tx's profit err."), None::<bool>)
         })
     }
 
+    async fn get_tx_audit(&self, tx_hash: H256) -> RpcResult<TxAudit> {
+        get_tx_audit(&self.storage, &self.txs_db, &self.blocks_state, tx_hash).map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: get tx audit err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
     async fn get_root(&self) -> RpcResult<String> {
         let state = self.state.read().map_err(|_| {
             ErrorObject::owned(
@@ -226,43 +476,42 @@ impl SubmitterApiServer for SubmitterApiServerImpl<'static> {
         user: Address,
         tokens: Vec<(u64, Address)>,
     ) -> RpcResult<Vec<ProfitProof>> {
-        let state = self.state.read().map_err(|_| {
+        let keys = tokens.into_iter().map(|(c, t)| (c, t, user)).collect();
+        Ok(self.get_profit_proofs_of(keys, false)?.proofs)
+    }
+
+    async fn get_profit_proofs(
+        &self,
+        keys: Vec<(u64, Address, Address)>,
+        compiled: Option<bool>,
+    ) -> RpcResult<ProfitProofs> {
+        self.get_profit_proofs_of(keys, compiled.unwrap_or(false))
+    }
+
+    async fn get_withdraw_call(
+        &self,
+        address: Address,
+        tokens: Vec<(u64, Address)>,
+        amounts: Option<Vec<U256>>,
+    ) -> RpcResult<WithdrawCall> {
+        build_withdraw_call(
+            &self.provider,
+            &self.storage,
+            &self.state,
+            &self.blocks_state,
+            &self.profit_changes_db,
+            address,
+            tokens,
+            amounts,
+        )
+        .await
+        .map_err(|e| {
             ErrorObject::owned(
-                RWLOCK_READ_ERROR_CODE,
-                format!("error: state read error."),
+                state_error_code(&e),
+                format!("error: get withdraw call err: {:?}", e),
                 None::<bool>,
             )
-        })?;
-        let mut v: Vec<ProfitProof> = vec![];
-        if tokens.len() == 0 {
-            return Ok(v);
-        }
-
-        for i in tokens.clone() {
-            let root = state
-                .try_get_root()
-                .map_err(|e| Into::<JsonRpcError>::into(e))?;
-            let bitmap_and_sils = state
-                .try_get_merkle_proof_1(chain_token_address_convert_to_h256(i.0, i.1, user))
-                .map_err(|e| Into::<JsonRpcError>::into(e))?;
-            let old_token = state
-                .try_get(chain_token_address_convert_to_h256(i.0, i.1, user))
-                .map_err(|e| Into::<JsonRpcError>::into(e))?;
-            let no1_merge_value = get_no1_merge_value(
-                chain_token_address_convert_to_h256(i.0, i.1, user).into(),
-                SmtValue::new(old_token.clone()).unwrap(),
-                bitmap_and_sils.0.into(),
-            );
-            v.push(ProfitProof {
-                path: chain_token_address_convert_to_h256(i.0, i.1, user).into(),
-                leave_bitmap: bitmap_and_sils.0.into(),
-                token: old_token,
-                siblings: bitmap_and_sils.1,
-                root: root.into(),
-                no1_merge_value,
-            });
-        }
-        Ok(v)
+        })
     }
 
     async fn verify(
@@ -302,4 +551,229 @@ impl SubmitterApiServer for SubmitterApiServerImpl<'static> {
             .map_err(|e| Into::<JsonRpcError>::into(e))?;
         Ok(root)
     }
+    async fn get_missed_txs(&self) -> RpcResult<Vec<MissedTx>> {
+        self.missed_txs_db.get_missed_txs().map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: get missed txs err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+    async fn get_retry_metrics(&self) -> RpcResult<Vec<EndpointMetrics>> {
+        Ok(retry_metrics())
+    }
+
+    async fn get_support_set_history(&self) -> RpcResult<Vec<SupportSet>> {
+        self.support_set_db.get_history().map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: get support set history err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
+    async fn get_state_diff(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> RpcResult<Vec<ProfitStateDiff>> {
+        get_state_diff(
+            &self.profit_changes_db,
+            &self.blocks_state,
+            from_block,
+            to_block,
+        )
+        .map_err(|e| {
+            ErrorObject::owned(
+                state_error_code(&e),
+                format!("error: get state diff err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
+    async fn list_balances(
+        &self,
+        cursor: Option<H256>,
+        limit: u64,
+        filter: Option<BalanceFilter>,
+    ) -> RpcResult<BalancePage> {
+        list_balances(
+            &self.state,
+            &self.user_tokens_db,
+            cursor,
+            limit.clamp(1, MAX_LIST_BALANCES_LIMIT) as usize,
+            filter.unwrap_or_default(),
+        )
+        .map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: list balances err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
+    async fn get_txs_by_dealer(
+        &self,
+        dealer: Address,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage> {
+        self.get_txs_by_index(TxIndex::Dealer(dealer), from, to, cursor, limit)
+    }
+
+    async fn get_txs_by_maker(
+        &self,
+        maker: Address,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage> {
+        self.get_txs_by_index(TxIndex::Maker(maker), from, to, cursor, limit)
+    }
+
+    async fn get_txs_by_source_chain(
+        &self,
+        chain_id: u64,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage> {
+        self.get_txs_by_index(TxIndex::SourceChain(chain_id), from, to, cursor, limit)
+    }
+
+    async fn get_txs_by_target_chain(
+        &self,
+        chain_id: u64,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage> {
+        self.get_txs_by_index(TxIndex::TargetChain(chain_id), from, to, cursor, limit)
+    }
+
+    async fn get_txs_by_source_id(&self, source_id: String) -> RpcResult<Vec<TxWithProfit>> {
+        self.txs_db.get_txs_by_source_id(&source_id).map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: get txs by source id err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+
+    async fn get_dealer_stats(
+        &self,
+        dealer: Address,
+        from: u64,
+        to: u64,
+    ) -> RpcResult<Vec<AddressTxStats>> {
+        self.tx_stats_db
+            .get_dealer_stats(from, to, Some(dealer), None)
+            .map_err(|e| {
+                ErrorObject::owned(
+                    STATE_ERROR_CODE,
+                    format!("error: get dealer stats err: {:?}", e),
+                    None::<bool>,
+                )
+            })
+    }
+
+    async fn get_top_dealers(
+        &self,
+        from: u64,
+        to: u64,
+        token: Option<Address>,
+        limit: Option<u64>,
+    ) -> RpcResult<Vec<AddressTxStats>> {
+        let limit = limit
+            .unwrap_or(DEFAULT_TOP_DEALERS_LIMIT)
+            .clamp(1, MAX_TOP_DEALERS_LIMIT) as usize;
+        self.tx_stats_db
+            .get_top_dealers(from, to, token, limit)
+            .map_err(|e| {
+                ErrorObject::owned(
+                    STATE_ERROR_CODE,
+                    format!("error: get top dealers err: {:?}", e),
+                    None::<bool>,
+                )
+            })
+    }
+
+    async fn get_maker_fees(
+        &self,
+        from: u64,
+        to: u64,
+        maker: Option<Address>,
+    ) -> RpcResult<Vec<AddressTxStats>> {
+        self.tx_stats_db
+            .get_maker_stats(from, to, maker)
+            .map_err(|e| {
+                ErrorObject::owned(
+                    STATE_ERROR_CODE,
+                    format!("error: get maker fees err: {:?}", e),
+                    None::<bool>,
+                )
+            })
+    }
+
+    async fn get_chain_stats(&self, from: u64, to: u64) -> RpcResult<Vec<ChainTxStats>> {
+        self.tx_stats_db.get_chain_stats(from, to).map_err(|e| {
+            ErrorObject::owned(
+                STATE_ERROR_CODE,
+                format!("error: get chain stats err: {:?}", e),
+                None::<bool>,
+            )
+        })
+    }
+}
+
+#[async_trait]
+impl SubmitterPubSubApiServer for SubmitterPubSubApiServerImpl {
+    async fn subscribe_new_root(&self, pending: PendingSubscriptionSink) -> SubscriptionResult {
+        pipe_events(pending, self.events.subscribe(), |event| match event {
+            SubmitterEvent::NewRoot(root) => vec![root],
+            _ => vec![],
+        })
+        .await
+    }
+
+    async fn subscribe_archived_block(
+        &self,
+        pending: PendingSubscriptionSink,
+    ) -> SubscriptionResult {
+        pipe_events(pending, self.events.subscribe(), |event| match event {
+            SubmitterEvent::ArchivedBlock(block) => vec![block],
+            _ => vec![],
+        })
+        .await
+    }
+
+    async fn subscribe_balance(
+        &self,
+        pending: PendingSubscriptionSink,
+        address: Address,
+    ) -> SubscriptionResult {
+        pipe_events(pending, self.events.subscribe(), move |event| match event {
+            SubmitterEvent::ArchivedBlock(block) => block
+                .changes
+                .into_iter()
+                .filter(|diff| diff.address == address)
+                .map(|diff| BalanceChange {
+                    block_num: block.block.block_num,
+                    diff,
+                })
+                .collect(),
+            _ => vec![],
+        })
+        .await
+    }
 }
@@ -0,0 +1,804 @@
+//! The rpc server, configured from the `RPC_*` env vars.
+//!
+//! jsonrpsee serves HTTP and WebSocket with the size, connection and CORS limits. If TLS or a
+//! rate limit is set, it listens on localhost only, and a gate on the bind address terminates TLS,
+//! limits the calls of each IP and forwards everything, WebSocket upgrades included, to it. The
+//! server can neither terminate TLS nor tell who made a call, hence the hop. The gate reads the
+//! calls of a batch and the messages of a WebSocket to take one token per call, and signs what it
+//! forwards with a token made at startup, so that local processes can not go around it.
+//!
+//! With `RPC_REST_ENABLED`, `GET /v1/...` is answered by the REST gateway, see `rest`.
+//!
+//! The debug rpc is served on its own listener, over HTTP only. Every request must carry the
+//! bearer token from `DEBUG_RPC_TOKEN_FILE`, and the method and id of every call are logged.
+
+use super::rest::RestLayer;
+use anyhow::{anyhow, Result};
+use hyper::{
+    body::HttpBody,
+    client::HttpConnector,
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use jsonrpsee::{server::ServerBuilder, Methods};
+use primitives::env::{
+    get_debug_rpc_bind_address, get_debug_rpc_token_file, get_rpc_batch_requests,
+    get_rpc_bind_address, get_rpc_cors_origins, get_rpc_max_connections, get_rpc_max_request_bytes,
+    get_rpc_max_response_bytes, get_rpc_rate_limit_burst, get_rpc_rate_limit_per_second,
+    get_rpc_tls_cert_and_key,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    future::Future,
+    io::{BufReader, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+use tower::{BoxError, Layer, Service};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{event, Level};
+
+// Idle buckets are dropped once this many IPs are tracked.
+const MAX_RATE_LIMIT_BUCKETS: usize = 10000;
+// Set by the gate on what it forwards to the server on localhost.
+const GATE_TOKEN_HEADER: &str = "x-submitter-gate-token";
+// The gate warns about rejected connections at most once in this many seconds.
+const REJECTED_WARN_SECONDS: u64 = 60;
+// A rejected connection is closed after this many seconds even if the answer was not written.
+const REJECT_TIMEOUT_SECONDS: u64 = 5;
+// What the gate answers over `RPC_MAX_CONNECTIONS` before closing.
+const SERVICE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Start the rpc server on `RPC_BIND_ADDRESS:port`, and return the address it listens on. With
+/// `rest`, the REST gateway is served on it too.
+pub async fn start_rpc_server(
+    port: u16,
+    methods: Methods,
+    rest: Option<RestLayer>,
+) -> Result<SocketAddr> {
+    let bind = format!("{}:{}", get_rpc_bind_address(), port);
+    let tls = match get_rpc_tls_cert_and_key() {
+        Some((cert, key)) => Some(load_tls(&cert, &key)?),
+        None => None,
+    };
+    let rate_limit = get_rpc_rate_limit_per_second();
+    let max_connections = get_rpc_max_connections();
+
+    let cors = match get_rpc_cors_origins() {
+        origins if origins.is_empty() => None,
+        origins => {
+            let allow_origin = if origins.iter().any(|o| o == "*") {
+                AllowOrigin::any()
+            } else {
+                AllowOrigin::list(
+                    origins
+                        .iter()
+                        .map(|o| HeaderValue::from_str(o))
+                        .collect::<std::result::Result<Vec<_>, _>>()?,
+                )
+            };
+            Some(
+                CorsLayer::new()
+                    .allow_methods(if rest.is_some() {
+                        vec![Method::POST, Method::GET]
+                    } else {
+                        vec![Method::POST]
+                    })
+                    .allow_headers([CONTENT_TYPE])
+                    .allow_origin(allow_origin),
+            )
+        }
+    };
+    let gated = tls.is_some() || rate_limit > 0;
+    let gate_token = gated.then(|| hex::encode(ethers::core::rand::random::<[u8; 32]>()));
+    let server = ServerBuilder::new()
+        .max_request_body_size(get_rpc_max_request_bytes())
+        .max_response_body_size(get_rpc_max_response_bytes())
+        // Behind the gate each downstream connection takes one upstream connection, plus the idle
+        // ones kept by its pool.
+        .max_connections(if gated {
+            max_connections.saturating_mul(2)
+        } else {
+            max_connections
+        })
+        .batch_requests_supported(get_rpc_batch_requests())
+        .set_middleware(
+            tower::ServiceBuilder::new()
+                .option_layer(gate_token.clone().map(|token| AdminLayer {
+                    token: Arc::new(token),
+                    gate: true,
+                    max_request_bytes: get_rpc_max_request_bytes() as usize,
+                }))
+                .option_layer(cors)
+                .option_layer(rest),
+        )
+        .build(if gated {
+            "127.0.0.1:0".to_string()
+        } else {
+            bind.clone()
+        })
+        .await?;
+    let upstream = server.local_addr()?;
+    let server_handle = server.start(methods)?;
+    tokio::spawn(server_handle.stopped());
+    if !gated {
+        return Ok(upstream);
+    }
+
+    let listener = TcpListener::bind(&bind).await?;
+    let addr = listener.local_addr()?;
+    let gate = Arc::new(Gate {
+        upstream,
+        client: hyper::Client::builder()
+            .pool_max_idle_per_host(max_connections as usize)
+            .build_http(),
+        limiter: (rate_limit > 0)
+            .then(|| RateLimiter::new(rate_limit, get_rpc_rate_limit_burst().max(1))),
+        tls,
+        token: HeaderValue::from_str(&gate_token.unwrap_or_default())?,
+        max_request_bytes: get_rpc_max_request_bytes() as usize,
+    });
+    tokio::spawn(run_gate(
+        listener,
+        gate,
+        Arc::new(Semaphore::new(max_connections as usize)),
+    ));
+    event!(
+        Level::INFO,
+        "Rpc gate forwards {:?} to {:?}. - rate limit: {:?}/s",
+        addr,
+        upstream,
+        rate_limit
+    );
+    Ok(addr)
+}
+
+/// Read the debug rpc token from `DEBUG_RPC_TOKEN_FILE`, or from `{db_path}/debug.token`, which
+/// is created with a random token if it does not exist.
+pub fn load_debug_token(db_path: &str) -> Result<String> {
+    let path = match get_debug_rpc_token_file() {
+        Some(path) => return read_token(Path::new(&path)),
+        None => Path::new(db_path).join("debug.token"),
+    };
+    if !path.exists() {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // Only readable by the submitter's user.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&path)?
+            .write_all(hex::encode(ethers::core::rand::random::<[u8; 32]>()).as_bytes())?;
+        event!(Level::INFO, "Debug rpc token is created at {:?}.", path);
+    }
+    read_token(&path)
+}
+
+fn read_token(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)?.trim().to_string();
+    if token.is_empty() {
+        return Err(anyhow!("debug rpc token file {:?} is empty", path));
+    }
+    Ok(token)
+}
+
+/// Start the debug rpc server on `DEBUG_RPC_BIND_ADDRESS:port`, and return the address it
+/// listens on.
+pub async fn start_debug_rpc_server(
+    port: u16,
+    methods: Methods,
+    token: String,
+) -> Result<SocketAddr> {
+    let server = ServerBuilder::new()
+        .http_only()
+        .max_request_body_size(get_rpc_max_request_bytes())
+        .max_response_body_size(get_rpc_max_response_bytes())
+        .set_middleware(tower::ServiceBuilder::new().layer(AdminLayer {
+            token: Arc::new(token),
+            gate: false,
+            max_request_bytes: get_rpc_max_request_bytes() as usize,
+        }))
+        .build(format!("{}:{}", get_debug_rpc_bind_address(), port))
+        .await?;
+    let addr = server.local_addr()?;
+    let server_handle = server.start(methods)?;
+    tokio::spawn(server_handle.stopped());
+    Ok(addr)
+}
+
+// Compares every byte, so the time taken does not tell how much of the token is right.
+fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Rejects the requests without the bearer token, and logs the method and id of the calls of the
+/// others and the size of their results. With `gate`, the token is the one the gate sets in
+/// `GATE_TOKEN_HEADER`, and nothing is logged.
+#[derive(Clone)]
+struct AdminLayer {
+    token: Arc<String>,
+    gate: bool,
+    max_request_bytes: usize,
+}
+
+impl<S> Layer<S> for AdminLayer {
+    type Service = Admin<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Admin {
+            inner,
+            token: self.token.clone(),
+            gate: self.gate,
+            max_request_bytes: self.max_request_bytes,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Admin<S> {
+    inner: S,
+    token: Arc<String>,
+    gate: bool,
+    max_request_bytes: usize,
+}
+
+impl<S> Service<Request<Body>> for Admin<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // Use the service that is ready, and leave a clone for the next call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let gate = self.gate;
+        let max_request_bytes = self.max_request_bytes;
+        let token = match gate {
+            true => req.headers().get(GATE_TOKEN_HEADER),
+            false => req.headers().get(AUTHORIZATION),
+        };
+        let authorized = token
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| match gate {
+                true => Some(v),
+                false => v.strip_prefix("Bearer "),
+            })
+            .map_or(false, |t| token_eq(t.as_bytes(), self.token.as_bytes()));
+        Box::pin(async move {
+            if !authorized {
+                match gate {
+                    true => event!(Level::WARN, "Rpc request is rejected, not from the gate."),
+                    false => event!(Level::WARN, "Admin call is rejected, bad or missing token."),
+                }
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::empty())
+                    .unwrap());
+            }
+            if gate {
+                return inner.call(req).await.map_err(Into::into);
+            }
+            let (parts, body) = req.into_parts();
+            let call = match read_body(body, max_request_bytes).await? {
+                Some(call) => call,
+                None => {
+                    event!(Level::WARN, "Admin call is rejected, the body is too large.");
+                    return Ok(Response::builder()
+                        .status(StatusCode::PAYLOAD_TOO_LARGE)
+                        .body(Body::empty())
+                        .unwrap());
+                }
+            };
+            event!(Level::INFO, "Admin call: {}", describe_calls(&call));
+            let res = inner
+                .call(Request::from_parts(parts, Body::from(call)))
+                .await
+                .map_err(Into::into)?;
+            event!(
+                Level::INFO,
+                "Admin call result. status: {:?}, bytes: {:?}",
+                res.status(),
+                res.body().size_hint().exact()
+            );
+            Ok(res)
+        })
+    }
+}
+
+fn load_tls(cert_path: &str, key_path: &str) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("no PKCS#8 private key in {}", key_path))?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, PrivateKey(key))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A token bucket for each IP.
+struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn new(per_second: u64, burst: u64) -> Self {
+        Self {
+            per_second: per_second as f64,
+            burst: burst as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token of `ip` for each of `calls`, false if it has not as many left.
+    fn check(&self, ip: IpAddr, calls: u64) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS {
+            let (per_second, burst) = (self.per_second, self.burst);
+            buckets.retain(|_, (tokens, at)| {
+                *tokens + now.duration_since(*at).as_secs_f64() * per_second < burst
+            });
+        }
+        let (tokens, at) = buckets.entry(ip).or_insert((self.burst, now));
+        *tokens =
+            (*tokens + now.duration_since(*at).as_secs_f64() * self.per_second).min(self.burst);
+        *at = now;
+        if *tokens < calls as f64 {
+            return false;
+        }
+        *tokens -= calls as f64;
+        true
+    }
+
+    /// Wait until `ip` has a token for each of `calls`, false if it never will.
+    async fn wait(&self, ip: IpAddr, calls: u64) -> bool {
+        if calls as f64 > self.burst {
+            return false;
+        }
+        while !self.check(ip, calls) {
+            tokio::time::sleep(Duration::from_secs_f64(1.0 / self.per_second)).await;
+        }
+        true
+    }
+}
+
+/// The calls in a json-rpc message: the entries of a batch, else one.
+fn calls_in(message: &[u8]) -> u64 {
+    let is_batch = message
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| *b == b'[');
+    if !is_batch {
+        return 1;
+    }
+    serde_json::from_slice::<Vec<serde::de::IgnoredAny>>(message)
+        .map_or(1, |calls| calls.len().max(1) as u64)
+}
+
+/// The method and id of each call in a json-rpc message, for the audit log.
+fn describe_calls(message: &[u8]) -> String {
+    let calls = match serde_json::from_slice::<serde_json::Value>(message) {
+        Ok(serde_json::Value::Array(calls)) => calls,
+        Ok(call) => vec![call],
+        Err(_) => return format!("not json, {} bytes", message.len()),
+    };
+    calls
+        .iter()
+        .map(|c| {
+            format!(
+                "{} (id {})",
+                c["method"].as_str().unwrap_or("?"),
+                c.get("id").unwrap_or(&serde_json::Value::Null)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Read `body`, None if it is larger than `max_bytes`.
+async fn read_body(
+    mut body: Body,
+    max_bytes: usize,
+) -> std::result::Result<Option<Vec<u8>>, hyper::Error> {
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > max_bytes {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+/// Copy the WebSocket frames of a client from `from` to `to`, and wait for a token of `ip` for
+/// each call before the last frame of its message is copied. Fails on a message larger than
+/// `max_bytes` or with more calls than the burst, which closes the connection.
+async fn forward_ws_calls<R, W>(
+    limiter: Option<&RateLimiter>,
+    ip: IpAddr,
+    max_bytes: usize,
+    mut from: R,
+    mut to: W,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
+    // The unmasked payload of the message so far.
+    let mut message: Vec<u8> = vec![];
+    loop {
+        let mut frame = vec![0u8; 2];
+        from.read_exact(&mut frame).await?;
+        let fin = frame[0] & 0x80 != 0;
+        let opcode = frame[0] & 0x0f;
+        let masked = frame[1] & 0x80 != 0;
+        let len = match frame[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                from.read_exact(&mut len).await?;
+                frame.extend_from_slice(&len);
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                from.read_exact(&mut len).await?;
+                frame.extend_from_slice(&len);
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        let mut mask = [0u8; 4];
+        if masked {
+            from.read_exact(&mut mask).await?;
+            frame.extend_from_slice(&mask);
+        }
+        if len > max_bytes as u64 {
+            return Err(invalid("websocket frame is too large"));
+        }
+        let start = frame.len();
+        frame.resize(start + len as usize, 0);
+        from.read_exact(&mut frame[start..]).await?;
+
+        // Text, binary and continuation frames, the others are control frames.
+        if opcode < 0x8 {
+            if message.len() + len as usize > max_bytes {
+                return Err(invalid("websocket message is too large"));
+            }
+            message.extend(
+                frame[start..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| b ^ mask[i % 4]),
+            );
+            if fin {
+                if let Some(limiter) = limiter {
+                    if !limiter.wait(ip, calls_in(&message)).await {
+                        return Err(invalid("too many calls in one message"));
+                    }
+                }
+                message.clear();
+            }
+        }
+        to.write_all(&frame).await?;
+    }
+}
+
+async fn write_and_close<I>(mut io: I, data: &[u8]) -> std::io::Result<()>
+where
+    I: AsyncWrite + Unpin,
+{
+    io.write_all(data).await?;
+    io.shutdown().await
+}
+
+struct Gate {
+    upstream: SocketAddr,
+    client: hyper::Client<HttpConnector>,
+    limiter: Option<RateLimiter>,
+    tls: Option<TlsAcceptor>,
+    // Proves to the server on localhost that a request came through the gate.
+    token: HeaderValue,
+    max_request_bytes: usize,
+}
+
+impl Gate {
+    async fn serve<I>(self: Arc<Self>, io: I, ip: IpAddr)
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let gate = self.clone();
+        let service = service_fn(move |req| gate.clone().forward(req, ip));
+        if let Err(e) = Http::new()
+            .serve_connection(io, service)
+            .with_upgrades()
+            .await
+        {
+            event!(Level::DEBUG, "Rpc gate connection of {:?} err: {:?}", ip, e);
+        }
+    }
+
+    /// Answer 503 and close.
+    async fn reject(&self, stream: TcpStream) {
+        let _ = match &self.tls {
+            Some(acceptor) => match acceptor.accept(stream).await {
+                Ok(stream) => write_and_close(stream, SERVICE_UNAVAILABLE).await,
+                Err(e) => Err(e),
+            },
+            None => write_and_close(stream, SERVICE_UNAVAILABLE).await,
+        };
+    }
+
+    async fn forward(
+        self: Arc<Self>,
+        req: Request<Body>,
+        ip: IpAddr,
+    ) -> std::result::Result<Response<Body>, hyper::Error> {
+        let (parts, body) = req.into_parts();
+        let body = match read_body(body, self.max_request_bytes).await? {
+            Some(body) => body,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::empty())
+                    .unwrap())
+            }
+        };
+        if let Some(limiter) = &self.limiter {
+            if !limiter.check(ip, calls_in(&body)) {
+                return Ok(Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .body(Body::from("too many requests"))
+                    .unwrap());
+            }
+        }
+        let mut req = Request::from_parts(parts, Body::from(body));
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/")
+            .to_string();
+        *req.uri_mut() = format!("http://{}{}", self.upstream, path).parse().unwrap();
+        req.headers_mut()
+            .insert(GATE_TOKEN_HEADER, self.token.clone());
+        let downstream = hyper::upgrade::on(&mut req);
+        let mut res = match self.client.request(req).await {
+            Ok(res) => res,
+            Err(e) => {
+                event!(Level::WARN, "Rpc gate forward err: {:?}", e);
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::empty())
+                    .unwrap());
+            }
+        };
+        if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+            let upstream = hyper::upgrade::on(&mut res);
+            let gate = self.clone();
+            tokio::spawn(async move {
+                if let (Ok(downstream), Ok(upstream)) = (downstream.await, upstream.await) {
+                    let (down_read, mut down_write) = tokio::io::split(downstream);
+                    let (mut up_read, up_write) = tokio::io::split(upstream);
+                    tokio::select! {
+                        _ = tokio::io::copy(&mut up_read, &mut down_write) => {}
+                        res = forward_ws_calls(
+                            gate.limiter.as_ref(),
+                            ip,
+                            gate.max_request_bytes,
+                            down_read,
+                            up_write,
+                        ) => {
+                            if let Err(e) = res {
+                                event!(Level::DEBUG, "Rpc gate websocket of {:?} err: {:?}", ip, e);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        Ok(res)
+    }
+}
+
+async fn run_gate(listener: TcpListener, gate: Arc<Gate>, connections: Arc<Semaphore>) {
+    let mut rejected: u64 = 0;
+    let mut warned_at: Option<Instant> = None;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                event!(Level::WARN, "Rpc gate accept err: {:?}", e);
+                continue;
+            }
+        };
+        // Over `RPC_MAX_CONNECTIONS` the connection is answered with 503 and closed.
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(p) => Some(p),
+            Err(_) => {
+                rejected += 1;
+                if warned_at.map_or(true, |at| {
+                    at.elapsed() >= Duration::from_secs(REJECTED_WARN_SECONDS)
+                }) {
+                    event!(
+                        Level::WARN,
+                        "Rpc gate is at max connections {:?}, rejected {:?} connections, the last of {:?}",
+                        get_rpc_max_connections(),
+                        rejected,
+                        peer
+                    );
+                    rejected = 0;
+                    warned_at = Some(Instant::now());
+                }
+                None
+            }
+        };
+        let gate = gate.clone();
+        tokio::spawn(async move {
+            let _permit = match permit {
+                Some(p) => p,
+                None => {
+                    let _ = tokio::time::timeout(
+                        Duration::from_secs(REJECT_TIMEOUT_SECONDS),
+                        gate.reject(stream),
+                    )
+                    .await;
+                    return;
+                }
+            };
+            match &gate.tls {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => gate.clone().serve(stream, peer.ip()).await,
+                    Err(e) => event!(Level::DEBUG, "Rpc gate tls err of {:?}: {:?}", peer, e),
+                },
+                None => gate.clone().serve(stream, peer.ip()).await,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(1, 2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.check(ip, 1));
+        assert!(limiter.check(ip, 1));
+        assert!(!limiter.check(ip, 1));
+        // Another IP has its own bucket.
+        assert!(limiter.check("10.0.0.2".parse().unwrap(), 2));
+        assert!(!limiter.check("10.0.0.3".parse().unwrap(), 3));
+    }
+
+    #[test]
+    fn test_calls_in() {
+        assert_eq!(calls_in(b""), 1);
+        assert_eq!(
+            calls_in(br#"{"jsonrpc":"2.0","id":1,"method":"submitter_getRoot"}"#),
+            1
+        );
+        assert_eq!(calls_in(br#" [{"id":1},{"id":2},{"id":3}]"#), 3);
+        assert_eq!(calls_in(b"[not json"), 1);
+    }
+
+    // A masked client frame.
+    fn ws_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1u8, 2, 3, 4];
+        let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[tokio::test]
+    async fn test_forward_ws_calls() {
+        let limiter = RateLimiter::new(1, 3);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let call = br#"{"id":1}"#;
+        let batch = br#"[{"id":2},{"id":3}]"#;
+        let mut frames = vec![];
+        frames.extend(ws_frame(true, 0x1, call));
+        // A batch in two frames, with a ping in between.
+        frames.extend(ws_frame(false, 0x1, &batch[..5]));
+        frames.extend(ws_frame(true, 0x9, b""));
+        frames.extend(ws_frame(true, 0x0, &batch[5..]));
+        let forwarded = frames.clone();
+        // Over the burst of 3.
+        frames.extend(ws_frame(
+            true,
+            0x1,
+            br#"[{"id":4},{"id":5},{"id":6},{"id":7}]"#,
+        ));
+
+        let mut to = vec![];
+        let res = forward_ws_calls(Some(&limiter), ip, 1024, &frames[..], &mut to).await;
+        assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(to, forwarded);
+        // Every call of the batch took a token.
+        assert!(!limiter.check(ip, 1));
+
+        let mut to = vec![];
+        let res = forward_ws_calls(None, ip, 4, &ws_frame(true, 0x1, call)[..], &mut to).await;
+        assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(to.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gate_over_max_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let gate = Arc::new(Gate {
+            upstream: addr,
+            client: hyper::Client::builder().build_http(),
+            limiter: None,
+            tls: None,
+            token: HeaderValue::from_static(""),
+            max_request_bytes: 1024,
+        });
+        tokio::spawn(run_gate(listener, gate, Arc::new(Semaphore::new(0))));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut res = vec![];
+        stream.read_to_end(&mut res).await.unwrap();
+        assert_eq!(res, SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_describe_calls() {
+        assert_eq!(
+            describe_calls(br#"{"jsonrpc":"2.0","id":1,"method":"debug_replay","params":[1]}"#),
+            "debug_replay (id 1)"
+        );
+        assert_eq!(
+            describe_calls(br#"[{"id":"a","method":"m"},{"params":[]}]"#),
+            r#"m (id "a"), ? (id null)"#
+        );
+        assert_eq!(describe_calls(b"[not json"), "not json, 9 bytes");
+    }
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq(b"secret", b"secret"));
+        assert!(!token_eq(b"secret", b"secreT"));
+        assert!(!token_eq(b"secret", b"secret1"));
+    }
+}
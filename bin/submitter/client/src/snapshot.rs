@@ -0,0 +1,318 @@
+//! Portable snapshot of the submitter's state.
+//!
+//! A snapshot holds every profit leaf, every block leaf, `UserTokensDB` and `ProfitStatisticsDB`
+//! at the last archived block. A new submitter imports it and resumes sync from the next block
+//! instead of replaying from `START_BLOCK`.
+//!
+//! File layout: `SNAPSHOT_MAGIC`, the version (u32, little endian), the bincode encoded
+//! `SnapshotHeader` and the bincode encoded `SnapshotBody`. The header's checksum is the keccak256
+//! of the encoded body.
+
+use anyhow::{anyhow, Result};
+use contract::SubmitterContract;
+use ethers::{
+    prelude::{LocalWallet, Middleware},
+    types::Address,
+    utils::keccak256,
+};
+use primitives::{
+    func::block_number_convert_to_h256,
+    storage::Storage,
+    traits::{Contract as ContractTrait, StataTrait},
+    types::{BlockInfo, BlocksStateData, ProfitStateData, ProfitStatistics},
+};
+use serde::{Deserialize, Serialize};
+use state::{Keccak256Hasher, State, H256};
+use std::{
+    io::{Cursor, Read, Write},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+use tracing::{event, Level};
+use txs::sled_db::{ProfitStatisticsDB, UserTokensDB};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"SUBMSNAP";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// Leaves are written to the trees in batches of this size on import.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotHeader {
+    pub version: u32,
+    /// The last archived block.
+    pub block_number: u64,
+    pub profit_root: [u8; 32],
+    pub blocks_root: [u8; 32],
+    pub profit_leaves: u64,
+    pub block_leaves: u64,
+    pub checksum: [u8; 32],
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotBody {
+    pub profit_leaves: Vec<([u8; 32], ProfitStateData)>,
+    pub block_leaves: Vec<([u8; 32], BlocksStateData)>,
+    pub user_tokens: Vec<(Address, Vec<(u64, Address)>)>,
+    pub profit_statistics: Vec<((Address, u64, Address), ProfitStatistics)>,
+}
+
+pub fn encode_snapshot(header: &SnapshotHeader, body: &[u8]) -> Result<Vec<u8>> {
+    let mut bytes = SNAPSHOT_MAGIC.to_vec();
+    bytes.extend(header.version.to_le_bytes());
+    bytes.extend(bincode::serialize(header)?);
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+/// Check the magic, the version and the checksum, and decode the snapshot.
+pub fn decode_snapshot(bytes: &[u8]) -> Result<(SnapshotHeader, SnapshotBody)> {
+    let mut cursor = Cursor::new(bytes);
+    let mut magic = [0u8; 8];
+    let mut version = [0u8; 4];
+    cursor
+        .read_exact(&mut magic)
+        .map_err(|_| anyhow!("not a snapshot file"))?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(anyhow!("not a snapshot file"));
+    }
+    cursor.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != SNAPSHOT_VERSION {
+        return Err(anyhow!(
+            "unsupported snapshot version {}, expected {}",
+            version,
+            SNAPSHOT_VERSION
+        ));
+    }
+    let header: SnapshotHeader = bincode::deserialize_from(&mut cursor)?;
+    let body_bytes = &bytes[cursor.position() as usize..];
+    if keccak256(body_bytes) != header.checksum {
+        return Err(anyhow!("snapshot checksum mismatch, the file is corrupted"));
+    }
+    let body: SnapshotBody = bincode::deserialize(body_bytes)?;
+    if body.profit_leaves.len() as u64 != header.profit_leaves
+        || body.block_leaves.len() as u64 != header.block_leaves
+    {
+        return Err(anyhow!("snapshot leaves count mismatch"));
+    }
+    Ok((header, body))
+}
+
+/// Write the state at the last archived block to `out`. Export from a stopped submitter,
+/// the state can not be rolled back, so `block` must be the last archived block if given.
+pub fn export_state(
+    profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    storage: Arc<Storage>,
+    out: &str,
+    block: Option<u64>,
+) -> Result<SnapshotHeader> {
+    let profit_state = profit_state.read().unwrap();
+    let blocks_state = blocks_state.read().unwrap();
+
+    let block_leaves = blocks_state.try_get_leaves()?;
+    let block_number = block_leaves
+        .iter()
+        .map(|(_, b)| b.block_num)
+        .max()
+        .ok_or(anyhow!("no archived block, nothing to export"))?;
+    if let Some(block) = block {
+        if block != block_number {
+            return Err(anyhow!(
+                "the last archived block is #{}, can not export the state at block #{}",
+                block_number,
+                block
+            ));
+        }
+    }
+
+    let last_block = blocks_state.try_get(block_number_convert_to_h256(block_number))?;
+    let profit_root = profit_state.try_get_root()?;
+    if H256::from(last_block.profit_root) != profit_root {
+        return Err(anyhow!(
+            "profit root of block #{} does not match the profit state, is the submitter running?",
+            block_number
+        ));
+    }
+
+    let body = SnapshotBody {
+        profit_leaves: profit_state
+            .try_get_leaves()?
+            .into_iter()
+            .map(|(k, v)| (k.into(), v))
+            .collect(),
+        block_leaves: block_leaves
+            .into_iter()
+            .map(|(k, v)| (k.into(), v))
+            .collect(),
+        user_tokens: UserTokensDB::new(storage.clone())?.get_all_tokens()?,
+        profit_statistics: ProfitStatisticsDB::new(storage.clone())?.get_all_profit_statistics()?,
+    };
+    let body_bytes = bincode::serialize(&body)?;
+    let header = SnapshotHeader {
+        version: SNAPSHOT_VERSION,
+        block_number,
+        profit_root: profit_root.into(),
+        blocks_root: blocks_state.try_get_root()?.into(),
+        profit_leaves: body.profit_leaves.len() as u64,
+        block_leaves: body.block_leaves.len() as u64,
+        checksum: keccak256(&body_bytes),
+    };
+
+    let mut file = std::fs::File::create(out)?;
+    file.write_all(&encode_snapshot(&header, &body_bytes)?)?;
+    file.sync_all()?;
+    event!(
+        Level::INFO,
+        "State exported at Block #{:?} to {}. header: {:?}",
+        block_number,
+        out,
+        header,
+    );
+    Ok(header)
+}
+
+/// Rebuild the state trees and the key-value trees from the snapshot at `file` into an empty db,
+/// and check the roots against the header. With `verify_onchain`, the profit root must also be
+/// the one submitted on chain for the snapshot's block.
+pub async fn import_state(
+    profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    storage: Arc<Storage>,
+    file: &str,
+    verify_onchain: bool,
+) -> Result<SnapshotHeader> {
+    let (header, body) = decode_snapshot(&std::fs::read(file)?)?;
+
+    if verify_onchain {
+        verify_onchain_profit_root(&header).await?;
+    }
+
+    {
+        let mut profit_state = profit_state.write().unwrap();
+        let mut blocks_state = blocks_state.write().unwrap();
+        if profit_state.try_get_root()? != H256::zero()
+            || blocks_state.try_get_root()? != H256::zero()
+        {
+            return Err(anyhow!("the db is not empty, import into a new db path"));
+        }
+
+        for chunk in body.profit_leaves.chunks(IMPORT_BATCH_SIZE) {
+            profit_state.try_update_all(
+                chunk
+                    .iter()
+                    .map(|(k, v)| (H256::from(*k), v.clone()))
+                    .collect(),
+            )?;
+        }
+        for chunk in body.block_leaves.chunks(IMPORT_BATCH_SIZE) {
+            blocks_state.try_update_all(
+                chunk
+                    .iter()
+                    .map(|(k, v)| (H256::from(*k), v.clone()))
+                    .collect(),
+            )?;
+        }
+
+        let profit_root = profit_state.try_get_root()?;
+        let blocks_root = blocks_state.try_get_root()?;
+        if profit_root != H256::from(header.profit_root)
+            || blocks_root != H256::from(header.blocks_root)
+        {
+            profit_state.try_clear()?;
+            blocks_state.try_clear()?;
+            return Err(anyhow!(
+                "imported roots do not match the snapshot. profit root: {}, blocks root: {}",
+                hex::encode(profit_root.as_slice()),
+                hex::encode(blocks_root.as_slice()),
+            ));
+        }
+    }
+
+    let user_tokens_db = UserTokensDB::new(storage.clone())?;
+    for (user, tokens) in body.user_tokens {
+        user_tokens_db.insert_tokens(user, tokens)?;
+    }
+    let profit_statistics_db = ProfitStatisticsDB::new(storage.clone())?;
+    for ((user, chain_id, token), profit_statistics) in body.profit_statistics {
+        profit_statistics_db.insert_profit_statistics(user, chain_id, token, profit_statistics)?;
+    }
+    storage.flush()?;
+
+    event!(
+        Level::INFO,
+        "State imported at Block #{:?} from {}. header: {:?}",
+        header.block_number,
+        file,
+        header,
+    );
+    Ok(header)
+}
+
+async fn verify_onchain_profit_root(header: &SnapshotHeader) -> Result<()> {
+    // Read only, the same key as the no-private-key mode.
+    let wallet = LocalWallet::from_str(
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+    )?;
+    let (s, _r) = tokio::sync::broadcast::channel::<BlockInfo>(1);
+    let contract = SubmitterContract::new(
+        s,
+        wallet,
+        Arc::new(tokio::sync::RwLock::new(0)),
+        Arc::new(tokio::sync::RwLock::new(vec![])),
+    )
+    .await;
+    let block_number = contract.provider.get_block_number().await?.as_u64();
+    let storage = contract
+        .get_block_storage(block_number)
+        .await?
+        .ok_or(anyhow!("can not get the fee manager storage"))?;
+    // The submitted root covers the blocks before `last_update_block`.
+    if storage.last_update_block != header.block_number + 1 {
+        return Err(anyhow!(
+            "the on-chain root covers the blocks before #{}, the snapshot is at Block #{}",
+            storage.last_update_block,
+            header.block_number
+        ));
+    }
+    if storage.profit_root != header.profit_root {
+        return Err(anyhow!(
+            "profit root {} does not match the on-chain profit root {}",
+            hex::encode(header.profit_root),
+            hex::encode(storage.profit_root),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_encoding() {
+        let body = SnapshotBody {
+            profit_leaves: vec![([1u8; 32], ProfitStateData::default())],
+            block_leaves: vec![],
+            user_tokens: vec![(Address::zero(), vec![(1, Address::zero())])],
+            profit_statistics: vec![],
+        };
+        let body_bytes = bincode::serialize(&body).unwrap();
+        let header = SnapshotHeader {
+            version: SNAPSHOT_VERSION,
+            block_number: 100,
+            profit_leaves: 1,
+            checksum: keccak256(&body_bytes),
+            ..Default::default()
+        };
+        let mut bytes = encode_snapshot(&header, &body_bytes).unwrap();
+        assert_eq!(decode_snapshot(&bytes).unwrap(), (header, body));
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(decode_snapshot(&bytes).is_err());
+        bytes[8] = 2;
+        assert!(decode_snapshot(&bytes).is_err());
+    }
+}
@@ -12,12 +12,13 @@ use ethers::{
     middleware::{Middleware, SignerMiddleware},
     prelude::LocalWallet,
     providers::Provider,
-    types::{Address, Filter, TransactionReceipt, H160, H256, U256},
+    types::{Address, Bytes, Filter, TransactionReceipt, H160, H256, U256},
 };
 use primitives::env::{get_dealer_withdraw_delay, get_lock_duration, get_withdraw_duration};
 use primitives::{
     env::{get_fee_manager_contract_address, get_mainnet_chain_id, get_mainnet_rpc_urls},
     error::{Error as LocalError, Result},
+    retry::{retry, RetryPolicy, MAINNET_RPC_ENDPOINT},
     traits::Contract as ContractTrait,
     types::{BlockInfo, BlockStorage, DepositEvent, Event, FeeManagerDuration, WithdrawEvent},
 };
@@ -56,7 +57,7 @@ pub struct SubmitterContract {
     pub sender: Sender<BlockInfo>,
     pub provider: Provider<ethers_providers::Http>,
     pub client: SignerMiddleware<Provider<ethers_providers::Http>, LocalWallet>,
-    pub support_mainnet_tokens: Arc<Vec<Address>>,
+    pub support_mainnet_tokens: Arc<RwLock<Vec<Address>>>,
     pub now_block_num: Arc<RwLock<u64>>,
 }
 
@@ -65,7 +66,7 @@ impl SubmitterContract {
         sender: Sender<BlockInfo>,
         wallet: LocalWallet,
         now_block_num: Arc<RwLock<u64>>,
-        support_mainnet_tokens: Arc<Vec<Address>>,
+        support_mainnet_tokens: Arc<RwLock<Vec<Address>>>,
     ) -> Self {
         let provider =
             Provider::<ethers::providers::Http>::try_from(get_mainnet_rpc_urls()[0].clone())
@@ -78,7 +79,7 @@ impl SubmitterContract {
         event!(
             Level::INFO,
             "Successfully connected to the ethereum network. Support mainnet tokens: {:?}",
-            support_mainnet_tokens.as_ref().clone(),
+            support_mainnet_tokens.read().await.clone(),
         );
         Self {
             sender,
@@ -135,6 +136,67 @@ pub async fn run(contract: Arc<SubmitterContract>) -> Result<()> {
     }
 }
 
+/// The last root submitted to the FeeManager contract: (start block, end block, profit root).
+pub async fn get_submitted_root(provider: &Provider<Http>) -> Result<(u64, u64, [u8; 32])> {
+    let fee_manager_contract = FeeManagerContract::new(
+        get_fee_manager_contract_address(),
+        Arc::new(provider.clone()),
+    );
+    let (start_block, end_block, _, profit_root, _) =
+        fee_manager_contract.submissions().call().await?;
+    Ok((start_block, end_block, profit_root))
+}
+
+/// Arguments of `withdrawVerification`, one entry per leaf.
+#[derive(Debug, Clone, Default)]
+pub struct WithdrawArgs {
+    /// (chain id, token, user, amount, debt)
+    pub leaves: Vec<(u64, Address, Address, U256, U256)>,
+    pub siblings: Vec<Vec<[u8; 32]>>,
+    pub start_index: Vec<u8>,
+    pub first_zero_bits: Vec<[u8; 32]>,
+    pub bitmaps: Vec<U256>,
+    pub withdraw_amount: Vec<U256>,
+}
+
+/// Encode the `withdrawVerification` call of `from` and simulate it with `eth_call`. Returns the
+/// calldata and, if the call reverts, why.
+pub async fn simulate_withdraw(
+    provider: &Provider<Http>,
+    from: Address,
+    args: WithdrawArgs,
+) -> Result<(Bytes, Option<String>)> {
+    let fee_manager_contract = FeeManagerContract::new(
+        get_fee_manager_contract_address(),
+        Arc::new(provider.clone()),
+    );
+    let call = fee_manager_contract
+        .withdraw_verification(
+            args.leaves
+                .into_iter()
+                .map(|(chain_id, token, user, amount, debt)| Smtleaf {
+                    chain_id,
+                    token,
+                    user,
+                    amount,
+                    debt,
+                })
+                .collect(),
+            args.siblings,
+            args.start_index,
+            args.first_zero_bits,
+            args.bitmaps,
+            args.withdraw_amount,
+        )
+        .from(from);
+    let calldata = call.calldata().unwrap_or_default();
+    let simulation_error = match call.call().await {
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    };
+    Ok((calldata, simulation_error))
+}
+
 #[async_trait]
 impl ContractTrait for SubmitterContract {
     async fn submit_root(
@@ -322,7 +384,14 @@ impl ContractTrait for SubmitterContract {
             .topic0(vec![deposit_id, withdraw_id])
             .from_block(from_block)
             .to_block(to_block);
-        let logs = self.client.get_logs(&filter).await.unwrap_or(vec![]);
+        let provider = &self.provider;
+        let filter = &filter;
+        let logs = retry(
+            MAINNET_RPC_ENDPOINT,
+            &RetryPolicy::default(),
+            move || async move { provider.get_logs(filter).await.map_err(LocalError::from) },
+        )
+        .await?;
 
         let mut events: Vec<Event> = vec![];
         for log in logs {
@@ -445,13 +514,12 @@ impl ContractTrait for SubmitterContract {
 
         let events = self
             .get_feemanager_contract_events(from_block, to_block)
-            .await
-            .unwrap();
+            .await?;
 
         // TODO: Currently only supports eth, and will be optimized later.
         // let erc_transfer_events = self
         //     .get_erc20_transfer_events_by_tokens_id(
-        //         self.support_mainnet_tokens.as_ref().clone(),
+        //         self.support_mainnet_tokens.read().await.clone(),
         //         from_block,
         //     )
         //     .await?;
@@ -501,10 +569,20 @@ impl ContractTrait for SubmitterContract {
         let fee_manager_contract_address: H160 = get_fee_manager_contract_address();
         let fee_manager_contract =
             FeeManagerContract::new(fee_manager_contract_address, Arc::new(self.client.clone()));
-        let info = fee_manager_contract
-            .get_dealer_info(dealer)
-            .block(block_number)
-            .await?;
+        let fee_manager_contract = &fee_manager_contract;
+        let info = retry(
+            MAINNET_RPC_ENDPOINT,
+            &RetryPolicy::default(),
+            move || async move {
+                fee_manager_contract
+                    .get_dealer_info(dealer)
+                    .block(block_number)
+                    .call()
+                    .await
+                    .map_err(LocalError::from)
+            },
+        )
+        .await?;
         let r = info.fee_ratio.as_u64();
         event!(
             Level::INFO,
@@ -56,11 +56,11 @@ async fn local_test() {
 
     let (s, _r) = tokio::sync::broadcast::channel::<BlockInfo>(100);
     let start_num = Arc::new(RwLock::new(064));
-    let tokens: Arc<Vec<Address>> = Arc::new(vec![
+    let tokens: Arc<RwLock<Vec<Address>>> = Arc::new(RwLock::new(vec![
         Address::from_str("0xa3a8a6b323e3d38f5284db9337e7c6d74af3366a").unwrap(),
         Address::from_str("0xa0321efeb50c46c17a7d72a52024eea7221b215a").unwrap(),
         Address::from_str("0x29b6a77911c1ce3b3849f28721c65dada015c768").unwrap(),
-    ]);
+    ]));
     let contract = SubmitterContract::new(s.clone(), wallet.clone(), start_num, tokens).await;
     // 9734015
     let block_infos = contract.get_block_infos(9733395, 9733395).await;
@@ -31,17 +31,18 @@ use ethers::{
     utils::keccak256,
 };
 pub use primitives::keccak256_hasher::Keccak256Hasher;
-use primitives::{error::Result, traits::StataTrait, types::AbiDecode};
+use primitives::{error::Result, storage::Storage, traits::StataTrait, types::AbiDecode};
 use rocksdb::{prelude::Iterate, Direction, IteratorMode};
 pub use rocksdb::{prelude::Open, DBVector, OptimisticTransaction, OptimisticTransactionDB};
 use serde::{Deserialize, Serialize};
 use smt_rocksdb_store::default_store::DefaultStoreMultiTree;
+use sparse_merkle_tree::default_store::DefaultStore;
 use sparse_merkle_tree::merge::MergeValue;
 pub use sparse_merkle_tree::{
-    traits::{Hasher, Value},
+    traits::{Hasher, StoreReadOps, Value},
     CompiledMerkleProof, SparseMerkleTree, H256,
 };
-use std::{fmt::Debug, marker::PhantomData};
+use std::{fmt::Debug, marker::PhantomData, sync::Arc};
 use thiserror::Error;
 
 type DefaultStoreMultiSMT<'a, H, T, W, Data> =
@@ -123,6 +124,50 @@ impl<D: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable
     }
 }
 
+/// Proofs of several leaves, read from one snapshot.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StateProofs<D> {
+    pub root: H256,
+    /// The leaves in the order of the keys.
+    pub leaves: Vec<D>,
+    /// The leaf bitmap and the siblings of each key, empty if the proof is compiled.
+    pub paths: Vec<(H256, Vec<MergeValue>)>,
+    /// The compiled multiproof of all the keys.
+    pub compiled: Option<Vec<u8>>,
+}
+
+fn proofs_of<H, D, S>(
+    smt: &SparseMerkleTree<H, SmtValue<D>, S>,
+    keys: Vec<H256>,
+    compile: bool,
+) -> Result<StateProofs<D>>
+where
+    H: Hasher + Default,
+    D: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable + AbiDecode,
+    S: StoreReadOps<SmtValue<D>>,
+{
+    let mut proofs = StateProofs {
+        root: *smt.root(),
+        leaves: vec![],
+        paths: vec![],
+        compiled: None,
+    };
+    for key in keys.iter() {
+        proofs.leaves.push(smt.get(key)?.get_data());
+        if !compile {
+            let proof = smt.merkle_proof(vec![*key])?;
+            proofs
+                .paths
+                .push((proof.leaves_bitmap()[0], proof.merkle_path().clone()));
+        }
+    }
+    if compile && !keys.is_empty() {
+        let proof = smt.merkle_proof(keys.clone())?;
+        proofs.compiled = Some(proof.compile(keys)?.0);
+    }
+    Ok(proofs)
+}
+
 /// The state of the bundler.
 /// stores off-chain state, its merkle root is stored on-chain.
 /// Each entry point contract of each chain has a state.
@@ -132,7 +177,7 @@ pub struct State<
     D: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable + AbiDecode,
 > {
     prefix: &'a [u8],
-    db: OptimisticTransactionDB,
+    storage: Arc<Storage>,
     _hasher: PhantomData<(H, D)>,
 }
 
@@ -142,44 +187,110 @@ impl<
         D: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable + AbiDecode,
     > State<'a, H, D>
 {
-    pub fn new(prefix: &'a [u8], db: OptimisticTransactionDB) -> Self {
+    /// `prefix` separates this tree from the other trees in the storage.
+    pub fn new(prefix: &'a [u8], storage: Arc<Storage>) -> Self {
         State {
             prefix,
-            db,
+            storage,
             _hasher: PhantomData,
         }
     }
-}
 
-impl<
-        H,
-        Data: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable + AbiDecode,
-    > StataTrait<H256, Data> for State<'_, H, Data>
-where
-    H: Hasher + Default,
-{
-    fn try_update_all(&mut self, future_k_v: Vec<(H256, Data)>) -> Result<H256> {
+    /// Update the leaves through `tx`, so that they are committed together with the other writes
+    /// of the transaction. Returns the root as seen through `tx`.
+    pub fn try_update_all_in(
+        &self,
+        tx: &OptimisticTransaction,
+        future_k_v: Vec<(H256, D)>,
+    ) -> Result<H256> {
         let kvs = future_k_v
             .into_iter()
             .map(|(k, v)| match SmtValue::new(v) {
                 Ok(v) => Ok((k, v)),
                 Err(e) => Err(e),
             })
-            .collect::<Result<Vec<(H256, SmtValue<Data>)>>>()?;
+            .collect::<Result<Vec<(H256, SmtValue<D>)>>>()?;
 
-        let tx = self.db.transaction_default();
         let mut rocksdb_store_smt: SparseMerkleTree<
             H,
-            SmtValue<Data>,
+            SmtValue<D>,
             DefaultStoreMultiTree<'_, OptimisticTransaction, ()>,
-        > = DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::new(self.prefix, &tx))?;
+        > = DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::new(self.prefix, tx))?;
         rocksdb_store_smt.update_all(kvs)?;
-        tx.commit()?;
         Ok(*rocksdb_store_smt.root())
     }
 
+    /// The leaf at `key` as seen through `tx`, including its uncommitted writes.
+    pub fn try_get_in(&self, tx: &OptimisticTransaction, key: H256) -> Result<D> {
+        let rocksdb_store_smt: SparseMerkleTree<
+            H,
+            SmtValue<D>,
+            DefaultStoreMultiTree<'_, OptimisticTransaction, ()>,
+        > = DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::new(self.prefix, tx))?;
+        Ok(rocksdb_store_smt.get(&key)?.get_data())
+    }
+
+    /// The root and the leaves at `keys` with their proofs, all read from one snapshot, so they
+    /// never mix two roots. With `compile`, one multiproof of all the keys instead of a path for
+    /// each.
+    pub fn try_get_proofs(&self, keys: Vec<H256>, compile: bool) -> Result<StateProofs<D>> {
+        let snapshot = self.storage.db().snapshot();
+        let rocksdb_store_smt: SparseMerkleTree<H, SmtValue<D>, DefaultStoreMultiTree<'_, _, ()>> =
+            DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::<_, ()>::new(
+                self.prefix,
+                &snapshot,
+            ))?;
+        proofs_of(&rocksdb_store_smt, keys, compile)
+    }
+
+    /// Same as `try_get_proofs`, as seen through `tx`, including its uncommitted writes.
+    pub fn try_get_proofs_in(
+        &self,
+        tx: &OptimisticTransaction,
+        keys: Vec<H256>,
+        compile: bool,
+    ) -> Result<StateProofs<D>> {
+        let rocksdb_store_smt: SparseMerkleTree<
+            H,
+            SmtValue<D>,
+            DefaultStoreMultiTree<'_, OptimisticTransaction, ()>,
+        > = DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::new(self.prefix, tx))?;
+        proofs_of(&rocksdb_store_smt, keys, compile)
+    }
+
+    /// Recompute the root from the leaves alone in memory, ignoring the stored branches.
+    pub fn try_compute_root_from_leaves(&self) -> Result<H256> {
+        let kvs = self
+            .try_get_leaves()?
+            .into_iter()
+            .map(|(k, v)| match SmtValue::new(v) {
+                Ok(v) => Ok((k, v)),
+                Err(e) => Err(e),
+            })
+            .collect::<Result<Vec<(H256, SmtValue<D>)>>>()?;
+        let mut smt: SparseMerkleTree<H, SmtValue<D>, DefaultStore<SmtValue<D>>> =
+            SparseMerkleTree::default();
+        smt.update_all(kvs)?;
+        Ok(*smt.root())
+    }
+}
+
+impl<
+        H,
+        Data: Debug + Clone + Default + Eq + PartialEq + TokenizableItem + Tokenizable + AbiDecode,
+    > StataTrait<H256, Data> for State<'_, H, Data>
+where
+    H: Hasher + Default,
+{
+    fn try_update_all(&mut self, future_k_v: Vec<(H256, Data)>) -> Result<H256> {
+        let tx = self.storage.transaction();
+        let root = self.try_update_all_in(&tx, future_k_v)?;
+        tx.commit()?;
+        Ok(root)
+    }
+
     fn try_clear(&mut self) -> Result<()> {
-        let snapshot = self.db.snapshot();
+        let snapshot = self.storage.db().snapshot();
         let prefix = self.prefix;
         let prefix_len = prefix.len();
         let leaf_key_len = prefix_len + 32;
@@ -196,7 +307,7 @@ where
             })
             .collect();
 
-        let tx = self.db.transaction_default();
+        let tx = self.storage.transaction();
         let mut rocksdb_store_smt: SparseMerkleTree<
             H,
             SmtValue<Data>,
@@ -217,7 +328,7 @@ where
     }
 
     fn try_get_merkle_proof(&self, keys: Vec<H256>) -> Result<Vec<u8>> {
-        let snapshot = self.db.snapshot();
+        let snapshot = self.storage.db().snapshot();
         let rocksdb_store_smt: SparseMerkleTree<
             H,
             SmtValue<Data>,
@@ -232,7 +343,7 @@ where
     }
 
     fn try_get_merkle_proof_1(&self, key: H256) -> Result<(H256, Vec<MergeValue>)> {
-        let snapshot = self.db.snapshot();
+        let snapshot = self.storage.db().snapshot();
         let rocksdb_store_smt: SparseMerkleTree<
             H,
             SmtValue<Data>,
@@ -267,7 +378,7 @@ where
     }
 
     fn try_get(&self, key: H256) -> Result<Data> {
-        let snapshot = self.db.snapshot();
+        let snapshot = self.storage.db().snapshot();
         let rocksdb_store_smt: SparseMerkleTree<
             H,
             SmtValue<Data>,
@@ -282,7 +393,7 @@ where
     }
 
     fn try_get_root(&self) -> Result<H256> {
-        let snapshot = self.db.snapshot();
+        let snapshot = self.storage.db().snapshot();
         let rocksdb_store_smt: SparseMerkleTree<
             H,
             SmtValue<Data>,
@@ -294,4 +405,55 @@ where
         let root = *rocksdb_store_smt.root();
         Ok(root)
     }
+
+    fn try_get_leaves(&self) -> Result<Vec<(H256, Data)>> {
+        Ok(self.try_get_leaves_page(None, usize::MAX)?.0)
+    }
+
+    fn try_get_leaves_page(
+        &self,
+        cursor: Option<H256>,
+        limit: usize,
+    ) -> Result<(Vec<(H256, Data)>, Option<H256>)> {
+        let snapshot = self.storage.db().snapshot();
+        let prefix = self.prefix;
+        let prefix_len = prefix.len();
+        let leaf_key_len = prefix_len + 32;
+        let rocksdb_store_smt: SparseMerkleTree<
+            H,
+            SmtValue<Data>,
+            DefaultStoreMultiTree<'_, _, ()>,
+        > = DefaultStoreMultiSMT::new_with_store(DefaultStoreMultiTree::<_, ()>::new(
+            prefix, &snapshot,
+        ))?;
+        let mut from = prefix.to_vec();
+        if let Some(cursor) = cursor {
+            from.extend_from_slice(cursor.as_slice());
+        }
+        let mut leaves = vec![];
+        for (k, _) in snapshot
+            .iterator(IteratorMode::From(&from, Direction::Forward))
+            .take_while(|(k, _)| k.starts_with(prefix))
+        {
+            if k.len() != leaf_key_len {
+                continue;
+            }
+            let leaf_key: [u8; 32] = k[prefix_len..].try_into().expect("checked 32 bytes");
+            let key: H256 = leaf_key.into();
+            if Some(key) == cursor {
+                continue;
+            }
+            let data = rocksdb_store_smt.get(&key)?.get_data();
+            if data != Data::default() {
+                leaves.push((key, data));
+            }
+            // One more leaf than the limit tells whether there is a next page.
+            if leaves.len() > limit {
+                leaves.pop();
+                let next = leaves.last().map(|(k, _)| *k);
+                return Ok((leaves, next));
+            }
+        }
+        Ok((leaves, None))
+    }
 }
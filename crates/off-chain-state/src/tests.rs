@@ -2,13 +2,13 @@
 
 use super::{State, *};
 use ethers::{types::U256, utils::keccak256};
+use primitives::storage::Storage;
 use primitives::{func::chain_token_address_convert_to_h256, types::ProfitStateData};
 use sparse_merkle_tree::{
     merge::{hash_base_node, merge},
     CompiledMerkleProof,
 };
-use std::str::FromStr;
-use OptimisticTransactionDB;
+use std::{str::FromStr, sync::Arc};
 
 pub fn into_merge_value<H: Hasher + Default>(key: H256, value: H256, height: u8) -> MergeValue {
     // try keep hash same with MergeWithZero
@@ -32,9 +32,9 @@ pub fn into_merge_value<H: Hasher + Default>(key: H256, value: H256, height: u8)
 }
 
 fn new_state() -> State<'static, Keccak256Hasher, ProfitStateData> {
-    let db = OptimisticTransactionDB::open_default("./db1").unwrap();
+    let storage = Arc::new(Storage::open("./db1").unwrap());
     let prefix = b"test";
-    State::new(prefix, db)
+    State::new(prefix, storage)
 }
 
 // fn update_db(k_v: Vec<(H256, ProfitStateData)>) -> SMT {
@@ -74,6 +74,89 @@ fn test_state() {
     println!("value: {:?}", value);
 }
 
+#[test]
+fn test_leaves_page() {
+    let path = "./db_leaves";
+    let _ = std::fs::remove_dir_all(path);
+    let storage = Arc::new(Storage::open(path).unwrap());
+    let mut tree: State<'static, Keccak256Hasher, ProfitStateData> = State::new(b"leaves", storage);
+    tree.try_clear().unwrap();
+    let token: Address = Address::from_str("0x0000000000000000000000000000000000000011").unwrap();
+    let kvs: Vec<(H256, ProfitStateData)> = (1..=5u64)
+        .map(|i| {
+            (
+                chain_token_address_convert_to_h256(i, token, Address::from_low_u64_be(i)),
+                ProfitStateData {
+                    token,
+                    token_chain_id: i,
+                    balance: U256::from(i),
+                    debt: U256::zero(),
+                },
+            )
+        })
+        .collect();
+    tree.try_update_all(kvs.clone()).unwrap();
+
+    let mut leaves = vec![];
+    let mut cursor = None;
+    loop {
+        let (page, next) = tree.try_get_leaves_page(cursor, 2).unwrap();
+        assert!(page.len() <= 2);
+        leaves.extend(page);
+        if next.is_none() {
+            break;
+        }
+        cursor = next;
+    }
+    assert_eq!(leaves.len(), kvs.len());
+    assert_eq!(leaves, tree.try_get_leaves().unwrap());
+    for kv in kvs {
+        assert!(leaves.contains(&kv));
+    }
+}
+
+#[test]
+fn test_proofs() {
+    let path = "./db_proofs";
+    let _ = std::fs::remove_dir_all(path);
+    let storage = Arc::new(Storage::open(path).unwrap());
+    let mut tree: State<'static, Keccak256Hasher, ProfitStateData> = State::new(b"proofs", storage);
+    tree.try_clear().unwrap();
+    let token: Address = Address::from_str("0x0000000000000000000000000000000000000011").unwrap();
+    let kvs: Vec<(H256, ProfitStateData)> = (1..=3u64)
+        .map(|i| {
+            (
+                chain_token_address_convert_to_h256(i, token, Address::from_low_u64_be(i)),
+                ProfitStateData {
+                    token,
+                    token_chain_id: i,
+                    balance: U256::from(i),
+                    debt: U256::zero(),
+                },
+            )
+        })
+        .collect();
+    let root = tree.try_update_all(kvs.clone()).unwrap();
+    let keys: Vec<H256> = kvs.iter().map(|(k, _)| *k).collect();
+
+    let proofs = tree.try_get_proofs(keys.clone(), false).unwrap();
+    assert_eq!(proofs.root, root);
+    assert_eq!(proofs.paths.len(), 3);
+    for (i, (k, v)) in kvs.iter().enumerate() {
+        assert_eq!(&proofs.leaves[i], v);
+        assert_eq!(proofs.paths[i], tree.try_get_merkle_proof_1(*k).unwrap());
+    }
+
+    let proofs = tree.try_get_proofs(keys.clone(), true).unwrap();
+    assert!(proofs.paths.is_empty());
+    let leaves: Vec<(H256, H256)> = kvs
+        .iter()
+        .map(|(k, v)| (*k, SmtValue::new(v.clone()).unwrap().to_h256()))
+        .collect();
+    let compiled = CompiledMerkleProof(proofs.compiled.unwrap());
+    assert!(compiled.verify::<Keccak256Hasher>(&root, leaves).unwrap());
+}
+
 #[test]
 fn main() {
     // let data = ProfitStateData {
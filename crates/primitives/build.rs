@@ -0,0 +1,589 @@
+//! Generate the OpenRPC documents of the `#[rpc]` traits in `src/traits.rs`, one per namespace,
+//! into `$OUT_DIR/{namespace}.openrpc.json`. See `primitives::openrpc`.
+//!
+//! The serialized structs and enums of `TYPES` are described under `components.schemas`, and the
+//! params and results of those types refer to them with `$ref`. The serde attributes read are
+//! `rename_all`, `rename` and `serde_as` hex, others fail the build.
+
+use quote::ToTokens;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+use syn::{
+    punctuated::Punctuated, Attribute, Expr, ExprLit, Fields, FnArg, GenericArgument, Item, Lit,
+    LitStr, Meta, Pat, PathArguments, ReturnType, Token, TraitItem, Type,
+};
+
+const TRAITS: &str = "src/traits.rs";
+// The files of the types used by the rpc traits.
+const TYPES: &[&str] = &["src/types.rs", "src/retry.rs"];
+
+// Names of the types described under `components.schemas`.
+type Components = BTreeSet<String>;
+
+fn docs(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The arguments of `#[rpc(...)]`, `#[method(...)]` and `#[subscription(...)]` used here. Names
+/// of subscriptions are `"subscribe" => "notification"`.
+#[derive(Default)]
+struct RpcAttr {
+    namespace: Option<String>,
+    name: Option<String>,
+    notification: Option<String>,
+    unsubscribe: Option<String>,
+    item: Option<Type>,
+}
+
+fn parse_attr(attr: &Attribute) -> syn::Result<RpcAttr> {
+    let mut args = RpcAttr::default();
+    attr.parse_args_with(syn::meta::parser(|meta| {
+        if meta.path.is_ident("namespace") {
+            args.namespace = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("name") {
+            let value = meta.value()?;
+            args.name = Some(value.parse::<LitStr>()?.value());
+            if value.peek(Token![=>]) {
+                value.parse::<Token![=>]>()?;
+                args.notification = Some(value.parse::<LitStr>()?.value());
+            }
+        } else if meta.path.is_ident("unsubscribe") {
+            args.unsubscribe = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("item") {
+            args.item = Some(meta.value()?.parse()?);
+        } else if meta.input.peek(Token![=]) {
+            // Other arguments, e.g. `aliases = [...]`, are not documented.
+            meta.value()?.parse::<Expr>()?;
+        }
+        Ok(())
+    }))?;
+    Ok(args)
+}
+
+fn missing(attr: &Attribute, key: &str) -> syn::Error {
+    syn::Error::new_spanned(attr, format!("`{}` is missing", key))
+}
+
+fn type_name(ty: &Type) -> String {
+    ty.to_token_stream().to_string().replace(' ', "")
+}
+
+/// The generic arguments of `outer<...>`, e.g. of `HashMap<K, V>`.
+fn type_args<'a>(ty: &'a Type, outer: &str) -> Option<Vec<&'a Type>> {
+    if let Type::Path(p) = ty {
+        let segment = p.path.segments.last()?;
+        if segment.ident != outer {
+            return None;
+        }
+        if let PathArguments::AngleBracketed(args) = &segment.arguments {
+            return Some(
+                args.args
+                    .iter()
+                    .filter_map(|a| match a {
+                        GenericArgument::Type(t) => Some(t),
+                        _ => None,
+                    })
+                    .collect(),
+            );
+        }
+    }
+    None
+}
+
+/// The single generic argument of `RpcResult<T>`, `Option<T>` or `Vec<T>`.
+fn inner_type<'a>(ty: &'a Type, outer: &str) -> Option<&'a Type> {
+    type_args(ty, outer).and_then(|args| args.first().copied())
+}
+
+fn schema(ty: &Type, components: &Components) -> Value {
+    if let Some(t) = inner_type(ty, "Option") {
+        return json!({ "oneOf": [schema(t, components), { "type": "null" }] });
+    }
+    if let Some(t) = inner_type(ty, "Vec") {
+        return json!({ "type": "array", "items": schema(t, components) });
+    }
+    if let Some(t) = inner_type(ty, "Box") {
+        return schema(t, components);
+    }
+    for map in ["HashMap", "BTreeMap"] {
+        if let Some([_, v]) = type_args(ty, map).as_deref() {
+            return json!({ "type": "object", "additionalProperties": schema(v, components) });
+        }
+    }
+    match ty {
+        Type::Reference(r) => return schema(&r.elem, components),
+        Type::Array(a) => {
+            let mut array = json!({ "type": "array", "items": schema(&a.elem, components) });
+            if let Expr::Lit(ExprLit {
+                lit: Lit::Int(len), ..
+            }) = &a.len
+            {
+                if let Ok(len) = len.base10_parse::<u64>() {
+                    array["minItems"] = json!(len);
+                    array["maxItems"] = json!(len);
+                }
+            }
+            return array;
+        }
+        Type::Tuple(t) => {
+            let items: Vec<Value> = t.elems.iter().map(|e| schema(e, components)).collect();
+            if items.is_empty() {
+                return json!({ "type": "null" });
+            }
+            return json!({
+                "type": "array",
+                "prefixItems": items,
+                "minItems": t.elems.len(),
+                "maxItems": t.elems.len(),
+            });
+        }
+        _ => {}
+    }
+    let name = type_name(ty);
+    match name.as_str() {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128"
+        | "isize" => json!({ "type": "integer" }),
+        "f32" | "f64" => json!({ "type": "number" }),
+        "bool" => json!({ "type": "boolean" }),
+        "String" | "str" => json!({ "type": "string" }),
+        "Address" => json!({ "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$", "title": name }),
+        "H256" => json!({ "type": "string", "pattern": "^0x[0-9a-fA-F]{64}$", "title": name }),
+        "U256" | "U64" => json!({ "type": "string", "pattern": "^0x[0-9a-fA-F]+$", "title": name }),
+        _ if components.contains(&name) => {
+            json!({ "$ref": format!("#/components/schemas/{}", name) })
+        }
+        _ => json!({ "type": "object", "title": name }),
+    }
+}
+
+fn content_descriptor(name: &str, ty: &Type, components: &Components) -> Value {
+    json!({
+        "name": name,
+        "required": inner_type(ty, "Option").is_none(),
+        "schema": schema(ty, components),
+    })
+}
+
+/// The arguments of `#[serde(...)]` and `#[serde_as(...)]` used here.
+#[derive(Default)]
+struct SerdeAttr {
+    rename_all: Option<String>,
+    rename: Option<String>,
+    serde_as: Option<String>,
+}
+
+fn parse_serde_attrs(attrs: &[Attribute]) -> syn::Result<SerdeAttr> {
+    let mut args = SerdeAttr::default();
+    for attr in attrs {
+        if attr.path().is_ident("serde_as") {
+            if let Meta::List(_) = attr.meta {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("as") {
+                        args.serde_as = Some(meta.value()?.parse::<LitStr>()?.value());
+                    } else {
+                        return Err(meta.error("unsupported serde_as attribute"));
+                    }
+                    Ok(())
+                })?;
+            }
+        } else if attr.path().is_ident("serde") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename_all") {
+                    args.rename_all = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("rename") {
+                    args.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else {
+                    return Err(meta.error("unsupported serde attribute"));
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok(args)
+}
+
+fn renamed(name: String, rename_all: Option<&str>, attr: &Attribute) -> syn::Result<String> {
+    match rename_all {
+        None => Ok(name),
+        Some("camelCase") => {
+            let mut parts = name.split('_');
+            let mut renamed = parts.next().unwrap_or_default().to_string();
+            for part in parts {
+                let mut chars = part.chars();
+                if let Some(first) = chars.next() {
+                    renamed.extend(first.to_uppercase());
+                    renamed.push_str(chars.as_str());
+                }
+            }
+            Ok(renamed)
+        }
+        Some(r) => Err(syn::Error::new_spanned(
+            attr,
+            format!("rename_all = {:?} is not supported", r),
+        )),
+    }
+}
+
+fn is_serialized(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("derive"))
+        .any(|a| {
+            a.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                .is_ok_and(|paths| {
+                    paths
+                        .iter()
+                        .any(|p| p.segments.last().is_some_and(|s| s.ident == "Serialize"))
+                })
+        })
+}
+
+// The attribute an error of a struct or an enum points at.
+fn container_attr(attrs: &[Attribute]) -> &Attribute {
+    attrs
+        .iter()
+        .find(|a| a.path().is_ident("serde"))
+        .unwrap_or(&attrs[0])
+}
+
+fn with_description(mut schema: Value, attrs: &[Attribute]) -> Value {
+    let description = docs(attrs);
+    if !description.is_empty() {
+        if let Value::Object(o) = &mut schema {
+            o.insert("description".into(), json!(description));
+        }
+    }
+    schema
+}
+
+/// The schema of the fields of a struct or an enum variant, as serde writes them.
+fn fields_schema(
+    fields: &Fields,
+    rename_all: Option<&str>,
+    attr: &Attribute,
+    components: &Components,
+) -> syn::Result<Value> {
+    match fields {
+        Fields::Named(named) => {
+            let mut properties = Map::new();
+            let mut required = vec![];
+            for field in named.named.iter() {
+                let args = parse_serde_attrs(&field.attrs)?;
+                let ident = field.ident.as_ref().expect("named field").to_string();
+                let name = match args.rename {
+                    Some(name) => name,
+                    None => renamed(ident, rename_all, attr)?,
+                };
+                let hex = json!({ "type": "string", "pattern": "^[0-9a-fA-F]*$" });
+                let field_schema = match args.serde_as.as_deref() {
+                    Some(a) if a.starts_with("Option<") && a.contains("Hex") => {
+                        json!({ "oneOf": [hex, { "type": "null" }] })
+                    }
+                    Some(a) if a.contains("Hex") => hex,
+                    Some(a) => {
+                        return Err(syn::Error::new_spanned(
+                            field,
+                            format!("serde_as = {:?} is not supported", a),
+                        ))
+                    }
+                    None => schema(&field.ty, components),
+                };
+                if inner_type(&field.ty, "Option").is_none() {
+                    required.push(name.clone());
+                }
+                properties.insert(name, with_description(field_schema, &field.attrs));
+            }
+            Ok(json!({ "type": "object", "properties": properties, "required": required }))
+        }
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            Ok(schema(&unnamed.unnamed[0].ty, components))
+        }
+        Fields::Unnamed(unnamed) => {
+            let items: Vec<Value> = unnamed
+                .unnamed
+                .iter()
+                .map(|f| schema(&f.ty, components))
+                .collect();
+            Ok(json!({
+                "type": "array",
+                "prefixItems": items,
+                "minItems": unnamed.unnamed.len(),
+                "maxItems": unnamed.unnamed.len(),
+            }))
+        }
+        Fields::Unit => Ok(json!({ "type": "null" })),
+    }
+}
+
+/// The schema of a serialized struct or enum. Enums are externally tagged: unit variants are
+/// their names, the others an object with the name as the only key.
+fn component(item: &Item, components: &Components) -> syn::Result<Option<(String, Value)>> {
+    let (ident, attrs) = match item {
+        Item::Struct(s) => (&s.ident, &s.attrs),
+        Item::Enum(e) => (&e.ident, &e.attrs),
+        _ => return Ok(None),
+    };
+    if !is_serialized(attrs) {
+        return Ok(None);
+    }
+    let args = parse_serde_attrs(attrs)?;
+    let attr = container_attr(attrs);
+    let schema = match item {
+        Item::Struct(s) => fields_schema(&s.fields, args.rename_all.as_deref(), attr, components)?,
+        Item::Enum(e) => {
+            let mut units = vec![];
+            let mut one_of = vec![];
+            for variant in e.variants.iter() {
+                let variant_args = parse_serde_attrs(&variant.attrs)?;
+                let name = match variant_args.rename {
+                    Some(name) => name,
+                    None => renamed(variant.ident.to_string(), args.rename_all.as_deref(), attr)?,
+                };
+                match &variant.fields {
+                    Fields::Unit => units.push(json!(name)),
+                    fields => {
+                        let mut properties = Map::new();
+                        properties.insert(
+                            name.clone(),
+                            fields_schema(
+                                fields,
+                                variant_args.rename_all.as_deref(),
+                                attr,
+                                components,
+                            )?,
+                        );
+                        one_of.push(with_description(
+                            json!({
+                                "type": "object",
+                                "properties": properties,
+                                "required": [name],
+                                "additionalProperties": false,
+                            }),
+                            &variant.attrs,
+                        ));
+                    }
+                }
+            }
+            let units = json!({ "type": "string", "enum": units });
+            match one_of.is_empty() {
+                true => units,
+                false => {
+                    one_of.insert(0, units);
+                    json!({ "oneOf": one_of })
+                }
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((ident.to_string(), with_description(schema, attrs))))
+}
+
+/// The component schemas of the serialized types in `sources`, by name. Items that can not be
+/// read are left out and returned as errors.
+fn components(sources: &[(&str, String)]) -> (BTreeMap<String, Value>, Vec<String>) {
+    let mut schemas = BTreeMap::new();
+    let mut errors = vec![];
+    let mut files = vec![];
+    for (path, source) in sources {
+        match syn::parse_file(source) {
+            Ok(file) => files.push((path, file)),
+            Err(e) => errors.push(format!("{}: {}", path, e)),
+        }
+    }
+    // Every name first, so that the types can refer to the ones after them.
+    let names: Components = files
+        .iter()
+        .flat_map(|(_, file)| file.items.iter())
+        .filter_map(|item| match item {
+            Item::Struct(s) if is_serialized(&s.attrs) => Some(s.ident.to_string()),
+            Item::Enum(e) if is_serialized(&e.attrs) => Some(e.ident.to_string()),
+            _ => None,
+        })
+        .collect();
+    for (path, file) in files.iter() {
+        for item in file.items.iter() {
+            match component(item, &names) {
+                Ok(Some((name, schema))) => {
+                    schemas.insert(name, schema);
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(format!("{}: {}", path, e)),
+            }
+        }
+    }
+    (schemas, errors)
+}
+
+fn method(
+    namespace: &str,
+    item: &syn::TraitItemFn,
+    components: &Components,
+) -> syn::Result<Option<Vec<Value>>> {
+    let attr = match item
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("method") || a.path().is_ident("subscription"))
+    {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let args = parse_attr(attr)?;
+    let name = args.name.ok_or_else(|| missing(attr, "name"))?;
+    let params: Vec<Value> = item
+        .sig
+        .inputs
+        .iter()
+        .filter_map(|arg| match arg {
+            FnArg::Typed(t) => match &*t.pat {
+                Pat::Ident(i) => Some(content_descriptor(&i.ident.to_string(), &t.ty, components)),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        })
+        .collect();
+    let description = docs(&item.attrs);
+    let mut method = Map::new();
+    method.insert("name".into(), json!(format!("{}_{}", namespace, name)));
+    if !description.is_empty() {
+        method.insert("description".into(), json!(description));
+    }
+    method.insert("params".into(), json!(params));
+    method.insert("paramStructure".into(), json!("either"));
+
+    if attr.path().is_ident("method") {
+        let result = match &item.sig.output {
+            ReturnType::Type(_, ty) => inner_type(ty, "RpcResult").unwrap_or(ty),
+            ReturnType::Default => return Ok(None),
+        };
+        method.insert(
+            "result".into(),
+            content_descriptor("result", result, components),
+        );
+        return Ok(Some(vec![Value::Object(method)]));
+    }
+
+    // A subscription returns its id, and sends the items as notifications.
+    let item_type = args.item.ok_or_else(|| missing(attr, "item"))?;
+    let unsubscribe = args
+        .unsubscribe
+        .ok_or_else(|| missing(attr, "unsubscribe"))?;
+    method.insert(
+        "result".into(),
+        json!({ "name": "subscription", "schema": { "type": "string" } }),
+    );
+    method.insert(
+        "x-notification".into(),
+        json!({
+            "method": format!("{}_{}", namespace, args.notification.unwrap_or(name)),
+            "item": content_descriptor("item", &item_type, components),
+        }),
+    );
+    let unsubscribe = json!({
+        "name": format!("{}_{}", namespace, unsubscribe),
+        "params": [{ "name": "subscription", "required": true, "schema": { "type": "string" } }],
+        "result": { "name": "result", "schema": { "type": "boolean" } },
+    });
+    Ok(Some(vec![Value::Object(method), unsubscribe]))
+}
+
+/// The documents of every `#[rpc]` trait in `source`, by namespace. Items that can not be read are
+/// left out and returned as errors.
+fn documents(source: &str, components: &Components) -> (BTreeMap<String, Vec<Value>>, Vec<String>) {
+    let mut methods: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    let mut errors = vec![];
+    let file = match syn::parse_file(source) {
+        Ok(file) => file,
+        Err(e) => return (methods, vec![format!("{}: {}", TRAITS, e)]),
+    };
+    for item in file.items {
+        let item = match item {
+            Item::Trait(t) => t,
+            _ => continue,
+        };
+        let attr = match item.attrs.iter().find(|a| a.path().is_ident("rpc")) {
+            Some(attr) => attr,
+            None => continue,
+        };
+        let namespace = match parse_attr(attr)
+            .and_then(|a| a.namespace.ok_or_else(|| missing(attr, "namespace")))
+        {
+            Ok(namespace) => namespace,
+            Err(e) => {
+                errors.push(format!("{}: trait {}: {}", TRAITS, item.ident, e));
+                continue;
+            }
+        };
+        for trait_item in item.items.iter() {
+            if let TraitItem::Fn(f) = trait_item {
+                match method(&namespace, f, components) {
+                    Ok(Some(m)) => methods.entry(namespace.clone()).or_default().extend(m),
+                    Ok(None) => {}
+                    Err(e) => errors.push(format!("{}: fn {}: {}", TRAITS, f.sig.ident, e)),
+                }
+            }
+        }
+    }
+    (methods, errors)
+}
+
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed={}", TRAITS);
+    let mut sources = vec![];
+    for path in TYPES {
+        println!("cargo:rerun-if-changed={}", path);
+        sources.push((*path, fs::read_to_string(path)?));
+    }
+    let out_dir = env::var("OUT_DIR").map_err(|e| Error::new(ErrorKind::NotFound, e))?;
+    let (schemas, mut errors) = components(&sources);
+    let names: Components = schemas.keys().cloned().collect();
+    let (methods, method_errors) = documents(&fs::read_to_string(TRAITS)?, &names);
+    errors.extend(method_errors);
+
+    // Unreadable items fail the build from `openrpc.rs`, instead of a panic here.
+    let mut compile_errors = String::new();
+    for e in errors.iter() {
+        println!("cargo:warning=openrpc: {}", e);
+        compile_errors.push_str(&format!(
+            "compile_error!({:?});\n",
+            format!("openrpc: {}", e)
+        ));
+    }
+    fs::write(
+        Path::new(&out_dir).join("openrpc_errors.rs"),
+        compile_errors,
+    )?;
+
+    for (namespace, methods) in methods {
+        let document = json!({
+            "openrpc": "1.2.6",
+            "info": {
+                "title": format!("submitter {} api", namespace),
+                "version": env::var("CARGO_PKG_VERSION").unwrap_or_default(),
+            },
+            "methods": methods,
+            "components": { "schemas": schemas },
+        });
+        fs::write(
+            Path::new(&out_dir).join(format!("{}.openrpc.json", namespace)),
+            serde_json::to_string_pretty(&document)?,
+        )?;
+    }
+    Ok(())
+}
@@ -1,5 +1,9 @@
 pub const STATE_ERROR_CODE: i32 = 666;
 pub const RWLOCK_READ_ERROR_CODE: i32 = 888;
 pub const ACCOUNT_NOT_EXISTS_CODE: i32 = 777;
+pub const BLOCK_NOT_ARCHIVED_CODE: i32 = 999;
 
 pub const ETH_DELAY_BLOCKS: u64 = 12;
+
+// Events a subscriber can fall behind by before it misses some.
+pub const SUBMITTER_EVENTS_CAPACITY: usize = 1024;
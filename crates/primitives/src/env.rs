@@ -47,6 +47,168 @@ pub fn get_lock_duration() -> u64 {
         .unwrap_or(240)
 }
 
+pub fn get_reconcile_horizon_blocks() -> u64 {
+    std::env::var("RECONCILE_HORIZON_BLOCKS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(7200)
+}
+
+pub fn get_reconcile_interval_seconds() -> u64 {
+    std::env::var("RECONCILE_INTERVAL_SECONDS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(1800)
+}
+
+pub fn get_support_set_refresh_seconds() -> u64 {
+    std::env::var("SUPPORT_SET_REFRESH_SECONDS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(600)
+}
+
+pub fn get_support_set_epoch_blocks() -> u64 {
+    std::env::var("SUPPORT_SET_EPOCH_BLOCKS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(7200)
+}
+
+pub fn get_support_set_genesis_chains() -> Vec<String> {
+    std::env::var("SUPPORT_SET_GENESIS_CHAINS")
+        .unwrap_or("".to_string())
+        .split(";")
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+pub fn get_support_set_genesis_tokens() -> Vec<String> {
+    std::env::var("SUPPORT_SET_GENESIS_TOKENS")
+        .unwrap_or("".to_string())
+        .split(";")
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+pub fn get_checkpoint_retain() -> usize {
+    std::env::var("CHECKPOINT_RETAIN")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(5)
+}
+
+/// Txs of blocks covered by this many newer submitted roots are pruned, 0 keeps every tx.
+pub fn get_txs_retain_roots() -> u64 {
+    std::env::var("TXS_RETAIN_ROOTS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(0)
+}
+
+pub fn get_txs_prune_interval_seconds() -> u64 {
+    std::env::var("TXS_PRUNE_INTERVAL_SECONDS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(3600)
+}
+
+pub fn get_txs_prune_compact_bytes() -> u64 {
+    std::env::var("TXS_PRUNE_COMPACT_BYTES")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(64 * 1024 * 1024)
+}
+
+pub fn get_rpc_bind_address() -> String {
+    std::env::var("RPC_BIND_ADDRESS").unwrap_or("127.0.0.1".to_string())
+}
+
+/// Origins allowed to call the rpc from a browser, `*` allows any. No CORS headers if unset.
+pub fn get_rpc_cors_origins() -> Vec<String> {
+    std::env::var("RPC_CORS_ORIGINS")
+        .unwrap_or("".to_string())
+        .split(";")
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+pub fn get_rpc_max_request_bytes() -> u32 {
+    std::env::var("RPC_MAX_REQUEST_BYTES")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(10 * 1024 * 1024)
+}
+
+pub fn get_rpc_max_response_bytes() -> u32 {
+    std::env::var("RPC_MAX_RESPONSE_BYTES")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(10 * 1024 * 1024)
+}
+
+pub fn get_rpc_max_connections() -> u32 {
+    std::env::var("RPC_MAX_CONNECTIONS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(100)
+}
+
+/// Serve the REST gateway under `/v1/` on the rpc port.
+pub fn get_rpc_rest_enabled() -> bool {
+    std::env::var("RPC_REST_ENABLED")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(false)
+}
+
+pub fn get_rpc_batch_requests() -> bool {
+    std::env::var("RPC_BATCH_REQUESTS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(true)
+}
+
+/// Requests per second of each IP, 0 is unlimited.
+pub fn get_rpc_rate_limit_per_second() -> u64 {
+    std::env::var("RPC_RATE_LIMIT_PER_SECOND")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(0)
+}
+
+pub fn get_rpc_rate_limit_burst() -> u64 {
+    std::env::var("RPC_RATE_LIMIT_BURST")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(get_rpc_rate_limit_per_second())
+}
+
+/// PEM certificate chain and PKCS#8 key, TLS is terminated by the submitter if both are set.
+pub fn get_rpc_tls_cert_and_key() -> Option<(String, String)> {
+    match (
+        std::env::var("RPC_TLS_CERT_PATH"),
+        std::env::var("RPC_TLS_KEY_PATH"),
+    ) {
+        (Ok(cert), Ok(key)) if !cert.is_empty() && !key.is_empty() => Some((cert, key)),
+        _ => None,
+    }
+}
+
+pub fn get_debug_rpc_bind_address() -> String {
+    std::env::var("DEBUG_RPC_BIND_ADDRESS").unwrap_or("127.0.0.1".to_string())
+}
+
+/// File holding the bearer token of the debug rpc.
+pub fn get_debug_rpc_token_file() -> Option<String> {
+    std::env::var("DEBUG_RPC_TOKEN_FILE")
+        .ok()
+        .filter(|f| !f.is_empty())
+}
+
 pub fn get_fee_manager_contract_address() -> Address {
     std::env::var("ORFeeManager_CONTRACT_ADDRESS")
         .unwrap()
@@ -58,6 +220,20 @@ pub fn get_txs_source_url() -> String {
     std::env::var("TXS_SOURCE_URL").expect("TXS_SOURCE_URL is not set")
 }
 
+pub fn get_txs_request_window_seconds() -> u64 {
+    std::env::var("TXS_REQUEST_WINDOW_SECONDS")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(3600)
+}
+
+pub fn get_txs_response_max_bytes() -> usize {
+    std::env::var("TXS_RESPONSE_MAX_BYTES")
+        .unwrap_or("".to_string())
+        .parse()
+        .unwrap_or(16 * 1024 * 1024)
+}
+
 pub fn get_chains_info_source_url() -> String {
     std::env::var("SUPPORT_CHAINS_SOURCE_URL").expect("SUPPORT_CHAINS_SOURCE_URL is not set")
 }
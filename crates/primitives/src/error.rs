@@ -1,6 +1,6 @@
 use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
-use crate::types::BlockInfo;
+use crate::{retry::CircuitOpen, types::BlockInfo};
 use ethers::prelude::MulticallError;
 use ethers::types::U64;
 use ethers::{
@@ -20,6 +20,8 @@ pub enum Error {
     RocksDBError(#[from] rocksdb::Error),
     #[error("sparse-merkle-tree errors")]
     SparseMerkleTreeError(#[from] sparse_merkle_tree::error::Error),
+    #[error("io err")]
+    IoError(#[from] std::io::Error),
     #[error("sled db err")]
     SledDBError(#[from] sled::Error),
     #[error("tokio broadcast send err")]
@@ -36,6 +38,12 @@ pub enum Error {
     ETHContractError(#[from] ContractError<Provider<ethers_providers::Http>>),
     #[error("submit root failed")]
     SubmitRootFailed(String, Option<U64>),
+    #[error("circuit breaker is open")]
+    CircuitOpen(#[from] CircuitOpen),
+    #[error("db schema version {0} is not supported, this build supports up to {1}")]
+    IncompatibleSchema(u32, u32),
+    #[error("ethers abi err")]
+    ETHAbiError(#[from] ethers::abi::Error),
     #[error("ethers multicall err")]
     ETHMulticallError(#[from] MulticallError<ethers_providers::Provider<ethers_providers::Http>>),
 }
@@ -4,6 +4,9 @@ pub mod env;
 pub mod error;
 pub mod func;
 pub mod keccak256_hasher;
+pub mod openrpc;
+pub mod retry;
+pub mod storage;
 mod tests;
 pub mod traits;
 pub mod types;
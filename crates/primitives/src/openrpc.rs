@@ -0,0 +1,11 @@
+//! OpenRPC documents of the rpc traits, generated by `build.rs` from `traits.rs`, with the
+//! schemas of the types in `types.rs`. The server answers `rpc.discover` with them.
+
+// Errors `build.rs` met while reading `traits.rs` and the types, as `compile_error!`s.
+include!(concat!(env!("OUT_DIR"), "/openrpc_errors.rs"));
+
+/// Methods and subscriptions of the `submitter` namespace.
+pub const SUBMITTER_OPENRPC: &str =
+    include_str!(concat!(env!("OUT_DIR"), "/submitter.openrpc.json"));
+/// Methods of the `debug` namespace.
+pub const DEBUG_OPENRPC: &str = include_str!(concat!(env!("OUT_DIR"), "/debug.openrpc.json"));
@@ -0,0 +1,380 @@
+//! Retry with exponential backoff and jitter, and a circuit breaker per external endpoint.
+//!
+//! Every call made through `retry` is counted per endpoint, the counters are available through
+//! `retry_metrics` and state changes of the circuit are logged.
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{event, Level};
+
+pub const TXS_SOURCE_ENDPOINT: &str = "txs-source";
+pub const SUPPORT_CHAINS_SOURCE_ENDPOINT: &str = "support-chains-source";
+pub const MAINNET_RPC_ENDPOINT: &str = "mainnet-rpc";
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Consecutive failures that open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects calls before one trial call is let through. A trial call
+    /// that has not finished after as long is given up, and another one is let through.
+    pub open_duration: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        let env = |key: &str, default: u64| -> u64 {
+            std::env::var(key)
+                .unwrap_or("".to_string())
+                .parse()
+                .unwrap_or(default)
+        };
+        Self {
+            max_attempts: env("RETRY_MAX_ATTEMPTS", 5) as u32,
+            base_delay: Duration::from_millis(env("RETRY_BASE_DELAY_MS", 500)),
+            max_delay: Duration::from_millis(env("RETRY_MAX_DELAY_MS", 30_000)),
+            failure_threshold: env("CIRCUIT_FAILURE_THRESHOLD", 10) as u32,
+            open_duration: Duration::from_secs(env("CIRCUIT_OPEN_SECONDS", 60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the `attempt`th failure (starting at 1), with jitter in [d/2, d].
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let delay = std::cmp::min(exp, self.max_delay).as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(delay / 2..=delay))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum CircuitState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct EndpointMetrics {
+    pub endpoint: String,
+    pub state: CircuitState,
+    pub attempts: u64,
+    pub successes: u64,
+    pub failures: u64,
+    // Calls refused while the circuit was open.
+    pub rejected: u64,
+    pub consecutive_failures: u32,
+}
+
+/// Returned without calling the endpoint while its circuit is open.
+#[derive(Debug, Clone)]
+pub struct CircuitOpen(pub String);
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "circuit breaker of {} is open", self.0)
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+struct Breaker {
+    metrics: EndpointMetrics,
+    opened_at: Option<Instant>,
+    // When the trial call of the half-open circuit started, if it has not finished.
+    probe_started_at: Option<Instant>,
+}
+
+lazy_static! {
+    static ref BREAKERS: Mutex<BTreeMap<String, Breaker>> = Mutex::new(BTreeMap::new());
+}
+
+fn with_breaker<R>(endpoint: &str, f: impl FnOnce(&mut Breaker) -> R) -> R {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers
+        .entry(endpoint.to_string())
+        .or_insert_with(|| Breaker {
+            metrics: EndpointMetrics {
+                endpoint: endpoint.to_string(),
+                ..Default::default()
+            },
+            opened_at: None,
+            probe_started_at: None,
+        });
+    f(breaker)
+}
+
+fn set_state(breaker: &mut Breaker, state: CircuitState) {
+    if breaker.metrics.state != state {
+        event!(
+            Level::WARN,
+            "circuit breaker of {} changed from {:?} to {:?}. metrics: {:?}",
+            breaker.metrics.endpoint,
+            breaker.metrics.state,
+            state,
+            breaker.metrics,
+        );
+        breaker.metrics.state = state;
+    }
+}
+
+fn before_call(endpoint: &str, policy: &RetryPolicy) -> Result<(), CircuitOpen> {
+    with_breaker(endpoint, |breaker| {
+        let reject = match breaker.metrics.state {
+            CircuitState::Closed => false,
+            CircuitState::Open => {
+                let elapsed = breaker.opened_at.map(|t| t.elapsed()).unwrap_or_default();
+                elapsed < policy.open_duration
+            }
+            // Only one trial call at a time.
+            CircuitState::HalfOpen => breaker
+                .probe_started_at
+                .is_some_and(|t| t.elapsed() < policy.open_duration),
+        };
+        if reject {
+            breaker.metrics.rejected += 1;
+            return Err(CircuitOpen(endpoint.to_string()));
+        }
+        if breaker.metrics.state != CircuitState::Closed {
+            set_state(breaker, CircuitState::HalfOpen);
+            breaker.probe_started_at = Some(Instant::now());
+        }
+        breaker.metrics.attempts += 1;
+        Ok(())
+    })
+}
+
+/// A call that failed with an error that does not count against the circuit.
+fn on_skipped(endpoint: &str) {
+    with_breaker(endpoint, |breaker| breaker.probe_started_at = None)
+}
+
+fn on_success(endpoint: &str) {
+    with_breaker(endpoint, |breaker| {
+        breaker.metrics.successes += 1;
+        breaker.metrics.consecutive_failures = 0;
+        breaker.opened_at = None;
+        breaker.probe_started_at = None;
+        set_state(breaker, CircuitState::Closed);
+    })
+}
+
+/// Returns whether the circuit is open after this failure.
+fn on_failure(endpoint: &str, policy: &RetryPolicy) -> bool {
+    with_breaker(endpoint, |breaker| {
+        breaker.metrics.failures += 1;
+        breaker.metrics.consecutive_failures += 1;
+        breaker.probe_started_at = None;
+        if breaker.metrics.state == CircuitState::HalfOpen
+            || breaker.metrics.consecutive_failures >= policy.failure_threshold
+        {
+            breaker.opened_at = Some(Instant::now());
+            set_state(breaker, CircuitState::Open);
+            return true;
+        }
+        false
+    })
+}
+
+/// Snapshot of the counters of every endpoint called so far.
+pub fn retry_metrics() -> Vec<EndpointMetrics> {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|b| b.metrics.clone())
+        .collect()
+}
+
+/// Call `f` until it succeeds, the attempts are used up or the circuit opens.
+pub async fn retry<T, E, F, Fut>(endpoint: &str, policy: &RetryPolicy, f: F) -> Result<T, E>
+where
+    E: From<CircuitOpen> + Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(endpoint, policy, f, |_| true).await
+}
+
+/// Same as `retry`, but errors for which `should_retry` is false are returned immediately and
+/// do not count against the circuit.
+pub async fn retry_if<T, E, F, Fut, P>(
+    endpoint: &str,
+    policy: &RetryPolicy,
+    mut f: F,
+    should_retry: P,
+) -> Result<T, E>
+where
+    E: From<CircuitOpen> + Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: Fn(&E) -> bool,
+{
+    let mut attempt = 0u32;
+    loop {
+        before_call(endpoint, policy)?;
+        attempt += 1;
+        let err = match f().await {
+            Ok(v) => {
+                on_success(endpoint);
+                return Ok(v);
+            }
+            Err(err) => err,
+        };
+        if !should_retry(&err) {
+            on_skipped(endpoint);
+            return Err(err);
+        }
+        let is_open = on_failure(endpoint, policy);
+        if is_open || attempt >= policy.max_attempts {
+            event!(
+                Level::WARN,
+                "{} failed after {} attempts: {:?}",
+                endpoint,
+                attempt,
+                err
+            );
+            return Err(err);
+        }
+        let delay = policy.backoff(attempt);
+        event!(
+            Level::WARN,
+            "{} attempt {} failed: {:?}, retry in {:?}",
+            endpoint,
+            attempt,
+            err,
+            delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum TestError {
+        Down,
+        Open,
+    }
+
+    impl From<CircuitOpen> for TestError {
+        fn from(_: CircuitOpen) -> Self {
+            TestError::Open
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            failure_threshold: 3,
+            open_duration: Duration::from_secs(60),
+        };
+        for (attempt, max) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (30, 1000),
+        ] {
+            let delay = policy.backoff(attempt).as_millis() as u64;
+            assert!(delay >= max / 2 && delay <= max, "attempt {}", attempt);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_opens() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            failure_threshold: 3,
+            open_duration: Duration::from_secs(60),
+        };
+        let mut calls = 0;
+        let res: Result<(), TestError> = retry("test-endpoint", &policy, || {
+            calls += 1;
+            async { Err(TestError::Down) }
+        })
+        .await;
+        assert_eq!(res, Err(TestError::Down));
+        assert_eq!(calls, 3);
+
+        // Open circuit rejects without calling.
+        let res: Result<(), TestError> = retry("test-endpoint", &policy, || async { Ok(()) }).await;
+        assert_eq!(res, Err(TestError::Open));
+
+        let metrics = retry_metrics()
+            .into_iter()
+            .find(|m| m.endpoint == "test-endpoint")
+            .unwrap();
+        assert_eq!(metrics.state, CircuitState::Open);
+        assert_eq!(metrics.failures, 3);
+        assert_eq!(metrics.rejected, 1);
+    }
+
+    #[test]
+    fn test_half_open_single_probe() {
+        let policy = RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            failure_threshold: 1,
+            open_duration: Duration::from_millis(50),
+        };
+        let endpoint = "test-half-open";
+        let state = || {
+            retry_metrics()
+                .into_iter()
+                .find(|m| m.endpoint == endpoint)
+                .unwrap()
+                .state
+        };
+        assert!(before_call(endpoint, &policy).is_ok());
+        assert!(on_failure(endpoint, &policy));
+        assert!(before_call(endpoint, &policy).is_err());
+
+        // One trial call once the circuit has been open long enough, the others are rejected.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(before_call(endpoint, &policy).is_ok());
+        assert_eq!(state(), CircuitState::HalfOpen);
+        assert!(before_call(endpoint, &policy).is_err());
+
+        // A failed trial opens the circuit again.
+        assert!(on_failure(endpoint, &policy));
+        assert_eq!(state(), CircuitState::Open);
+        assert!(before_call(endpoint, &policy).is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(before_call(endpoint, &policy).is_ok());
+        // A trial that does not count lets the next call be the trial.
+        on_skipped(endpoint);
+        assert!(before_call(endpoint, &policy).is_ok());
+        assert!(before_call(endpoint, &policy).is_err());
+
+        // A successful trial closes it.
+        on_success(endpoint);
+        assert_eq!(state(), CircuitState::Closed);
+        assert!(before_call(endpoint, &policy).is_ok());
+        assert!(before_call(endpoint, &policy).is_ok());
+    }
+}
@@ -0,0 +1,430 @@
+//! The single RocksDB that holds every piece of the submitter's data, so writes to the state
+//! trees, the txs and the key-value trees can share one transaction and the whole db path can be
+//! backed up at once.
+//!
+//! Column families:
+//! - `default`: the state trees, each under its own key prefix (see `State`).
+//! - `txs`: the crawled txs with their profits, ordered by `tx_compare`.
+//! - `kv`: the key-value trees of the `sled_db` wrappers, each under the prefix `name/`.
+
+use crate::{error::Result, func::tx_compare};
+use rocksdb::{
+    ops::{CompactRangeCF, CreateCheckpointObject},
+    prelude::*,
+    ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, OptimisticTransaction,
+    OptimisticTransactionDB, Options,
+};
+use std::{
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+pub const TXS_CF: &str = "txs";
+pub const KV_CF: &str = "kv";
+
+pub const PROFIT_STATE_PREFIX: &[u8] = b"profit";
+pub const BLOCKS_STATE_PREFIX: &[u8] = b"blocks";
+
+// Times a transaction is run before a write conflict is returned.
+const MAX_COMMIT_ATTEMPTS: usize = 5;
+
+/// Whether `e` is a commit that lost to a concurrent write of the same keys, and can be run again.
+pub fn is_write_conflict(e: &rocksdb::Error) -> bool {
+    let message: &str = e.as_ref();
+    message.starts_with("Resource busy") || message.starts_with("Operation failed. Try again")
+}
+
+pub struct Storage {
+    db: OptimisticTransactionDB,
+    path: String,
+}
+
+impl Storage {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let mut txs_opts = Options::default();
+        txs_opts.set_comparator("custom", tx_compare);
+        let db = OptimisticTransactionDB::open_cf_descriptors(
+            &opts,
+            path,
+            vec![
+                ColumnFamilyDescriptor::new(TXS_CF, txs_opts),
+                ColumnFamilyDescriptor::new(KV_CF, Options::default()),
+            ],
+        )?;
+        Ok(Self {
+            db,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn db(&self) -> &OptimisticTransactionDB {
+        &self.db
+    }
+
+    pub fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db
+            .cf_handle(name)
+            .expect("every column family is opened with the storage")
+    }
+
+    /// Writes through the transaction are visible to reads through it, and to everyone else once
+    /// it is committed.
+    pub fn transaction(&self) -> OptimisticTransaction {
+        self.db.transaction_default()
+    }
+
+    /// Run `f` in a transaction and commit it. If another task wrote the same keys in the
+    /// meantime, `f` is run again in a new transaction, so `f` must only write through it.
+    pub fn transact<T, E, F>(&self, mut f: F) -> std::result::Result<T, E>
+    where
+        E: From<rocksdb::Error>,
+        F: FnMut(&OptimisticTransaction) -> std::result::Result<T, E>,
+    {
+        let mut attempt = 1;
+        loop {
+            let tx = self.transaction();
+            let value = f(&tx)?;
+            match tx.commit() {
+                Ok(()) => return Ok(value),
+                Err(e) if attempt < MAX_COMMIT_ATTEMPTS && is_write_conflict(&e) => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn tree(self: &Arc<Self>, name: &str) -> Tree {
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(b'/');
+        Tree {
+            storage: self.clone(),
+            prefix,
+        }
+    }
+
+    /// Whether nothing but the trees in `except` has been written yet.
+    pub fn is_empty_except(self: &Arc<Self>, except: &[&str]) -> Result<bool> {
+        if self.db.iterator(IteratorMode::Start).next().is_some()
+            || self
+                .db
+                .iterator_cf(self.cf(TXS_CF), IteratorMode::Start)?
+                .next()
+                .is_some()
+        {
+            return Ok(false);
+        }
+        let except: Vec<Vec<u8>> = except.iter().map(|name| self.tree(name).prefix).collect();
+        for (k, _) in self.db.iterator_cf(self.cf(KV_CF), IteratorMode::Start)? {
+            if !except.iter().any(|p| k.starts_with(p)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Compact the whole column family `name`, so the space of removed keys is given back.
+    pub fn compact(&self, name: &str) -> Result<()> {
+        self.db
+            .compact_range_cf(self.cf(name), None::<&[u8]>, None::<&[u8]>)?;
+        Ok(())
+    }
+
+    /// Bytes taken by the storage's files.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        let mut size = 0;
+        for entry in std::fs::read_dir(&self.path)? {
+            size += entry?.metadata()?.len();
+        }
+        Ok(size)
+    }
+
+    /// Write a consistent copy of the storage to `path`, which must not exist yet. The copy
+    /// hard-links the sst files, so it is cheap as long as `path` is on the same filesystem.
+    pub fn checkpoint(&self, path: &str) -> Result<()> {
+        self.flush()?;
+        self.db
+            .create_checkpoint_object()?
+            .create_checkpoint(path)?;
+        Ok(())
+    }
+}
+
+/// A key-value tree in the `kv` column family, with the subset of the sled `Tree` api the
+/// wrappers use. Each method has an `_in` variant that goes through a transaction.
+#[derive(Clone)]
+pub struct Tree {
+    storage: Arc<Storage>,
+    prefix: Vec<u8>,
+}
+
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+impl Tree {
+    fn key<K: AsRef<[u8]>>(&self, k: K) -> Vec<u8> {
+        let mut key = self.prefix.clone();
+        key.extend_from_slice(k.as_ref());
+        key
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.storage.cf(KV_CF)
+    }
+
+    pub fn storage(&self) -> &Arc<Storage> {
+        &self.storage
+    }
+
+    pub fn get<K: AsRef<[u8]>>(&self, k: K) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .storage
+            .db
+            .get_cf(self.cf(), self.key(k))?
+            .map(|v| v.to_vec()))
+    }
+
+    pub fn get_in<K: AsRef<[u8]>>(
+        &self,
+        tx: &OptimisticTransaction,
+        k: K,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(tx.get_cf(self.cf(), self.key(k))?.map(|v| v.to_vec()))
+    }
+
+    pub fn contains_key<K: AsRef<[u8]>>(&self, k: K) -> Result<bool> {
+        Ok(self.get(k)?.is_some())
+    }
+
+    pub fn insert<K: AsRef<[u8]>, V: AsRef<[u8]>>(&self, k: K, v: V) -> Result<()> {
+        self.storage.db.put_cf(self.cf(), self.key(k), v)?;
+        Ok(())
+    }
+
+    pub fn insert_in<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &self,
+        tx: &OptimisticTransaction,
+        k: K,
+        v: V,
+    ) -> Result<()> {
+        tx.put_cf(self.cf(), self.key(k), v)?;
+        Ok(())
+    }
+
+    pub fn remove<K: AsRef<[u8]>>(&self, k: K) -> Result<()> {
+        self.storage.db.delete_cf(self.cf(), self.key(k))?;
+        Ok(())
+    }
+
+    pub fn remove_in<K: AsRef<[u8]>>(&self, tx: &OptimisticTransaction, k: K) -> Result<()> {
+        tx.delete_cf(self.cf(), self.key(k))?;
+        Ok(())
+    }
+
+    // Keys from `from` on in `direction`, while they are in the tree, without the tree prefix.
+    fn scan(&self, from: Vec<u8>, direction: Direction) -> KvIter<'_> {
+        let prefix_len = self.prefix.len();
+        match self
+            .storage
+            .db
+            .iterator_cf(self.cf(), IteratorMode::From(&from, direction))
+        {
+            Ok(iter) => Box::new(
+                iter.take_while(move |(k, _)| k.starts_with(&self.prefix))
+                    .map(move |(k, v)| Ok((k[prefix_len..].to_vec(), v.to_vec()))),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e.into()))),
+        }
+    }
+
+    // A key after every key of the tree, and before the keys of the following trees.
+    fn end(&self) -> Vec<u8> {
+        let mut end = self.prefix.clone();
+        *end.last_mut().expect("the prefix ends with '/'") += 1;
+        end
+    }
+
+    /// All entries in key order.
+    pub fn iter(&self) -> KvIter<'_> {
+        self.scan(self.prefix.clone(), Direction::Forward)
+    }
+
+    /// Entries within `range` in key order.
+    pub fn range<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> KvIter<'_> {
+        let (start, skip) = match range.start_bound() {
+            Bound::Included(k) => (self.key(k), None),
+            Bound::Excluded(k) => (self.key(k), Some(self.key(k))),
+            Bound::Unbounded => (self.prefix.clone(), None),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let skip = skip.map(|k| k[self.prefix.len()..].to_vec());
+        Box::new(
+            self.scan(start, Direction::Forward)
+                .filter(move |item| match (item, &skip) {
+                    (Ok((k, _)), Some(skip)) => k != skip,
+                    _ => true,
+                })
+                .take_while(move |item| match (item, &end) {
+                    (Ok((k, _)), Bound::Included(end)) => k <= end,
+                    (Ok((k, _)), Bound::Excluded(end)) => k < end,
+                    _ => true,
+                }),
+        )
+    }
+
+    /// Entries within `range` in reverse key order.
+    pub fn range_rev<K: AsRef<[u8]>, R: RangeBounds<K>>(&self, range: R) -> KvIter<'_> {
+        let (end, skip) = match range.end_bound() {
+            Bound::Included(k) => (self.key(k), None),
+            Bound::Excluded(k) => (self.key(k), Some(self.key(k))),
+            Bound::Unbounded => (self.end(), None),
+        };
+        let start = match range.start_bound() {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let skip = skip.map(|k| k[self.prefix.len()..].to_vec());
+        let prefix_len = self.prefix.len();
+        let iter: KvIter<'_> = match self
+            .storage
+            .db
+            .iterator_cf(self.cf(), IteratorMode::From(&end, Direction::Reverse))
+        {
+            Ok(iter) => Box::new(
+                iter.take_while(move |(k, _)| k.starts_with(&self.prefix))
+                    .map(move |(k, v)| Ok((k[prefix_len..].to_vec(), v.to_vec()))),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e.into()))),
+        };
+        Box::new(
+            iter.filter(move |item| match (item, &skip) {
+                (Ok((k, _)), Some(skip)) => k != skip,
+                _ => true,
+            })
+            .take_while(move |item| match (item, &start) {
+                (Ok((k, _)), Bound::Included(start)) => k >= start,
+                (Ok((k, _)), Bound::Excluded(start)) => k > start,
+                _ => true,
+            }),
+        )
+    }
+
+    /// Entries whose key starts with `prefix`, in key order.
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> KvIter<'_> {
+        let prefix = prefix.as_ref().to_vec();
+        Box::new(self.scan(self.key(&prefix), Direction::Forward).take_while(
+            move |item| match item {
+                Ok((k, _)) => k.starts_with(&prefix),
+                Err(_) => true,
+            },
+        ))
+    }
+
+    pub fn first(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.iter().next().transpose()
+    }
+
+    pub fn last(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.range_rev::<&[u8], _>(..).next().transpose()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let tx = self.storage.transaction();
+        for item in self.iter() {
+            self.remove_in(&tx, item?.0)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.storage.flush()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_tree_range() {
+        let path = "./db_storage_test";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let tree = storage.tree("numbers");
+        let other = storage.tree("numbers-other");
+        for i in 1u64..=5 {
+            tree.insert(i.to_be_bytes(), i.to_be_bytes()).unwrap();
+            other.insert(i.to_be_bytes(), vec![]).unwrap();
+        }
+        let keys = |iter: KvIter<'_>| -> Vec<u64> {
+            iter.map(|item| u64::from_be_bytes(item.unwrap().0.try_into().unwrap()))
+                .collect()
+        };
+
+        assert_eq!(keys(tree.iter()), vec![1, 2, 3, 4, 5]);
+        assert_eq!(
+            keys(tree.range(2u64.to_be_bytes()..4u64.to_be_bytes())),
+            vec![2, 3]
+        );
+        assert_eq!(keys(tree.range(..=2u64.to_be_bytes())), vec![1, 2]);
+        assert_eq!(keys(tree.range_rev(..=3u64.to_be_bytes())), vec![3, 2, 1]);
+        assert_eq!(
+            keys(tree.range_rev(2u64.to_be_bytes()..4u64.to_be_bytes())),
+            vec![3, 2]
+        );
+        assert_eq!(
+            tree.last().unwrap().map(|(k, _)| k),
+            Some(5u64.to_be_bytes().to_vec())
+        );
+        assert_eq!(tree.len(), 5);
+
+        // Writes through a transaction are only visible once committed.
+        let tx = storage.transaction();
+        tree.insert_in(&tx, 6u64.to_be_bytes(), vec![]).unwrap();
+        assert!(tree.get_in(&tx, 6u64.to_be_bytes()).unwrap().is_some());
+        assert!(tree.get(6u64.to_be_bytes()).unwrap().is_none());
+        tx.commit().unwrap();
+        assert!(tree.contains_key(6u64.to_be_bytes()).unwrap());
+
+        // A commit that lost to a write of the same key is run again.
+        let mut attempts = 0;
+        storage
+            .transact(|tx| -> Result<()> {
+                attempts += 1;
+                tree.insert_in(tx, 7u64.to_be_bytes(), vec![1])?;
+                if attempts == 1 {
+                    tree.insert(7u64.to_be_bytes(), vec![2])?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert_eq!(tree.get(7u64.to_be_bytes()).unwrap(), Some(vec![1]));
+
+        tree.clear().unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(other.len(), 5);
+        drop((tree, other));
+        drop(storage);
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
@@ -18,3 +18,34 @@ fn main() {
     // hasher.write_h256(&H256::from([0; 32]));
     println!("hash: {:?}", hex::encode(hasher.finish().as_slice()));
 }
+
+#[test]
+fn test_openrpc() {
+    let document: serde_json::Value =
+        serde_json::from_str(crate::openrpc::SUBMITTER_OPENRPC).unwrap();
+    let methods = document["methods"].as_array().unwrap();
+    assert!(methods.iter().any(|m| m["name"] == "submitter_getRoot"));
+    assert!(methods
+        .iter()
+        .any(|m| m["name"] == "submitter_unsubscribeBalance"));
+    let audit = methods
+        .iter()
+        .find(|m| m["name"] == "submitter_getTxAudit")
+        .unwrap();
+    assert_eq!(
+        audit["result"]["schema"]["$ref"],
+        "#/components/schemas/TxAudit"
+    );
+    let schemas = &document["components"]["schemas"];
+    assert_eq!(
+        schemas["TxAudit"]["properties"]["archive_status"]["$ref"],
+        "#/components/schemas/TxArchiveStatus"
+    );
+    assert_eq!(schemas["CrossTxData"]["required"][0], "dealerAddress");
+    let debug: serde_json::Value = serde_json::from_str(crate::openrpc::DEBUG_OPENRPC).unwrap();
+    assert!(debug["methods"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|m| m["name"].as_str().unwrap().starts_with("debug_")));
+}
@@ -1,17 +1,23 @@
+use crate::retry::EndpointMetrics;
 use crate::types::{
-    BlockInfo, BlockStorage, BlocksStateData, CrossTxProfit, Event, ProfitProof, ProfitStateData,
-    ProfitStateDataForRpc,
+    AddressTxStats, ArchivedBlock, BalanceChange, BalanceFilter, BalancePage, BlockInfo,
+    BlockStorage, BlocksStateData, ChainTxStats, CheckpointInfo, CrossTxProfit, Event, MissedTx,
+    NewRoot, ProfitAt, ProfitProof, ProfitProofs, ProfitStateData, ProfitStateDataForRpc,
+    ProfitStateDiff, SupportSet, TxAudit, TxCursor, TxPage, TxWithProfit, WithdrawCall,
 };
 use async_trait::async_trait;
-use ethers::types::Address;
 use ethers::types::U64;
-use jsonrpsee::{core::RpcResult, proc_macros::rpc};
+use ethers::types::{Address, U256};
+use jsonrpsee::{
+    core::{RpcResult, SubscriptionResult},
+    proc_macros::rpc,
+};
 use sparse_merkle_tree::{merge::MergeValue, H256};
 
 // local
 use super::error::Result;
 
-#[rpc(server, namespace = "debug")]
+#[rpc(server, client, namespace = "debug")]
 pub trait DebugApi {
     #[method(name = "clearState")]
     async fn clear_state(&self) -> RpcResult<()>;
@@ -19,10 +25,14 @@ pub trait DebugApi {
     async fn update_profit(&self, user: Address, profit: ProfitStateData) -> RpcResult<H256>;
     #[method(name = "updateProfitByCount")]
     async fn update_profit_by_count(&self, count: u64) -> RpcResult<H256>;
+    #[method(name = "createCheckpoint")]
+    async fn create_checkpoint(&self) -> RpcResult<CheckpointInfo>;
+    #[method(name = "listCheckpoints")]
+    async fn list_checkpoints(&self) -> RpcResult<Vec<CheckpointInfo>>;
 }
 
 // The rpc interface provided to the user externally.
-#[rpc(server, namespace = "submitter")]
+#[rpc(server, client, namespace = "submitter")]
 pub trait SubmitterApi {
     #[method(name = "getProfitInfo")]
     async fn get_profit_info(
@@ -33,8 +43,21 @@ pub trait SubmitterApi {
 
     #[method(name = "getAllProfitInfo")]
     async fn get_all_profit_info(&self, address: Address) -> RpcResult<Vec<ProfitStateDataForRpc>>;
+    /// Balances and debts of `user` for `tokens` as of the archived block `block_num`, e.g. the
+    /// last block of a submitted root.
+    #[method(name = "getProfitInfoAt")]
+    async fn get_profit_info_at(
+        &self,
+        user: Address,
+        tokens: Vec<(u64, Address)>,
+        block_num: u64,
+    ) -> RpcResult<ProfitAt>;
     #[method(name = "getProfitByTxHash")]
     async fn get_profit_by_tx_hash(&self, tx_hash: H256) -> RpcResult<Option<CrossTxProfit>>;
+    /// The lifecycle of the tx with the target tx hash `tx_hash`: its source record, target block,
+    /// fee ratio, profit, whether it was skipped and why, and the submitted root including it.
+    #[method(name = "getTxAudit")]
+    async fn get_tx_audit(&self, tx_hash: H256) -> RpcResult<TxAudit>;
     #[method(name = "getRoot")]
     async fn get_root(&self) -> RpcResult<String>;
     #[method(name = "getProfitProof")]
@@ -43,6 +66,24 @@ pub trait SubmitterApi {
         user: Address,
         tokens: Vec<(u64, Address)>,
     ) -> RpcResult<Vec<ProfitProof>>;
+    /// Proofs of the `(chain_id, token, address)` leaves against one root, or with `compiled` one
+    /// multiproof of all of them.
+    #[method(name = "getProfitProofs")]
+    async fn get_profit_proofs(
+        &self,
+        keys: Vec<(u64, Address, Address)>,
+        compiled: Option<bool>,
+    ) -> RpcResult<ProfitProofs>;
+    /// A ready-to-sign withdraw of `address` for the `(chain_id, token)` leaves against the last
+    /// submitted root, simulated with `eth_call`. The whole balances are withdrawn unless
+    /// `amounts` is given.
+    #[method(name = "getWithdrawCall")]
+    async fn get_withdraw_call(
+        &self,
+        address: Address,
+        tokens: Vec<(u64, Address)>,
+        amounts: Option<Vec<U256>>,
+    ) -> RpcResult<WithdrawCall>;
     #[method(name = "verify")]
     async fn verify(
         &self,
@@ -53,6 +94,119 @@ pub trait SubmitterApi {
     ) -> RpcResult<bool>;
     #[method(name = "getProfitRootByBlockNum")]
     async fn get_profit_root_by_block_num(&self, block_num: u64) -> RpcResult<BlocksStateData>;
+    #[method(name = "getMissedTxs")]
+    async fn get_missed_txs(&self) -> RpcResult<Vec<MissedTx>>;
+    #[method(name = "getRetryMetrics")]
+    async fn get_retry_metrics(&self) -> RpcResult<Vec<EndpointMetrics>>;
+    #[method(name = "getSupportSetHistory")]
+    async fn get_support_set_history(&self) -> RpcResult<Vec<SupportSet>>;
+    #[method(name = "getStateDiff")]
+    async fn get_state_diff(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> RpcResult<Vec<ProfitStateDiff>>;
+    #[method(name = "listBalances")]
+    async fn list_balances(
+        &self,
+        cursor: Option<H256>,
+        limit: u64,
+        filter: Option<BalanceFilter>,
+    ) -> RpcResult<BalancePage>;
+    /// Txs crediting `dealer` with a target time in [from, to) unix seconds.
+    #[method(name = "getTxsByDealer")]
+    async fn get_txs_by_dealer(
+        &self,
+        dealer: Address,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage>;
+    /// Txs debiting `maker` with a target time in [from, to) unix seconds.
+    #[method(name = "getTxsByMaker")]
+    async fn get_txs_by_maker(
+        &self,
+        maker: Address,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage>;
+    #[method(name = "getTxsBySourceChain")]
+    async fn get_txs_by_source_chain(
+        &self,
+        chain_id: u64,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage>;
+    #[method(name = "getTxsByTargetChain")]
+    async fn get_txs_by_target_chain(
+        &self,
+        chain_id: u64,
+        from: u64,
+        to: u64,
+        cursor: Option<TxCursor>,
+        limit: Option<u64>,
+    ) -> RpcResult<TxPage>;
+    #[method(name = "getTxsBySourceId")]
+    async fn get_txs_by_source_id(&self, source_id: String) -> RpcResult<Vec<TxWithProfit>>;
+    /// Txs and profit of `dealer` per token with a target time in [from, to) unix seconds. The
+    /// statistics are kept per hour, so the range is widened to whole hours.
+    #[method(name = "getDealerStats")]
+    async fn get_dealer_stats(
+        &self,
+        dealer: Address,
+        from: u64,
+        to: u64,
+    ) -> RpcResult<Vec<AddressTxStats>>;
+    /// The dealers with the most profit in [from, to), in `token` if given.
+    #[method(name = "getTopDealers")]
+    async fn get_top_dealers(
+        &self,
+        from: u64,
+        to: u64,
+        token: Option<Address>,
+        limit: Option<u64>,
+    ) -> RpcResult<Vec<AddressTxStats>>;
+    /// Fees paid to dealers per maker and token in [from, to), of `maker` if given.
+    #[method(name = "getMakerFees")]
+    async fn get_maker_fees(
+        &self,
+        from: u64,
+        to: u64,
+        maker: Option<Address>,
+    ) -> RpcResult<Vec<AddressTxStats>>;
+    /// Txs and volume per source chain and token in [from, to).
+    #[method(name = "getChainStats")]
+    async fn get_chain_stats(&self, from: u64, to: u64) -> RpcResult<Vec<ChainTxStats>>;
+}
+
+// The subscriptions, apart so that `SubmitterApiClient` also works over HTTP.
+#[rpc(server, client, namespace = "submitter")]
+pub trait SubmitterPubSubApi {
+    /// Roots submitted on chain, once their tx is included.
+    #[subscription(
+        name = "subscribeNewRoot" => "newRoot",
+        unsubscribe = "unsubscribeNewRoot",
+        item = NewRoot
+    )]
+    async fn subscribe_new_root(&self) -> SubscriptionResult;
+    #[subscription(
+        name = "subscribeArchivedBlock" => "archivedBlock",
+        unsubscribe = "unsubscribeArchivedBlock",
+        item = ArchivedBlock
+    )]
+    async fn subscribe_archived_block(&self) -> SubscriptionResult;
+    /// Changes of the profit leaves of `address` in every archived block.
+    #[subscription(
+        name = "subscribeBalance" => "balance",
+        unsubscribe = "unsubscribeBalance",
+        item = BalanceChange
+    )]
+    async fn subscribe_balance(&self, address: Address) -> SubscriptionResult;
 }
 
 /// Several basic implementations of off-chain state.
@@ -70,6 +224,15 @@ pub trait StataTrait<K, V> {
     fn try_get(&self, key: K) -> Result<V>;
     /// get current merkle root.
     fn try_get_root(&self) -> Result<H256>;
+    /// get all non-empty leaves.
+    fn try_get_leaves(&self) -> Result<Vec<(K, V)>>;
+    /// get at most `limit` non-empty leaves after `cursor` in key order, and the cursor of the
+    /// next page if there is one.
+    fn try_get_leaves_page(
+        &self,
+        cursor: Option<K>,
+        limit: usize,
+    ) -> Result<(Vec<(K, V)>, Option<K>)>;
 }
 
 #[async_trait]
@@ -36,6 +36,20 @@ pub struct ProfitProof {
     pub no1_merge_value: (u8, H256),
 }
 
+/// Proofs of several profit leaves against one root.
+#[serde_as]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitProofs {
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub root: [u8; 32],
+    /// One proof per key, empty if the proof is compiled.
+    pub proofs: Vec<ProfitProof>,
+    /// The leaves the multiproof is for, in the order of the keys.
+    pub leaves: Vec<ProfitBalance>,
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    pub multiproof: Option<Vec<u8>>,
+}
+
 #[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
 pub struct ProfitStateData {
     pub token: Address,
@@ -375,6 +389,251 @@ pub struct CrossTxProfit {
     pub token: Address,
 }
 
+/// Position of a tx in the txs db, ordered by target time, then target chain and target id.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct TxCursor {
+    pub target_time: u64,
+    pub target_chain: u64,
+    pub target_id: H256,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxWithProfit {
+    pub tx: CrossTxData,
+    pub profit: CrossTxProfit,
+}
+
+/// Txs in target time order, `next_cursor` is set if there are more.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxPage {
+    pub txs: Vec<TxWithProfit>,
+    pub next_cursor: Option<TxCursor>,
+}
+
+/// Txs of a dealer or a maker in one token over a time range, and the profit credited to the
+/// dealer or paid by the maker.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct AddressTxStats {
+    pub address: Address,
+    pub token: Address,
+    pub txs: u64,
+    pub profit: U256,
+}
+
+/// Txs from a source chain in one token over a time range.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ChainTxStats {
+    pub chain_id: u64,
+    pub token: Address,
+    pub txs: u64,
+    // Sum of the source amounts, a decimal in token units.
+    pub volume: String,
+    // Txs whose source amount is not a decimal, left out of `volume`.
+    pub unread_volume_txs: u64,
+}
+
+/// Chains and mainnet tokens supported from `from_block` until the next set.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct SupportSet {
+    pub from_block: u64,
+    pub chains: Vec<u64>,
+    pub tokens: Vec<Address>,
+}
+
+/// A tx that the source published after its block was archived.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct MissedTx {
+    pub tx: CrossTxData,
+    pub profit: CrossTxProfit,
+    pub block_number: u64,
+    // Whether the block is already covered by a root submitted on chain.
+    pub submitted: bool,
+}
+
+/// Why a tx earns its dealer nothing.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TxSkipReason {
+    /// No block info covers its target time.
+    MissingBlock,
+    UnsupportedTargetChain,
+    UnsupportedSourceChain,
+    /// Saved, but left out of the archived txs.
+    ZeroProfit,
+}
+
+/// What the crawler did with a tx.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TxOutcome {
+    /// Waiting for its target block to be crawled.
+    Pending,
+    /// Saved with its target block.
+    Assigned,
+    Skipped(TxSkipReason),
+    /// Published after its block was archived.
+    Missed,
+}
+
+impl Default for TxOutcome {
+    fn default() -> Self {
+        Self::Pending
+    }
+}
+
+/// A tx as the source returned it and the last thing the crawler did with it.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxAuditRecord {
+    pub raw: CrossTxRawData,
+    pub outcome: TxOutcome,
+    pub target_block: Option<u64>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TxArchiveStatus {
+    /// The submitter has not seen the tx, or its block was pruned.
+    Unknown,
+    /// Saved, its block is not archived yet.
+    Pending,
+    Archived,
+    Missed,
+    Skipped,
+}
+
+impl Default for TxArchiveStatus {
+    fn default() -> Self {
+        Self::Unknown
+    }
+}
+
+/// The lifecycle of a tx, from the source record to the submitted root that includes it.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxAudit {
+    pub target_id: H256,
+    // None if the tx was crawled before the records were kept, or its block was pruned.
+    pub record: Option<TxAuditRecord>,
+    // The tx and the profit it credits, as saved or as missed.
+    pub tx: Option<TxWithProfit>,
+    pub target_block: Option<u64>,
+    // The dealer's share of the tx profit in millionths, read at `target_block`.
+    pub fee_ratio: Option<u64>,
+    pub skipped: Option<TxSkipReason>,
+    pub archive_status: TxArchiveStatus,
+    // Txs of block `n` are archived with block `n + 1`.
+    pub archived_block: Option<u64>,
+    // The contract storage at the first block whose root covers `archived_block`.
+    pub submitted_root: Option<BlockStorage>,
+}
+
+/// Balance and debt of a profit leaf before and after a block or a range of blocks.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitStateDiff {
+    pub chain_id: u64,
+    pub token: Address,
+    pub address: Address,
+    pub old_balance: U256,
+    pub old_debt: U256,
+    pub new_balance: U256,
+    pub new_debt: U256,
+}
+
+/// A root submitted on chain, pushed to `submitter_subscribeNewRoot` once its tx is included.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct NewRoot {
+    pub tx_hash: ethers::types::H256,
+    // The block the tx is included in.
+    pub block_number: u64,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub profit_root: [u8; 32],
+    pub blocks_root: [u8; 32],
+}
+
+/// A block appended to the blocks state, with the profit leaves it changed.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ArchivedBlock {
+    pub block: BlocksStateData,
+    pub changes: Vec<ProfitStateDiff>,
+}
+
+/// A profit leaf changed by an archived block, pushed to `submitter_subscribeBalance`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BalanceChange {
+    pub block_num: u64,
+    pub diff: ProfitStateDiff,
+}
+
+/// Published by the submitter for the rpc subscriptions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SubmitterEvent {
+    NewRoot(NewRoot),
+    ArchivedBlock(ArchivedBlock),
+}
+
+/// A ready-to-sign `withdrawVerification` call to the FeeManager contract.
+#[serde_as]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct WithdrawCall {
+    pub from: Address,
+    pub to: Address,
+    pub data: ethers::types::Bytes,
+    /// The submitted root the proofs are against, and the last block it covers.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub profit_root: [u8; 32],
+    pub block_number: u64,
+    pub proofs: Vec<ProfitProof>,
+    pub withdraw_amount: Vec<U256>,
+    /// None if the call succeeded in an `eth_call`, otherwise why it reverted.
+    pub simulation_error: Option<String>,
+}
+
+/// A checkpoint of the storage, taken when `block_number` was the last archived block.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct CheckpointInfo {
+    pub name: String,
+    pub block_number: u64,
+    pub profit_root: [u8; 32],
+    pub blocks_root: [u8; 32],
+    pub schema_version: u32,
+    /// Unix seconds.
+    pub created_at: u64,
+}
+
+/// All fields are optional, a leaf matches if every given field is equal.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BalanceFilter {
+    pub chain_id: Option<u64>,
+    pub token: Option<Address>,
+    pub address: Option<Address>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitBalance {
+    pub key: H256,
+    pub chain_id: u64,
+    pub token: Address,
+    // None if the owner of the leaf is unknown.
+    pub address: Option<Address>,
+    pub balance: U256,
+    pub debt: U256,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BalancePage {
+    pub balances: Vec<ProfitBalance>,
+    // Pass it as the cursor to get the next page, None if this is the last page.
+    pub next_cursor: Option<H256>,
+}
+
+/// Profit leaves as they were when a block was archived.
+#[serde_as]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ProfitAt {
+    pub block_num: u64,
+    // The profit root archived with the block.
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub profit_root: [u8; 32],
+    pub balances: Vec<ProfitBalance>,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum FeeManagerDuration {
     Lock,
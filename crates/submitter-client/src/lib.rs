@@ -0,0 +1,42 @@
+//! Typed client of the submitter rpc, generated from the same traits the server implements.
+//!
+//! The methods are on [`SubmitterApiClient`], over HTTP or WebSocket. The subscriptions of
+//! [`SubmitterPubSubApiClient`] need a WebSocket client, and the methods of [`DebugApiClient`] the
+//! debug listener with its bearer token.
+
+pub use jsonrpsee::{
+    core::{client::Subscription, Error},
+    http_client::HttpClient,
+    ws_client::WsClient,
+};
+pub use primitives::{
+    openrpc::{DEBUG_OPENRPC, SUBMITTER_OPENRPC},
+    traits::{DebugApiClient, SubmitterApiClient, SubmitterPubSubApiClient},
+    types,
+};
+
+use jsonrpsee::{
+    http_client::{HeaderMap, HeaderValue, HttpClientBuilder},
+    ws_client::WsClientBuilder,
+};
+
+/// Client of the rpc served at `url`, e.g. `http://127.0.0.1:50001`.
+pub fn http_client(url: &str) -> Result<HttpClient, Error> {
+    HttpClientBuilder::default().build(url)
+}
+
+/// Client of the rpc served at `url`, e.g. `ws://127.0.0.1:50001`, with the subscriptions.
+pub async fn ws_client(url: &str) -> Result<WsClient, Error> {
+    WsClientBuilder::default().build(url).await
+}
+
+/// Client of the debug rpc served at `url`, sending `token` as the bearer token.
+pub fn debug_http_client(url: &str, token: &str) -> Result<HttpClient, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|e| Error::Custom(e.to_string()))?,
+    );
+    HttpClientBuilder::default().set_headers(headers).build(url)
+}
@@ -0,0 +1,302 @@
+//! Archiving of a crawled block into the profit state and the blocks state.
+
+use super::*;
+
+/// Applies blocks to the profit state and the blocks state, as the submitter does before
+/// submitting a root. Also used by `replay` to re-execute blocks into another state.
+pub struct Archiver {
+    storage: Arc<Storage>,
+    profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    txs_db: Arc<TxsRocksDB>,
+    user_tokens_db: UserTokensDB,
+    profit_statistic_db: ProfitStatisticsDB,
+    profit_changes_db: ProfitChangesDB,
+    // If set, the profit of each tx is recalculated from the saved fee ratio.
+    maker_profit_db: Option<MakerProfitDB>,
+    // If set, every archived block is published to it.
+    events: Option<Sender<SubmitterEvent>>,
+}
+
+impl Archiver {
+    pub fn new(
+        storage: Arc<Storage>,
+        txs_db: Arc<TxsRocksDB>,
+        profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+        blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            storage: storage.clone(),
+            profit_state,
+            blocks_state,
+            txs_db,
+            user_tokens_db: UserTokensDB::new(storage.clone())?,
+            profit_statistic_db: ProfitStatisticsDB::new(storage.clone())?,
+            profit_changes_db: ProfitChangesDB::new(storage.clone())?,
+            maker_profit_db: None,
+            events: None,
+        })
+    }
+
+    /// Recalculate the profit of each tx from the fee ratios in `maker_profit_db` instead of
+    /// using the profit saved with the tx.
+    pub fn with_recalculated_profit(mut self, maker_profit_db: MakerProfitDB) -> Self {
+        self.maker_profit_db = Some(maker_profit_db);
+        self
+    }
+
+    /// Publish every archived block and the profit leaves it changed to `events`.
+    pub fn with_events(mut self, events: Sender<SubmitterEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Apply the events of `now_block_info` and the txs between the two blocks' timestamps,
+    /// and append the block to the blocks state. Everything the block writes is committed in one
+    /// transaction, so a failure leaves the storage as it was before the block.
+    pub fn archive_block(
+        &self,
+        now_block_info: BlockInfo,
+        last_block_info: &BlockInfo,
+    ) -> anyhow::Result<BlocksStateData> {
+        let now_block_num = now_block_info.storage.block_number;
+        if now_block_num == 0 {
+            unreachable!()
+        }
+        let timestamp_range = (
+            last_block_info.storage.block_timestamp,
+            now_block_info.storage.block_timestamp,
+        );
+
+        // Held until the block is committed, so no one sees a half archived block.
+        let profit_state = self.profit_state.write().unwrap();
+        let blocks_state = self.blocks_state.write().unwrap();
+        // Run again if another task wrote the same keys meanwhile, e.g. the block info crawler
+        // the statistics of a maker.
+        let (new_block, changes) = self.storage.transact(|db_tx| -> anyhow::Result<_> {
+            let mut profit_root = profit_state.try_get_root()?;
+
+            let mut profit_changes: Vec<(H256, ProfitStateDiff)> = vec![];
+            for e in now_block_info.events.iter() {
+                match e.clone() {
+                    Event::Withdraw(w_e) => {
+                        let user = chain_token_address_convert_to_h256(
+                            w_e.chain_id,
+                            w_e.token_address,
+                            w_e.address,
+                        );
+                        let mut user_profit = profit_state.try_get_in(db_tx, user)?;
+                        let old_profit = user_profit.clone();
+                        if user_profit == ProfitStateData::default() {
+                            user_profit.token = w_e.token_address;
+                            user_profit.token_chain_id = w_e.chain_id;
+                        }
+                        user_profit.sub_balance(w_e.balance).unwrap();
+                        profit_root = profit_state
+                            .try_update_all_in(db_tx, vec![(user, user_profit.clone())])?;
+                        record_profit_change(
+                            &mut profit_changes,
+                            user,
+                            (w_e.chain_id, w_e.token_address, w_e.address),
+                            &old_profit,
+                            &user_profit,
+                        );
+                        event!(
+                            Level::INFO,
+                            "Block #{:?}. - withdraw event {:?} - user: {:?} - balance: {:?}",
+                            now_block_num,
+                            e,
+                            user,
+                            w_e.balance,
+                        );
+                    }
+                    Event::Deposit(d_e) => {
+                        let user = chain_token_address_convert_to_h256(
+                            d_e.chain_id,
+                            d_e.token_address,
+                            d_e.address,
+                        );
+                        let mut user_profit = profit_state.try_get_in(db_tx, user)?;
+                        let old_profit = user_profit.clone();
+                        if user_profit == ProfitStateData::default() {
+                            user_profit.token = d_e.token_address;
+                            user_profit.token_chain_id = d_e.chain_id;
+                        }
+                        user_profit.add_balance(d_e.balance).unwrap();
+                        profit_root = profit_state
+                            .try_update_all_in(db_tx, vec![(user, user_profit.clone())])?;
+                        record_profit_change(
+                            &mut profit_changes,
+                            user,
+                            (d_e.chain_id, d_e.token_address, d_e.address),
+                            &old_profit,
+                            &user_profit,
+                        );
+                        event!(
+                            Level::INFO,
+                            "Block #{:?}. - deposit event {:?} - user: {:?} - balance: {:?}",
+                            now_block_num,
+                            e,
+                            user,
+                            d_e.balance,
+                        );
+                    }
+                }
+            }
+
+            let txs = self
+                .txs_db
+                .get_txs_by_timestamp_range(timestamp_range.0, timestamp_range.1)?;
+            let mut tx_hashes: Vec<H256> = vec![];
+            for mut tx in txs {
+                if let Some(maker_profit_db) = &self.maker_profit_db {
+                    // Txs archived with this block were crawled for the previous block.
+                    let percent = maker_profit_db
+                        .get_percent(tx.0.dealer_address, now_block_num - 1, tx.0.source_token)?
+                        .ok_or(anyhow::anyhow!(
+                            "Block #{:?}. - no fee ratio of dealer {:?}",
+                            now_block_num - 1,
+                            tx.0.dealer_address
+                        ))?;
+                    tx.1 = calculate_profit(percent, tx.0.clone());
+                }
+                let profit = tx.1.profit;
+                if profit == U256::from(0) {
+                    event!(
+                        Level::INFO,
+                        "Block #{:?}. - tx {:?} - profit is zero.",
+                        now_block_num,
+                        hex::encode(&tx.0.target_id.as_slice()),
+                    );
+                    continue;
+                }
+                let maker = tx.1.maker_address;
+                let dealer = tx.1.dealer_address;
+                let chain_id = tx.1.chain_id;
+                let token_id = tx.1.token;
+                let maker_key = chain_token_address_convert_to_h256(chain_id, token_id, maker);
+                let dealer_key = chain_token_address_convert_to_h256(chain_id, token_id, dealer);
+                let mut maker_profit = profit_state.try_get_in(db_tx, maker_key)?;
+                let mut dealer_profit = profit_state.try_get_in(db_tx, dealer_key)?;
+                let old_maker_profit = maker_profit.clone();
+                let old_dealer_profit = dealer_profit.clone();
+                if maker_profit == ProfitStateData::default() {
+                    maker_profit.token = token_id;
+                    maker_profit.token_chain_id = chain_id;
+                }
+                maker_profit.sub_balance(profit).unwrap();
+                if dealer_profit == ProfitStateData::default() {
+                    dealer_profit.token = token_id;
+                    dealer_profit.token_chain_id = chain_id;
+                }
+                self.profit_statistic_db
+                    .update_total_withdraw_in(db_tx, maker, chain_id, token_id, profit)?;
+                dealer_profit.add_balance(profit).unwrap();
+                profit_root = profit_state.try_update_all_in(
+                    db_tx,
+                    vec![
+                        (maker_key, maker_profit.clone()),
+                        (dealer_key, dealer_profit.clone()),
+                    ],
+                )?;
+                record_profit_change(
+                    &mut profit_changes,
+                    maker_key,
+                    (chain_id, token_id, maker),
+                    &old_maker_profit,
+                    &maker_profit,
+                );
+                record_profit_change(
+                    &mut profit_changes,
+                    dealer_key,
+                    (chain_id, token_id, dealer),
+                    &old_dealer_profit,
+                    &dealer_profit,
+                );
+                event!(
+                    Level::INFO,
+                    "Block #{:?}. - tx {:?} - maker: {:?} - dealer: {:?} - profit: {:?}",
+                    now_block_num,
+                    hex::encode(&tx.0.target_id.as_slice()),
+                    maker,
+                    dealer,
+                    profit,
+                );
+                self.profit_statistic_db
+                    .update_total_profit_in(db_tx, dealer, chain_id, token_id, profit)?;
+                self.user_tokens_db
+                    .insert_token_in(db_tx, maker, chain_id, token_id)?;
+                self.user_tokens_db
+                    .insert_token_in(db_tx, dealer, chain_id, token_id)?;
+
+                tx_hashes.push(tx.0.target_id);
+            }
+
+            let txs_hash = get_one_block_txs_hash(tx_hashes.clone());
+            event!(
+                Level::INFO,
+                "Block #{:?}. - txs hash: {:?}",
+                now_block_num,
+                hex::encode(&txs_hash.as_slice()),
+            );
+
+            let last_key = block_number_convert_to_h256(now_block_num - 1);
+            let now_key = block_number_convert_to_h256(now_block_num);
+            let mut new_block = BlocksStateData {
+                txs: txs_hash.into(),
+                block_num: now_block_num,
+                profit_root: profit_root.into(),
+                ..Default::default()
+            };
+            let old_block = blocks_state.try_get_in(db_tx, last_key)?;
+            new_block.into_chain(old_block);
+            blocks_state.try_update_all_in(db_tx, vec![(now_key, new_block.clone())])?;
+            let changes: Vec<ProfitStateDiff> =
+                profit_changes.iter().map(|(_, d)| d.clone()).collect();
+            self.profit_changes_db
+                .insert_changes_in(db_tx, now_block_num, profit_changes)?;
+            Ok((new_block, changes))
+        })?;
+        if let Some(events) = &self.events {
+            // No subscribers is not an error.
+            let _ = events.send(SubmitterEvent::ArchivedBlock(ArchivedBlock {
+                block: new_block.clone(),
+                changes,
+            }));
+        }
+        event!(
+            Level::INFO,
+            "Block #{:?}. - block state add new block: {:?}",
+            now_block_num,
+            serde_json::to_string(&new_block).unwrap(),
+        );
+        Ok(new_block)
+    }
+}
+
+/// Merge a leaf update into the block's changes, keeping the value from before the block.
+fn record_profit_change(
+    changes: &mut Vec<(H256, ProfitStateDiff)>,
+    path: H256,
+    (chain_id, token, address): (u64, Address, Address),
+    old: &ProfitStateData,
+    new: &ProfitStateData,
+) {
+    if let Some((_, diff)) = changes.iter_mut().find(|(p, _)| p == &path) {
+        diff.new_balance = new.balance;
+        diff.new_debt = new.debt;
+        return;
+    }
+    changes.push((
+        path,
+        ProfitStateDiff {
+            chain_id,
+            token,
+            address,
+            old_balance: old.balance,
+            old_debt: old.debt,
+            new_balance: new.balance,
+            new_debt: new.debt,
+        },
+    ));
+}
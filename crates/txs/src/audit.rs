@@ -0,0 +1,171 @@
+//! The audit trail of a tx, put together from the record the crawler kept and where the tx is now.
+
+use super::*;
+use primitives::types::{BlockStorage, TxArchiveStatus, TxAudit, TxWithProfit};
+
+/// The contract storage at the first block whose submitted root covers the archived block
+/// `block_num`. A root ending at block `n` covers the blocks archived before `n`.
+fn find_submitted_root(
+    block_info_db: &ContractBlockInfoDB,
+    block_num: u64,
+) -> anyhow::Result<Option<BlockStorage>> {
+    let block_info = match block_info_db.get_block_info(block_num)? {
+        Some(bi) => bi,
+        None => return Ok(None),
+    };
+    for bi in block_info_db.iter_block_infos_from(block_info.storage.block_timestamp) {
+        let bi = bi?;
+        if bi.storage.last_update_block > block_num {
+            return Ok(Some(bi.storage));
+        }
+    }
+    Ok(None)
+}
+
+/// Everything the submitter knows about the tx with the target tx hash `target_id`.
+pub fn get_tx_audit(
+    storage: &Arc<Storage>,
+    txs_db: &TxsRocksDB,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    target_id: H256,
+) -> anyhow::Result<TxAudit> {
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let maker_profit_db = MakerProfitDB::new(storage.clone())?;
+    let missed_txs_db = MissedTxsDB::new(storage.clone())?;
+
+    let mut audit = TxAudit {
+        target_id,
+        record: TxAuditDB::new(storage.clone())?.get_record(target_id)?,
+        ..Default::default()
+    };
+    let outcome = audit.record.as_ref().map(|r| r.outcome.clone());
+    audit.target_block = audit.record.as_ref().and_then(|r| r.target_block);
+
+    if let Some(tx) = txs_db.get_tx_by_target_id(target_id)? {
+        if audit.target_block.is_none() {
+            audit.target_block = block_info_db.get_target_block_num(tx.tx.target_time / 1000)?;
+        }
+        audit.archive_status = match audit.target_block {
+            // Txs of block `n` are archived together with block `n + 1`.
+            Some(b) if is_block_archived(blocks_state, b + 1)? => TxArchiveStatus::Archived,
+            _ => TxArchiveStatus::Pending,
+        };
+        audit.tx = Some(tx);
+    } else if let Some(missed) = missed_txs_db.get_missed_tx(target_id)? {
+        audit.target_block = Some(missed.block_number);
+        audit.archive_status = TxArchiveStatus::Missed;
+        audit.tx = Some(TxWithProfit {
+            tx: missed.tx,
+            profit: missed.profit,
+        });
+    } else {
+        audit.archive_status = match (&outcome, audit.target_block) {
+            (Some(TxOutcome::Skipped(_)), _) => TxArchiveStatus::Skipped,
+            (Some(TxOutcome::Pending), _) => TxArchiveStatus::Pending,
+            _ => TxArchiveStatus::Unknown,
+        };
+    }
+
+    if let Some(TxOutcome::Skipped(reason)) = outcome {
+        audit.skipped = Some(reason);
+    } else if audit.archive_status == TxArchiveStatus::Archived
+        && audit
+            .tx
+            .as_ref()
+            .map_or(false, |t| t.profit.profit.is_zero())
+    {
+        audit.skipped = Some(TxSkipReason::ZeroProfit);
+    }
+
+    // The fee ratio is read at the target block, see `get_dealer_percent`.
+    let dealer_and_token = match (&audit.tx, &audit.record) {
+        (Some(t), _) => Some((t.tx.dealer_address, t.tx.source_token)),
+        (None, Some(r)) => Address::from_str(&r.raw.dealer_address)
+            .ok()
+            .zip(Address::from_str(&r.raw.source_token).ok()),
+        (None, None) => None,
+    };
+    if let (Some((dealer, token)), Some(b)) = (dealer_and_token, audit.target_block) {
+        audit.fee_ratio = maker_profit_db.get_percent(dealer, b, token)?;
+    }
+
+    if audit.archive_status == TxArchiveStatus::Archived {
+        audit.archived_block = audit.target_block.map(|b| b + 1);
+    }
+    if let Some(b) = audit.archived_block {
+        audit.submitted_root = find_submitted_root(&block_info_db, b)?;
+    }
+    Ok(audit)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::fixtures::{test_block_info, test_tx, DEALER};
+
+    #[test]
+    fn test_tx_audit() {
+        let path = "./db_tx_audit";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let txs_db = TxsRocksDB::new(storage.clone()).unwrap();
+        let block_info_db = ContractBlockInfoDB::new(storage.clone()).unwrap();
+        let tx_audit_db = TxAuditDB::new(storage.clone()).unwrap();
+        let blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>> =
+            Arc::new(RwLock::new(State::new(
+                primitives::storage::BLOCKS_STATE_PREFIX,
+                storage.clone(),
+            )));
+
+        // The root ending at block 3 is seen from block 4 on.
+        for n in 1u64..=4 {
+            let mut block_info = test_block_info(n);
+            block_info.storage.last_update_block = if n == 4 { 3 } else { 1 };
+            block_info_db.insert_block_info(n, block_info).unwrap();
+        }
+        let (tx, profit) = test_tx(11, 1);
+        txs_db.insert_txs(vec![(tx.clone(), profit)]).unwrap();
+        MakerProfitDB::new(storage.clone())
+            .unwrap()
+            .insert_percent(DEALER, 1, Address::zero(), 500)
+            .unwrap();
+        let db_tx = storage.transaction();
+        tx_audit_db
+            .record_in(
+                &db_tx,
+                [12u8; 32].into(),
+                Some(CrossTxRawData::default()),
+                TxOutcome::Skipped(TxSkipReason::UnsupportedSourceChain),
+                Some(1),
+            )
+            .unwrap();
+        db_tx.commit().unwrap();
+
+        let audit = get_tx_audit(&storage, &txs_db, &blocks_state, tx.target_id).unwrap();
+        assert_eq!(audit.target_block, Some(1));
+        assert_eq!(audit.fee_ratio, Some(500));
+        assert_eq!(audit.archive_status, TxArchiveStatus::Pending);
+
+        blocks_state
+            .write()
+            .unwrap()
+            .try_update_all(vec![(
+                block_number_convert_to_h256(2),
+                BlocksStateData {
+                    block_num: 2,
+                    ..Default::default()
+                },
+            )])
+            .unwrap();
+        let audit = get_tx_audit(&storage, &txs_db, &blocks_state, tx.target_id).unwrap();
+        assert_eq!(audit.archive_status, TxArchiveStatus::Archived);
+        assert_eq!(audit.archived_block, Some(2));
+        assert_eq!(audit.submitted_root.map(|s| s.block_number), Some(4));
+
+        let audit = get_tx_audit(&storage, &txs_db, &blocks_state, [12u8; 32].into()).unwrap();
+        assert_eq!(audit.archive_status, TxArchiveStatus::Skipped);
+        assert_eq!(audit.skipped, Some(TxSkipReason::UnsupportedSourceChain));
+        let audit = get_tx_audit(&storage, &txs_db, &blocks_state, [13u8; 32].into()).unwrap();
+        assert_eq!(audit.archive_status, TxArchiveStatus::Unknown);
+    }
+}
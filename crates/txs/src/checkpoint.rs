@@ -0,0 +1,303 @@
+//! Point-in-time checkpoints of the storage, taken while the submitter runs.
+//!
+//! A checkpoint is the directory `{db_path}/checkpoints/{name}`, holding a RocksDB checkpoint of
+//! the storage in `store` and its `CheckpointInfo` in `checkpoint.json`. The info is written last,
+//! so a directory without it is an unfinished checkpoint and is not listed.
+
+use super::*;
+use primitives::{
+    env::get_checkpoint_retain,
+    storage::{BLOCKS_STATE_PREFIX, PROFIT_STATE_PREFIX},
+    types::CheckpointInfo,
+};
+use schema::{get_schema_version, SCHEMA_VERSION};
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const CHECKPOINTS_DIR: &str = "checkpoints";
+pub const STORE_DIR: &str = "store";
+const INFO_FILE: &str = "checkpoint.json";
+
+fn checkpoints_dir(db_path: &str) -> PathBuf {
+    Path::new(db_path).join(CHECKPOINTS_DIR)
+}
+
+fn now_secs() -> anyhow::Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn last_archived_block(
+    blocks_state: &State<'static, Keccak256Hasher, BlocksStateData>,
+) -> anyhow::Result<u64> {
+    Ok(blocks_state
+        .try_get_leaves()?
+        .into_iter()
+        .map(|(_, b)| b.block_num)
+        .max()
+        .unwrap_or_default())
+}
+
+fn read_info(db_path: &str, name: &str) -> anyhow::Result<CheckpointInfo> {
+    let bytes = std::fs::read(checkpoints_dir(db_path).join(name).join(INFO_FILE))
+        .map_err(|_| anyhow::anyhow!("checkpoint {} does not exist or is unfinished", name))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Checkpoint the storage, tagged with the last archived block, then remove the oldest
+/// checkpoints beyond `CHECKPOINT_RETAIN`. The states are locked while the checkpoint is taken,
+/// so it never holds a block that is archived halfway.
+pub fn create_checkpoint(
+    storage: &Arc<Storage>,
+    profit_state: &Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    db_path: &str,
+) -> anyhow::Result<CheckpointInfo> {
+    let info = {
+        let profit_state = profit_state.read().unwrap();
+        let blocks_state = blocks_state.read().unwrap();
+        let block_number = last_archived_block(&blocks_state)?;
+        let profit_root = profit_state.try_get_root()?;
+        if block_number != 0
+            && H256::from(
+                blocks_state
+                    .try_get(block_number_convert_to_h256(block_number))?
+                    .profit_root,
+            ) != profit_root
+        {
+            return Err(anyhow::anyhow!(
+                "profit root does not match the one of Block #{:}",
+                block_number
+            ));
+        }
+
+        let created_at = now_secs()?;
+        let name = format!("{:012}-{}", block_number, created_at);
+        let dir = checkpoints_dir(db_path).join(&name);
+        if dir.exists() {
+            return Err(anyhow::anyhow!("checkpoint {} already exists", name));
+        }
+        std::fs::create_dir_all(&dir)?;
+        storage.checkpoint(&dir.join(STORE_DIR).to_string_lossy())?;
+        CheckpointInfo {
+            name,
+            block_number,
+            profit_root: profit_root.into(),
+            blocks_root: blocks_state.try_get_root()?.into(),
+            schema_version: get_schema_version(storage)?.unwrap_or(SCHEMA_VERSION),
+            created_at,
+        }
+    };
+    std::fs::write(
+        checkpoints_dir(db_path).join(&info.name).join(INFO_FILE),
+        serde_json::to_vec_pretty(&info)?,
+    )?;
+    event!(
+        Level::INFO,
+        "Checkpoint {} created at Block #{:?}.",
+        info.name,
+        info.block_number
+    );
+    prune_checkpoints(db_path, get_checkpoint_retain())?;
+    Ok(info)
+}
+
+/// Finished checkpoints under `db_path`, oldest first.
+pub fn list_checkpoints(db_path: &str) -> anyhow::Result<Vec<CheckpointInfo>> {
+    let dir = checkpoints_dir(db_path);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut checkpoints = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let info_path = entry?.path().join(INFO_FILE);
+        if !info_path.exists() {
+            continue;
+        }
+        checkpoints.push(serde_json::from_slice::<CheckpointInfo>(&std::fs::read(
+            info_path,
+        )?)?);
+    }
+    checkpoints.sort_by_key(|c| (c.block_number, c.created_at));
+    Ok(checkpoints)
+}
+
+/// Remove the oldest checkpoints until `retain` are left, keeping at least the newest one.
+/// Returns the names of the removed checkpoints.
+pub fn prune_checkpoints(db_path: &str, retain: usize) -> anyhow::Result<Vec<String>> {
+    let checkpoints = list_checkpoints(db_path)?;
+    let mut removed = vec![];
+    for c in checkpoints
+        .iter()
+        .take(checkpoints.len().saturating_sub(retain.max(1)))
+    {
+        std::fs::remove_dir_all(checkpoints_dir(db_path).join(&c.name))?;
+        event!(Level::INFO, "Checkpoint {} removed.", c.name);
+        removed.push(c.name.clone());
+    }
+    Ok(removed)
+}
+
+/// Check that the store at `path` holds the roots `info` was tagged with, and that the profit
+/// root is the one of its last archived block.
+fn verify_store(path: &Path, info: &CheckpointInfo) -> anyhow::Result<()> {
+    let storage = Arc::new(Storage::open(&path.to_string_lossy())?);
+    let schema_version = get_schema_version(&storage)?;
+    if schema_version != Some(info.schema_version) {
+        return Err(anyhow::anyhow!(
+            "schema version of the checkpoint is {:?}, expected {:?}",
+            schema_version,
+            info.schema_version
+        ));
+    }
+    let profit_state =
+        State::<'_, Keccak256Hasher, ProfitStateData>::new(PROFIT_STATE_PREFIX, storage.clone());
+    let blocks_state =
+        State::<'_, Keccak256Hasher, BlocksStateData>::new(BLOCKS_STATE_PREFIX, storage.clone());
+    let profit_root: [u8; 32] = profit_state.try_get_root()?.into();
+    let blocks_root: [u8; 32] = blocks_state.try_get_root()?.into();
+    if profit_root != info.profit_root || blocks_root != info.blocks_root {
+        return Err(anyhow::anyhow!(
+            "roots of checkpoint {} do not match its info",
+            info.name
+        ));
+    }
+    let block_number = last_archived_block(&blocks_state)?;
+    if block_number != info.block_number
+        || (block_number != 0
+            && blocks_state
+                .try_get(block_number_convert_to_h256(block_number))?
+                .profit_root
+                != info.profit_root)
+    {
+        return Err(anyhow::anyhow!(
+            "checkpoint {} is not at Block #{:}",
+            info.name,
+            info.block_number
+        ));
+    }
+    Ok(())
+}
+
+// The stores are flat directories.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// Replace `{db_path}/store` with a verified copy of the checkpoint `name`, which is left as it
+/// is. The replaced store is moved aside rather than removed. Run it while the submitter is
+/// stopped.
+pub fn restore_checkpoint(db_path: &str, name: &str) -> anyhow::Result<CheckpointInfo> {
+    let info = read_info(db_path, name)?;
+    if info.schema_version > SCHEMA_VERSION {
+        return Err(Error::IncompatibleSchema(info.schema_version, SCHEMA_VERSION).into());
+    }
+    let restoring = Path::new(db_path).join(format!("{}.restoring", STORE_DIR));
+    let _ = std::fs::remove_dir_all(&restoring);
+    copy_dir(
+        &checkpoints_dir(db_path).join(name).join(STORE_DIR),
+        &restoring,
+    )?;
+    if let Err(e) = verify_store(&restoring, &info) {
+        let _ = std::fs::remove_dir_all(&restoring);
+        return Err(e);
+    }
+
+    let store = Path::new(db_path).join(STORE_DIR);
+    if store.exists() {
+        let replaced = Path::new(db_path).join(format!("{}.replaced-{}", STORE_DIR, now_secs()?));
+        std::fs::rename(&store, &replaced)?;
+        event!(
+            Level::INFO,
+            "The replaced store is moved to {:?}, it can be removed once the submitter runs fine.",
+            replaced
+        );
+    }
+    std::fs::rename(&restoring, &store)?;
+    event!(
+        Level::INFO,
+        "Checkpoint {} restored at Block #{:?}.",
+        info.name,
+        info.block_number
+    );
+    Ok(info)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let db_path = "./db_checkpoint_test";
+        let _ = std::fs::remove_dir_all(db_path);
+        let leaf = |balance: u64| ProfitStateData {
+            token: Address::from_low_u64_be(1),
+            token_chain_id: 1,
+            balance: U256::from(balance),
+            debt: U256::zero(),
+        };
+        let key =
+            chain_token_address_convert_to_h256(1, Address::from_low_u64_be(1), Address::zero());
+
+        let info = {
+            let storage = Arc::new(Storage::open(&format!("{}/{}", db_path, STORE_DIR)).unwrap());
+            schema::check_and_migrate(&storage, db_path).unwrap();
+            let profit_state = Arc::new(RwLock::new(State::new(
+                PROFIT_STATE_PREFIX,
+                storage.clone(),
+            )));
+            let blocks_state = Arc::new(RwLock::new(State::new(
+                BLOCKS_STATE_PREFIX,
+                storage.clone(),
+            )));
+            let profit_root = profit_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(key, leaf(100))])
+                .unwrap();
+            blocks_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(
+                    block_number_convert_to_h256(1),
+                    BlocksStateData {
+                        block_num: 1,
+                        profit_root: profit_root.into(),
+                        ..Default::default()
+                    },
+                )])
+                .unwrap();
+
+            let info = create_checkpoint(&storage, &profit_state, &blocks_state, db_path).unwrap();
+            assert_eq!(info.block_number, 1);
+            assert_eq!(info.profit_root, <[u8; 32]>::from(profit_root));
+            assert_eq!(list_checkpoints(db_path).unwrap(), vec![info.clone()]);
+
+            // Not at an archived block any more.
+            profit_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(key, leaf(200))])
+                .unwrap();
+            assert!(create_checkpoint(&storage, &profit_state, &blocks_state, db_path).is_err());
+            info
+        };
+
+        assert!(restore_checkpoint(db_path, "missing").is_err());
+        assert_eq!(restore_checkpoint(db_path, &info.name).unwrap(), info);
+        let storage = Arc::new(Storage::open(&format!("{}/{}", db_path, STORE_DIR)).unwrap());
+        let profit_state: State<'static, Keccak256Hasher, ProfitStateData> =
+            State::new(PROFIT_STATE_PREFIX, storage);
+        assert_eq!(profit_state.try_get(key).unwrap(), leaf(100));
+        // The checkpoint can be restored again.
+        assert_eq!(list_checkpoints(db_path).unwrap(), vec![info]);
+        assert!(prune_checkpoints(db_path, 0).unwrap().is_empty());
+    }
+}
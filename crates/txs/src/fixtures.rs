@@ -0,0 +1,84 @@
+//! Fixtures shared by the tests of this crate and of the submitter, which enables the
+//! `test-fixtures` feature. Block `n` is at `n * 10` seconds, and the tx at `time` seconds has the
+//! target id `[time as u8; 32]`.
+
+use super::*;
+use primitives::storage::{BLOCKS_STATE_PREFIX, PROFIT_STATE_PREFIX};
+
+pub type ArchivedDb = (
+    Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    Arc<Storage>,
+    Arc<TxsRocksDB>,
+);
+
+pub const MAKER: Address = Address::repeat_byte(1);
+pub const DEALER: Address = Address::repeat_byte(2);
+pub const TOKEN: Address = Address::repeat_byte(3);
+
+pub fn test_tx(time: u64, profit: u64) -> (CrossTxData, CrossTxProfit) {
+    (
+        CrossTxData {
+            dealer_address: DEALER,
+            target_id: [time as u8; 32].into(),
+            target_time: time * 1000,
+            ..Default::default()
+        },
+        CrossTxProfit {
+            maker_address: MAKER,
+            dealer_address: DEALER,
+            profit: U256::from(profit),
+            chain_id: 1,
+            token: TOKEN,
+        },
+    )
+}
+
+pub fn test_block_info(n: u64) -> BlockInfo {
+    let mut block_info = BlockInfo::default();
+    block_info.storage.block_number = n;
+    block_info.storage.block_timestamp = n * 10;
+    block_info
+}
+
+/// Blocks 0 - 3, a tx in block 1 and 2, and blocks 1 - 3 archived.
+pub fn archived_db(path: &str) -> ArchivedDb {
+    let _ = std::fs::remove_dir_all(path);
+    let storage = Arc::new(Storage::open(path).unwrap());
+    let txs_db = Arc::new(TxsRocksDB::new(storage.clone()).unwrap());
+    let profit_state = Arc::new(RwLock::new(State::new(
+        PROFIT_STATE_PREFIX,
+        storage.clone(),
+    )));
+    let blocks_state = Arc::new(RwLock::new(State::new(
+        BLOCKS_STATE_PREFIX,
+        storage.clone(),
+    )));
+    let block_info_db = ContractBlockInfoDB::new(storage.clone()).unwrap();
+    let block_txs_count_db = BlockTxsCountDB::new(storage.clone()).unwrap();
+
+    for n in 0u64..=3 {
+        block_info_db
+            .insert_block_info(n, test_block_info(n))
+            .unwrap();
+    }
+    txs_db
+        .insert_txs(vec![test_tx(11, 5), test_tx(21, 7)])
+        .unwrap();
+    block_txs_count_db.insert_count(1, 1).unwrap();
+    block_txs_count_db.insert_count(2, 1).unwrap();
+
+    let archiver = Archiver::new(
+        storage.clone(),
+        txs_db.clone(),
+        profit_state.clone(),
+        blocks_state.clone(),
+    )
+    .unwrap();
+    for n in 1u64..=3 {
+        archiver
+            .archive_block(test_block_info(n), &test_block_info(n - 1))
+            .unwrap();
+    }
+    (profit_state, blocks_state, storage, txs_db)
+}
@@ -3,18 +3,20 @@
 #[allow(unused_imports)]
 use super::*;
 use primitives::{
-    env::get_mainnet_chain_id,
+    env::{get_mainnet_chain_id, get_txs_request_window_seconds, get_txs_response_max_bytes},
+    retry::{retry, retry_if, RetryPolicy, SUPPORT_CHAINS_SOURCE_ENDPOINT, TXS_SOURCE_ENDPOINT},
     types::{CrossTxData, CrossTxRawData},
 };
 use serde::{Deserialize, Serialize};
 use state::{Hasher, Keccak256Hasher};
-use std::string::String;
+use std::{collections::VecDeque, future::Future, string::String};
 
 pub struct TxsCrawler {
     url: String,
     headers: HeaderMap,
     method: String,
     client: Client,
+    retry_policy: RetryPolicy,
 }
 
 #[allow(non_snake_case)]
@@ -45,16 +47,20 @@ impl TxsCrawler {
             headers,
             method,
             client,
+            retry_policy: RetryPolicy::default(),
         }
     }
 
-    pub async fn request_txs(
+    /// Request txs in `[start_timestamp, end_timestamp]` (seconds, before the delay is applied).
+    /// The range is requested in bounded sub-windows, handed out one by one by
+    /// `TxsRequest::next_window`, so only one window is held at a time.
+    pub fn request_txs(
         &self,
         target_chain_id: u64,
         start_timestamp: u64,
         end_timestamp: u64,
         delay_timestamp: u64,
-    ) -> anyhow::Result<Vec<CrossTxRawData>> {
+    ) -> anyhow::Result<TxsRequest<'_>> {
         let start_timestamp =
             start_timestamp
                 .checked_sub(delay_timestamp)
@@ -68,7 +74,28 @@ impl TxsCrawler {
                 "end_timestamp checked_sub delay_timestamp error"
             ))?
             * 1000;
-        let res = self
+        Ok(TxsRequest {
+            crawler: self,
+            target_chain_id,
+            delay_timestamp,
+            windows: TxsWindows::new(
+                start_timestamp,
+                end_timestamp,
+                max(get_txs_request_window_seconds(), 1) * 1000,
+            ),
+            last_tx_ids: HashSet::new(),
+        })
+    }
+
+    /// Request one window, timestamps in milliseconds.
+    async fn request_txs_window(
+        &self,
+        target_chain_id: u64,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> anyhow::Result<Vec<CrossTxRawData>> {
+        let max_bytes = get_txs_response_max_bytes();
+        let mut res = self
             .client
             .post(self.url.clone())
             .headers(self.headers.clone())
@@ -84,37 +111,236 @@ impl TxsCrawler {
             .send()
             .await?;
 
-        if (res.status() == reqwest::StatusCode::OK)
-            || (res.status() == reqwest::StatusCode::CREATED)
+        if (res.status() != reqwest::StatusCode::OK)
+            && (res.status() != reqwest::StatusCode::CREATED)
         {
-            let res: Value = serde_json::from_str(&res.text().await?)?;
-            let res: &Value = &res["result"][target_chain_id.to_string()];
-            event!(
-                Level::INFO,
-                "start_timestamp: {}, end_timestamp: {}, chain id: {}, res: {:#?}",
-                start_timestamp,
-                end_timestamp,
-                target_chain_id,
-                res
-            );
-            let old_txs: Vec<CrossTxRawData> = serde_json::from_value(res.clone())?;
-            let mut new_txs: Vec<CrossTxRawData> = vec![];
-            for tx in old_txs {
-                // TODO: check source_time
-                event!(Level::INFO, "tx: {:?}", tx);
-                let mut tx = tx;
+            return Err(anyhow::anyhow!("err: {:#?}", res.text().await?));
+        }
+        if res.content_length().unwrap_or(0) > max_bytes as u64 {
+            return Err(ResponseTooLarge(max_bytes).into());
+        }
 
+        // Parse the body as its chunks arrive, so the raw response is not held next to the txs.
+        // An oversized response is dropped early.
+        let (sender, chunks) = tokio::sync::mpsc::channel(16);
+        let parser = tokio::task::spawn_blocking(move || {
+            serde_json::from_reader::<_, TxsResponse>(ChunkReader::new(chunks))
+        });
+        let mut response_bytes = 0;
+        while let Some(chunk) = res.chunk().await? {
+            response_bytes += chunk.len();
+            if response_bytes > max_bytes {
+                return Err(ResponseTooLarge(max_bytes).into());
+            }
+            if sender.send(chunk).await.is_err() {
+                // The parser stopped on an error, returned below.
+                break;
+            }
+        }
+        drop(sender);
+
+        let mut res = parser.await??;
+        let txs = res
+            .result
+            .remove(&target_chain_id.to_string())
+            .ok_or(anyhow::anyhow!(
+                "chain id {} is not in the txs response",
+                target_chain_id
+            ))?;
+        event!(
+            Level::DEBUG,
+            "start_timestamp: {}, end_timestamp: {}, chain id: {}, response bytes: {}, txs count: {}",
+            start_timestamp,
+            end_timestamp,
+            target_chain_id,
+            response_bytes,
+            txs.len()
+        );
+        Ok(txs)
+    }
+}
+
+/// The sub-windows of a txs request, timestamps in milliseconds, in time order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxsWindows {
+    windows: VecDeque<(u64, u64)>,
+}
+
+impl TxsWindows {
+    /// `[start, end]` in windows of `window` milliseconds. Adjacent windows share the boundary.
+    pub fn new(start: u64, end: u64, window: u64) -> Self {
+        let mut windows: VecDeque<(u64, u64)> = VecDeque::new();
+        let mut window_start = start;
+        loop {
+            let window_end = min(window_start.saturating_add(window), end);
+            windows.push_back((window_start, window_end));
+            if window_end >= end {
+                break;
+            }
+            window_start = window_end;
+        }
+        Self { windows }
+    }
+
+    fn pop(&mut self) -> Option<(u64, u64)> {
+        self.windows.pop_front()
+    }
+
+    /// Put `window` back as its two halves, false if it is one second or shorter.
+    fn split(&mut self, (start, end): (u64, u64)) -> bool {
+        if end - start <= 1000 {
+            return false;
+        }
+        let middle = start + (end - start) / 2;
+        self.windows.push_front((middle, end));
+        self.windows.push_front((start, middle));
+        true
+    }
+}
+
+/// The txs of one chain in a time range, see `TxsCrawler::request_txs`.
+pub struct TxsRequest<'a> {
+    crawler: &'a TxsCrawler,
+    target_chain_id: u64,
+    delay_timestamp: u64,
+    windows: TxsWindows,
+    // Adjacent windows share the boundary timestamp.
+    last_tx_ids: HashSet<String>,
+}
+
+impl<'a> TxsRequest<'a> {
+    /// The txs of the next window, None once the range is done. A window whose response is too
+    /// large is split in half until it fits.
+    pub async fn next_window(&mut self) -> anyhow::Result<Option<Vec<CrossTxRawData>>> {
+        let crawler = self.crawler;
+        let target_chain_id = self.target_chain_id;
+        self.next_window_with(|start, end| async move {
+            retry_if(
+                TXS_SOURCE_ENDPOINT,
+                &crawler.retry_policy,
+                move || crawler.request_txs_window(target_chain_id, start, end),
+                |err: &anyhow::Error| err.downcast_ref::<ResponseTooLarge>().is_none(),
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn next_window_with<F, Fut>(
+        &mut self,
+        request: F,
+    ) -> anyhow::Result<Option<Vec<CrossTxRawData>>>
+    where
+        F: Fn(u64, u64) -> Fut,
+        Fut: Future<Output = anyhow::Result<Vec<CrossTxRawData>>>,
+    {
+        while let Some(window) = self.windows.pop() {
+            let txs = match request(window.0, window.1).await {
+                Ok(txs) => txs,
+                Err(err)
+                    if err.downcast_ref::<ResponseTooLarge>().is_some()
+                        && self.windows.split(window) =>
+                {
+                    event!(
+                        Level::INFO,
+                        "chain id: {}, txs response of [{}, {}] is too large, split in half",
+                        self.target_chain_id,
+                        window.0,
+                        window.1,
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    // Requested again by the next call.
+                    self.windows.windows.push_front(window);
+                    return Err(err);
+                }
+            };
+
+            let mut tx_ids: HashSet<String> = HashSet::new();
+            let mut new_txs: Vec<CrossTxRawData> = vec![];
+            for mut tx in txs {
+                if self.last_tx_ids.contains(&tx.target_id) || !tx_ids.insert(tx.target_id.clone())
+                {
+                    continue;
+                }
+                event!(Level::DEBUG, "tx: {:?}", tx);
                 // TODO: What is the purpose of adding delay_timestamp here?
-                tx.target_time = tx.target_time + delay_timestamp * 1000;
+                tx.target_time = tx.target_time + self.delay_timestamp * 1000;
                 new_txs.push(tx);
             }
-            return Ok(new_txs);
-        } else {
-            return Err(anyhow::anyhow!("err: {:#?}", res.text().await?));
+            self.last_tx_ids = tx_ids;
+            event!(
+                Level::DEBUG,
+                "start_timestamp: {}, end_timestamp: {}, chain id: {}, txs count: {}",
+                window.0,
+                window.1,
+                self.target_chain_id,
+                new_txs.len()
+            );
+            return Ok(Some(new_txs));
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TxsResponse {
+    result: HashMap<String, Vec<CrossTxRawData>>,
+}
+
+/// A blocking `Read` over the chunks of a response, sent by the task that reads it. Ends when the
+/// sender is dropped.
+struct ChunkReader<B> {
+    chunks: tokio::sync::mpsc::Receiver<B>,
+    chunk: Option<B>,
+    pos: usize,
+}
+
+impl<B> ChunkReader<B> {
+    fn new(chunks: tokio::sync::mpsc::Receiver<B>) -> Self {
+        Self {
+            chunks,
+            chunk: None,
+            pos: 0,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> std::io::Read for ChunkReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.chunk {
+                let rest = &chunk.as_ref()[self.pos..];
+                if !rest.is_empty() {
+                    let n = min(rest.len(), buf.len());
+                    buf[..n].copy_from_slice(&rest[..n]);
+                    self.pos += n;
+                    return Ok(n);
+                }
+            }
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk);
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
         }
     }
 }
 
+#[derive(Debug)]
+pub struct ResponseTooLarge(pub usize);
+
+impl std::fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "txs response is larger than {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
 pub struct SupportChains {
     url: String,
     headers: HeaderMap,
@@ -122,6 +348,7 @@ pub struct SupportChains {
     #[allow(dead_code)]
     method: String,
     client: Client,
+    retry_policy: RetryPolicy,
 }
 
 impl SupportChains {
@@ -141,10 +368,29 @@ impl SupportChains {
             headers,
             method,
             client,
+            retry_policy: RetryPolicy::default(),
         }
     }
 
     pub async fn get_mainnet_support_tokens(&self) -> anyhow::Result<Vec<Address>> {
+        retry(
+            SUPPORT_CHAINS_SOURCE_ENDPOINT,
+            &self.retry_policy,
+            move || self.request_mainnet_support_tokens(),
+        )
+        .await
+    }
+
+    pub async fn get_support_chains(&self) -> anyhow::Result<Vec<u64>> {
+        retry(
+            SUPPORT_CHAINS_SOURCE_ENDPOINT,
+            &self.retry_policy,
+            move || self.request_support_chains(),
+        )
+        .await
+    }
+
+    async fn request_mainnet_support_tokens(&self) -> anyhow::Result<Vec<Address>> {
         let graphql_url = &self.url;
         let query = format!(
             r#"{{tokenRels (where: {{chainId: "{}"}}) {{tokenAddress}}}}"#,
@@ -174,13 +420,16 @@ impl SupportChains {
                 }
             }
         } else {
-            println!("err: {:?}", res.status());
+            return Err(anyhow::anyhow!(
+                "get support tokens err: {:?}",
+                res.status()
+            ));
         }
         tokens.push(Address::default());
         Ok(tokens)
     }
 
-    pub async fn get_support_chains(&self) -> anyhow::Result<Vec<u64>> {
+    async fn request_support_chains(&self) -> anyhow::Result<Vec<u64>> {
         let graphql_url = &self.url;
         let query = r#"
     {
@@ -204,11 +453,14 @@ impl SupportChains {
             let res: &Value = &res["data"]["chainRels"];
             let cs: Vec<ChainIds> = serde_json::from_value(res.clone())?;
             for i in cs {
-                chains.push(i.id.parse::<u64>().unwrap());
+                chains.push(i.id.parse::<u64>()?);
             }
             return Ok(chains);
         }
-        Ok(chains)
+        Err(anyhow::anyhow!(
+            "get support chains err: {:?}",
+            res.status()
+        ))
     }
 }
 
@@ -216,7 +468,7 @@ pub fn calculate_profit(percent: u64, tx: CrossTxData) -> CrossTxProfit {
     let profit = tx.profit;
     let profit = profit * U256::from(percent) / U256::from(100_0000);
     event!(
-        Level::INFO,
+        Level::DEBUG,
         "calculate_profit dealer: {:?}, maker: {:?}, profit: {:?}",
         tx.dealer_address,
         tx.source_maker,
@@ -231,6 +483,16 @@ pub fn calculate_profit(percent: u64, tx: CrossTxData) -> CrossTxProfit {
     }
 }
 
+/// Find the block `i` with `timestamp(i) <= target_time < timestamp(i + 1)` in blocks sorted by number.
+/// `target_time` is in milliseconds.
+pub fn find_target_block(block_infos: &[BlockInfo], target_time: u64) -> Option<&BlockInfo> {
+    let i = block_infos.partition_point(|b| b.storage.block_timestamp * 1000 <= target_time);
+    if i == 0 || i == block_infos.len() {
+        return None;
+    }
+    Some(&block_infos[i - 1])
+}
+
 pub fn get_one_block_txs_hash(mut txs: Vec<H256>) -> H256 {
     if txs.is_empty() {
         return H256::zero();
@@ -253,8 +515,131 @@ pub fn convert_string_to_hash(tx: String) -> H256 {
 
 #[cfg(test)]
 pub mod test {
-    use crate::{convert_string_to_hash, funcs::TxsCrawler, get_one_block_txs_hash, SupportChains};
+    use crate::{
+        convert_string_to_hash,
+        funcs::{
+            find_target_block, ChunkReader, ResponseTooLarge, TxsCrawler, TxsRequest, TxsResponse,
+            TxsWindows,
+        },
+        get_one_block_txs_hash, SupportChains,
+    };
+    use primitives::types::{BlockInfo, BlockStorage, CrossTxRawData};
     use sparse_merkle_tree::H256;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_find_target_block() {
+        let block_infos: Vec<BlockInfo> = (0..5u64)
+            .map(|i| BlockInfo {
+                storage: BlockStorage {
+                    block_number: 100 + i,
+                    block_timestamp: 1000 + i * 12,
+                    ..Default::default()
+                },
+                events: vec![],
+            })
+            .collect();
+        let number = |t: u64| find_target_block(&block_infos, t).map(|b| b.storage.block_number);
+
+        assert_eq!(number(999_999), None);
+        assert_eq!(number(1_000_000), Some(100));
+        assert_eq!(number(1_011_999), Some(100));
+        assert_eq!(number(1_012_000), Some(101));
+        assert_eq!(number(1_047_999), Some(103));
+        // The last block's window is still open.
+        assert_eq!(number(1_048_000), None);
+    }
+
+    #[test]
+    fn test_txs_windows() {
+        let mut windows = TxsWindows::new(0, 25_000, 10_000);
+        assert_eq!(windows.pop(), Some((0, 10_000)));
+        assert_eq!(windows.pop(), Some((10_000, 20_000)));
+        assert!(windows.split((10_000, 20_000)));
+        assert_eq!(windows.pop(), Some((10_000, 15_000)));
+        assert_eq!(windows.pop(), Some((15_000, 20_000)));
+        assert_eq!(windows.pop(), Some((20_000, 25_000)));
+        assert_eq!(windows.pop(), None);
+        assert!(!windows.split((0, 1000)));
+        assert_eq!(
+            TxsWindows::new(5000, 5000, 10_000),
+            TxsWindows {
+                windows: vec![(5000, 5000)].into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let tx = CrossTxRawData {
+            target_id: "0x1".to_string(),
+            target_time: 1000,
+            ..Default::default()
+        };
+        let body =
+            serde_json::to_vec(&serde_json::json!({ "result": { "5": [tx], "7": [] } })).unwrap();
+        let (sender, chunks) = tokio::sync::mpsc::channel(body.len());
+        for chunk in body.chunks(5) {
+            sender.try_send(chunk.to_vec()).unwrap();
+        }
+        drop(sender);
+        let mut res: TxsResponse = serde_json::from_reader(ChunkReader::new(chunks)).unwrap();
+        assert_eq!(res.result.remove("5").unwrap(), vec![tx]);
+        assert!(res.result.remove("7").unwrap().is_empty());
+
+        // A body cut short fails to parse.
+        let (sender, chunks) = tokio::sync::mpsc::channel(1);
+        sender.try_send(body[..20].to_vec()).unwrap();
+        drop(sender);
+        assert!(serde_json::from_reader::<_, TxsResponse>(ChunkReader::new(chunks)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_txs_request_split() {
+        let crawler = TxsCrawler::new("http://127.0.0.1:1".to_string());
+        let mut request = TxsRequest {
+            crawler: &crawler,
+            target_chain_id: 1,
+            delay_timestamp: 0,
+            windows: TxsWindows::new(0, 8000, 8000),
+            last_tx_ids: HashSet::new(),
+        };
+        // Too large over 2 seconds. One tx a second, on the boundaries of the windows.
+        let requested = std::sync::Mutex::new(vec![]);
+        let request_window = |start: u64, end: u64| {
+            requested.lock().unwrap().push((start, end));
+            async move {
+                if end - start > 2000 {
+                    return Err(anyhow::Error::new(ResponseTooLarge(0)));
+                }
+                Ok((start / 1000..=end / 1000)
+                    .map(|s| CrossTxRawData {
+                        target_id: s.to_string(),
+                        target_time: s * 1000,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<CrossTxRawData>>())
+            }
+        };
+        let mut txs = vec![];
+        while let Some(window) = request.next_window_with(request_window).await.unwrap() {
+            txs.extend(window.into_iter().map(|tx| tx.target_time / 1000));
+        }
+        assert_eq!(
+            requested.into_inner().unwrap(),
+            vec![
+                (0, 8000),
+                (0, 4000),
+                (0, 2000),
+                (2000, 4000),
+                (4000, 8000),
+                (4000, 6000),
+                (6000, 8000)
+            ]
+        );
+        // The txs on the shared boundaries are handed out once.
+        assert_eq!(txs, (0..=8).collect::<Vec<u64>>());
+    }
 
     #[tokio::test]
     async fn test() {
@@ -285,7 +670,11 @@ pub mod test {
         let op = 420;
         let start = end - duration;
         // let start = 1695023676;
-        let a = s.request_txs(op, start, end, 0).await.unwrap();
+        let mut request = s.request_txs(op, start, end, 0).unwrap();
+        let mut a = vec![];
+        while let Some(txs) = request.next_window().await.unwrap() {
+            a.extend(txs);
+        }
         println!("a: {:?}", a);
         println!("len: {:?}", a.len());
         for tx in a {
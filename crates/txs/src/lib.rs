@@ -2,29 +2,45 @@
 #![allow(unused_imports)]
 #![allow(unused_assignments)]
 
+pub mod archive;
+pub mod audit;
+pub mod checkpoint;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
 pub mod funcs;
+pub mod prune;
 pub mod rocks_db;
+pub mod schema;
 pub mod sled_db;
+pub mod stats;
+pub mod withdraw;
 
 use crate::funcs::{SupportChains, TxsCrawler};
 use contract::SubmitterContract;
 use ethers::types::{Address, H160, U256};
 use primitives::{env::get_block_infos_batch, error::Error};
 
-use funcs::{calculate_profit, convert_string_to_hash, get_one_block_txs_hash};
+use archive::Archiver;
+use funcs::{calculate_profit, convert_string_to_hash, find_target_block, get_one_block_txs_hash};
 use hex;
+use primitives::storage::{Storage, Tree};
 use primitives::{
     chain_type::get_chain_type,
     constants::ETH_DELAY_BLOCKS,
     env::{
         get_chains_info_source_url, get_delay_seconds_by_chain_type, get_mainnet_chain_id,
-        get_txs_source_url,
+        get_reconcile_horizon_blocks, get_reconcile_interval_seconds, get_support_set_epoch_blocks,
+        get_support_set_genesis_chains, get_support_set_genesis_tokens,
+        get_support_set_refresh_seconds, get_txs_retain_roots, get_txs_source_url,
     },
     func::{block_number_convert_to_h256, chain_token_address_convert_to_h256, tx_compare},
+    retry::RetryPolicy,
     traits::{Contract as ContractTrait, StataTrait},
     types::{
-        BlockInfo, BlocksStateData, Chain, ChainType, CrossTxData, CrossTxProfit, Debt, Event,
-        FeeManagerDuration, ProfitStateData, WithdrawEvent,
+        ArchivedBlock, BalanceFilter, BalancePage, BlockInfo, BlocksStateData, Chain, ChainType,
+        CrossTxData, CrossTxProfit, CrossTxRawData, Debt, Event, FeeManagerDuration, MissedTx,
+        NewRoot, ProfitAt, ProfitBalance, ProfitStateData, ProfitStateDiff, SubmitterEvent,
+        SupportSet, TxOutcome, TxSkipReason, WithdrawEvent,
     },
 };
 use reqwest::{
@@ -34,16 +50,15 @@ use reqwest::{
 use rocks_db::*;
 use rocksdb::{
     ops::{Flush, Iterate, Open, WriteOps},
-    IteratorMode, Options, ReadOptions, WriteBatch, DB,
+    IteratorMode, OptimisticTransaction, Options, ReadOptions, WriteBatch, DB,
 };
 use serde_json::{json, Value};
-use sled::{self, Db, Tree};
 use sled_db::*;
 use sparse_merkle_tree::H256;
 use state::{Keccak256Hasher, State};
 use std::{
     cmp::{max, min},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     str::FromStr,
     sync::{Arc, RwLock},
     time::Duration,
@@ -52,14 +67,18 @@ use tokio::sync::broadcast::{Receiver, Sender};
 use tracing::{event, info, level_to_log, span, Level};
 use utils::vec_unique;
 
+// Seconds before a failed task is started again, see `keep_running`.
+const TASK_RESTART_SECONDS: u64 = 10;
+
 pub struct Submitter {
     profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
     blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
-    sled_db: Arc<Db>,
+    storage: Arc<Storage>,
     rocks_db: Arc<TxsRocksDB>,
     contract: Arc<SubmitterContract>,
     start_block: Arc<RwLock<u64>>,
     db_path: String,
+    events: Sender<SubmitterEvent>,
 }
 
 impl Submitter {
@@ -68,19 +87,21 @@ impl Submitter {
         blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
         contract: Arc<SubmitterContract>,
         start_block: Arc<RwLock<u64>>,
-        sled_db: Arc<Db>,
+        storage: Arc<Storage>,
         rocks_db: Arc<TxsRocksDB>,
         db_path: String,
+        events: Sender<SubmitterEvent>,
     ) -> Self {
         event!(Level::INFO, "rocks db is ready.");
         Self {
             profit_state,
             blocks_state,
-            sled_db,
+            storage,
             rocks_db,
             contract,
             start_block,
             db_path,
+            events,
         }
     }
 
@@ -93,41 +114,134 @@ impl Submitter {
         .await
         .unwrap();
 
-        tokio::spawn(crawl_block_info(
-            self.contract.sender.subscribe(),
-            self.sled_db.clone(),
-            self.start_block.clone(),
-            self.contract.clone(),
-        ));
-        tokio::spawn(crawl_txs_and_calculate_profit_for_per_block(
-            self.sled_db.clone(),
+        // Each task is started again if it fails, so one failed read or write, e.g. a commit that
+        // lost to another task, does not stop it for good.
+        let (storage, txs_db, contract, start_block) = (
+            self.storage.clone(),
             self.rocks_db.clone(),
-            self.db_path.clone(),
-            self.start_block.clone(),
             self.contract.clone(),
-        ));
-        tokio::spawn(submit_root(
-            self.contract.sender.subscribe(),
-            self.sled_db.clone(),
-            self.rocks_db.clone(),
+            self.start_block.clone(),
+        );
+        let (profit_state, blocks_state, events) = (
             self.profit_state.clone(),
             self.blocks_state.clone(),
-            self.contract.clone(),
-            self.start_block.clone(),
-        ));
+            self.events.clone(),
+        );
+        let db_path = self.db_path.clone();
+
+        tokio::spawn(keep_running("block info crawler", {
+            let (storage, start_block, contract) =
+                (storage.clone(), start_block.clone(), contract.clone());
+            move || {
+                crawl_block_info(
+                    contract.sender.subscribe(),
+                    storage.clone(),
+                    start_block.clone(),
+                    contract.clone(),
+                )
+            }
+        }));
+        tokio::spawn(keep_running("txs crawler", {
+            let (storage, txs_db, blocks_state, start_block, contract) = (
+                storage.clone(),
+                txs_db.clone(),
+                blocks_state.clone(),
+                start_block.clone(),
+                contract.clone(),
+            );
+            move || {
+                crawl_txs_and_calculate_profit_for_per_block(
+                    storage.clone(),
+                    txs_db.clone(),
+                    blocks_state.clone(),
+                    db_path.clone(),
+                    start_block.clone(),
+                    contract.clone(),
+                )
+            }
+        }));
+        tokio::spawn(keep_running("support set refresher", {
+            let (storage, contract) = (storage.clone(), contract.clone());
+            move || refresh_support_set(storage.clone(), contract.clone())
+        }));
+        tokio::spawn(keep_running("late txs reconciler", {
+            let (storage, txs_db, blocks_state, start_block, contract) = (
+                storage.clone(),
+                txs_db.clone(),
+                blocks_state.clone(),
+                start_block.clone(),
+                contract.clone(),
+            );
+            move || {
+                reconcile_late_txs(
+                    storage.clone(),
+                    txs_db.clone(),
+                    blocks_state.clone(),
+                    start_block.clone(),
+                    contract.clone(),
+                )
+            }
+        }));
+        if get_txs_retain_roots() > 0 {
+            tokio::spawn(keep_running("txs pruner", {
+                let (storage, txs_db, blocks_state) =
+                    (storage.clone(), txs_db.clone(), blocks_state.clone());
+                move || {
+                    prune::prune_txs_periodically(
+                        storage.clone(),
+                        txs_db.clone(),
+                        blocks_state.clone(),
+                    )
+                }
+            }));
+        }
+        tokio::spawn(keep_running("root submitter", move || {
+            submit_root(
+                contract.sender.subscribe(),
+                storage.clone(),
+                txs_db.clone(),
+                profit_state.clone(),
+                blocks_state.clone(),
+                contract.clone(),
+                start_block.clone(),
+                events.clone(),
+            )
+        }));
         event!(Level::INFO, "submitter is ready.");
         std::future::pending::<()>().await;
         Ok(())
     }
 }
 
+/// Run the task made by `task` until it returns. If it fails, the error is logged and it is made
+/// and run again after `TASK_RESTART_SECONDS`.
+async fn keep_running<F, Fut>(name: &'static str, mut task: F)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<()>>,
+{
+    loop {
+        match task().await {
+            Ok(()) => return,
+            Err(err) => event!(
+                Level::ERROR,
+                "{} failed, restarting in {:}s. err: {:?}",
+                name,
+                TASK_RESTART_SECONDS,
+                err
+            ),
+        }
+        tokio::time::sleep(Duration::from_secs(TASK_RESTART_SECONDS)).await;
+    }
+}
+
 async fn crawl_block_info(
     mut newest_block_receiver: Receiver<BlockInfo>,
-    sled_db: Arc<Db>,
+    storage: Arc<Storage>,
     start_block: Arc<RwLock<u64>>,
     contract: Arc<SubmitterContract>,
 ) -> anyhow::Result<()> {
-    let block_info_db = ContractBlockInfoDB::new(sled_db.clone())?;
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
     let mut from_block = 0u64;
     {
         from_block = start_block.read().unwrap().clone();
@@ -140,8 +254,8 @@ async fn crawl_block_info(
 
     event!(Level::INFO, "block info crawler is ready.");
 
-    let profit_statistic_db = ProfitStatisticsDB::new(sled_db.clone())?;
-    let user_tokens_db = UserTokensDB::new(sled_db.clone())?;
+    let profit_statistic_db = ProfitStatisticsDB::new(storage.clone())?;
+    let user_tokens_db = UserTokensDB::new(storage.clone())?;
     loop {
         if let Ok(newest_block) = newest_block_receiver.recv().await {
             let end_block = newest_block.storage.block_number - ETH_DELAY_BLOCKS;
@@ -178,44 +292,52 @@ async fn crawl_block_info(
                 }
 
                 for bi in block_infos {
-                    block_info_db.insert_block_info(bi.storage.block_number, bi.clone())?;
+                    // The statistics are also written by the archiver, so the block goes in one
+                    // transaction that is run again if they conflict.
+                    storage.transact(|db_tx| -> anyhow::Result<()> {
+                        block_info_db.insert_block_info_in(db_tx, bi.storage.block_number, &bi)?;
+                        for e in bi.events.iter() {
+                            match e {
+                                Event::Withdraw(w_e) => {
+                                    user_tokens_db.insert_token_in(
+                                        db_tx,
+                                        w_e.address,
+                                        w_e.chain_id,
+                                        w_e.token_address,
+                                    )?;
+                                    profit_statistic_db.update_total_withdraw_in(
+                                        db_tx,
+                                        w_e.address,
+                                        w_e.chain_id,
+                                        w_e.token_address,
+                                        w_e.balance,
+                                    )?;
+                                }
+                                Event::Deposit(d_e) => {
+                                    user_tokens_db.insert_token_in(
+                                        db_tx,
+                                        d_e.address,
+                                        d_e.chain_id,
+                                        d_e.token_address,
+                                    )?;
+                                    profit_statistic_db.update_total_deposit_in(
+                                        db_tx,
+                                        d_e.address,
+                                        d_e.chain_id,
+                                        d_e.token_address,
+                                        d_e.balance,
+                                    )?;
+                                }
+                            }
+                        }
+                        Ok(())
+                    })?;
 
                     event!(
                         Level::INFO,
                         "Block #{:} info is saved.",
                         bi.storage.block_number,
                     );
-
-                    for e in bi.events {
-                        match e {
-                            Event::Withdraw(w_e) => {
-                                user_tokens_db.insert_token(
-                                    w_e.address,
-                                    w_e.chain_id,
-                                    w_e.token_address,
-                                )?;
-                                profit_statistic_db.update_total_withdraw(
-                                    w_e.address,
-                                    w_e.chain_id,
-                                    w_e.token_address,
-                                    w_e.balance,
-                                )?;
-                            }
-                            Event::Deposit(d_e) => {
-                                user_tokens_db.insert_token(
-                                    d_e.address,
-                                    d_e.chain_id,
-                                    d_e.token_address,
-                                )?;
-                                profit_statistic_db.update_total_deposit(
-                                    d_e.address,
-                                    d_e.chain_id,
-                                    d_e.token_address,
-                                    d_e.balance,
-                                )?;
-                            }
-                        }
-                    }
                 }
 
                 from_block = to_block + 1;
@@ -225,30 +347,28 @@ async fn crawl_block_info(
 }
 
 async fn crawl_txs_and_calculate_profit_for_per_block(
-    sled_db: Arc<Db>,
+    storage: Arc<Storage>,
     txs_db: Arc<TxsRocksDB>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
     db_path: String,
     start_block: Arc<RwLock<u64>>,
     contract: Arc<SubmitterContract>,
 ) -> anyhow::Result<()> {
-    let block_info_db = ContractBlockInfoDB::new(sled_db.clone())?;
-    let block_txs_count_db = BlockTxsCountDB::new(sled_db.clone())?;
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let block_txs_count_db = BlockTxsCountDB::new(storage.clone())?;
+    let missed_txs_db = MissedTxsDB::new(storage.clone())?;
     let mut from_block = start_block.read().unwrap().clone();
 
     if from_block == 0 {
         unreachable!()
     }
 
-    let maker_profit_db = MakerProfitDB::new(sled_db.clone())?;
-    let support_chains: Vec<u64> = vec_unique(
-        SupportChains::new(get_chains_info_source_url())
-            .get_support_chains()
-            .await?,
-    );
-    println!("support chains: {:?}", support_chains);
-    if support_chains.is_empty() {
-        panic!("support chains is empty.");
-    }
+    let maker_profit_db = MakerProfitDB::new(storage.clone())?;
+    let pending_txs_db = PendingTxsDB::new(storage.clone())?;
+    let retry_policy = RetryPolicy::default();
+    let mut request_failures = 0u32;
+    let support_set_db = SupportSetDB::new(storage.clone())?;
+    let tx_audit_db = TxAuditDB::new(storage.clone())?;
 
     event!(Level::INFO, "txs crawler is ready.");
 
@@ -278,7 +398,38 @@ async fn crawl_txs_and_calculate_profit_for_per_block(
         let from_block_info = block_infos[0].clone();
         let to_block_info = block_infos[block_infos.len() - 1].clone();
 
-        let mut new_txs_map: HashMap<u64, Vec<(CrossTxData, CrossTxProfit)>> = HashMap::new();
+        let support_chains = support_set_db.get_support_chains_in_range(
+            from_block_info.storage.block_number,
+            to_block_info.storage.block_number,
+        )?;
+        if support_chains.is_empty() {
+            event!(
+                Level::WARN,
+                "Block #{:} - #{:}, support chains is empty.",
+                from_block_info.storage.block_number,
+                to_block_info.storage.block_number,
+            );
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            continue;
+        }
+
+        // Txs queued earlier because their target block had not been crawled yet.
+        let retried_txs =
+            pending_txs_db.get_txs_before(to_block_info.storage.block_timestamp * 1000)?;
+        let mut seen_txs: HashSet<H256> = retried_txs.iter().map(|tx| tx.target_id).collect();
+        let mut txs: Vec<CrossTxData> = retried_txs.clone();
+        // Source records of the txs crawled in this window, the retried ones are recorded already.
+        let mut raws: HashMap<H256, CrossTxRawData> = HashMap::new();
+        if !retried_txs.is_empty() {
+            event!(
+                Level::INFO,
+                "Block #{:} - #{:}, retrying {:} pending txs.",
+                from_block_info.storage.block_number,
+                to_block_info.storage.block_number,
+                retried_txs.len(),
+            );
+        }
+
         let mut chain_count = 0;
         while chain_count < support_chains.len() {
             let chain = support_chains[chain_count];
@@ -292,173 +443,864 @@ async fn crawl_txs_and_calculate_profit_for_per_block(
                 to_block_info.storage.block_timestamp
             );
 
-            let result = TxsCrawler::new(get_txs_source_url())
-                .request_txs(
+            // Each window of txs is taken in as it arrives. After a failure the chain is requested
+            // again, and the txs taken in already are skipped.
+            let crawler = TxsCrawler::new(get_txs_source_url());
+            let mut count = 0;
+            let result: anyhow::Result<()> = async {
+                let mut request = crawler.request_txs(
                     chain,
                     from_block_info.storage.block_timestamp,
                     to_block_info.storage.block_timestamp,
                     get_delay_seconds_by_chain_type(get_chain_type(chain)),
-                )
-                .await;
+                )?;
+                while let Some(raw_txs) = request.next_window().await? {
+                    for raw_tx in raw_txs {
+                        let tx: CrossTxData = raw_tx.clone().into();
+                        if seen_txs.insert(tx.target_id) {
+                            raws.insert(tx.target_id, raw_tx);
+                            txs.push(tx);
+                            count += 1;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            .await;
             if let Err(err) = result {
+                request_failures += 1;
+                let delay = retry_policy.backoff(request_failures);
                 event!(
                     Level::WARN,
-                    "Get txs err: {:?}. start: {:?}, end: {:?}. chain_id: {:?}",
+                    "Get txs err: {:?}. start: {:?}, end: {:?}. chain_id: {:?}, retry in {:?}",
                     err,
                     from_block_info.storage.block_timestamp,
                     to_block_info.storage.block_timestamp,
-                    chain
+                    chain,
+                    delay
                 );
-                tokio::time::sleep(Duration::from_secs(3)).await;
+                tokio::time::sleep(delay).await;
                 continue;
             }
 
+            request_failures = 0;
             chain_count += 1;
-            let txs = result.unwrap();
-            if !txs.is_empty() {
+            if count > 0 {
                 event!(
                     Level::INFO,
                     "successfully obtained {:} pieces of txs from chain {:}",
-                    txs.clone().len(),
+                    count,
                     chain,
                 );
             }
+        }
 
-            let mut tx_index = 0;
-            while tx_index < txs.len() {
-                let tx: CrossTxData = txs[tx_index].clone().into();
-                tx_index += 1;
+        let mut new_txs_map: HashMap<u64, Vec<(CrossTxData, CrossTxProfit)>> = HashMap::new();
+        let mut deferred_txs: Vec<CrossTxData> = vec![];
+        let mut outcomes: Vec<(H256, TxOutcome, Option<u64>)> = vec![];
+        let mut tx_index = 0;
+        while tx_index < txs.len() {
+            let tx: CrossTxData = txs[tx_index].clone();
+            tx_index += 1;
 
-                // Check support chains
-                if let None = support_chains.iter().position(|p| p == &tx.target_chain) {
+            // Find target block
+            let target_block_info = match find_target_block(&block_infos, tx.target_time) {
+                Some(bi) => bi.clone(),
+                None if tx.target_time >= to_block_info.storage.block_timestamp * 1000 => {
+                    // The target block is not crawled yet, assign it later.
                     event!(
-                        Level::WARN,
-                        "target chain id {:} is not support, continue",
-                        tx.target_chain,
-                    );
-                    continue;
-                }
-                if let None = support_chains.iter().position(|p| p == &tx.source_chain) {
-                    event!(
-                        Level::WARN,
-                        "source chain id {:} is not support, continue",
-                        tx.source_chain,
+                        Level::INFO,
+                        "Target block not crawled yet, queue tx: {:}, target time: {:}",
+                        hex::encode(tx.target_id.as_slice()),
+                        tx.target_time,
                     );
+                    outcomes.push((tx.target_id, TxOutcome::Pending, None));
+                    deferred_txs.push(tx);
                     continue;
                 }
-
-                // Find target block
-                let mut _option: Option<BlockInfo> = None;
-                for i in 0..(block_infos.len() - 1) {
-                    if tx.target_time >= block_infos[i].storage.block_timestamp * 1000
-                        && tx.target_time < block_infos[i + 1].storage.block_timestamp * 1000
-                    {
-                        _option = Some(block_infos[i].clone());
+                None => match block_info_db.get_target_block_num(tx.target_time / 1000)? {
+                    Some(bn) => match block_info_db.get_block_info(bn)? {
+                        Some(bi) => bi,
+                        None => {
+                            outcomes.push((
+                                tx.target_id,
+                                TxOutcome::Skipped(TxSkipReason::MissingBlock),
+                                Some(bn),
+                            ));
+                            continue;
+                        }
+                    },
+                    None => {
+                        event!(
+                            Level::WARN,
+                            "Target block not found, target id: {:}",
+                            hex::encode(tx.target_id.as_slice()),
+                        );
+                        outcomes.push((
+                            tx.target_id,
+                            TxOutcome::Skipped(TxSkipReason::MissingBlock),
+                            None,
+                        ));
+                        continue;
                     }
+                },
+            };
+
+            // Check support chains at the target block
+            let block_support_chains = support_set_db
+                .get_support_set(target_block_info.storage.block_number)?
+                .map(|s| s.chains)
+                .unwrap_or_default();
+            if !block_support_chains.contains(&tx.target_chain) {
+                event!(
+                    Level::WARN,
+                    "target chain id {:} is not support, continue",
+                    tx.target_chain,
+                );
+                outcomes.push((
+                    tx.target_id,
+                    TxOutcome::Skipped(TxSkipReason::UnsupportedTargetChain),
+                    Some(target_block_info.storage.block_number),
+                ));
+                continue;
+            }
+            if !block_support_chains.contains(&tx.source_chain) {
+                event!(
+                    Level::WARN,
+                    "source chain id {:} is not support, continue",
+                    tx.source_chain,
+                );
+                outcomes.push((
+                    tx.target_id,
+                    TxOutcome::Skipped(TxSkipReason::UnsupportedSourceChain),
+                    Some(target_block_info.storage.block_number),
+                ));
+                continue;
+            }
+
+            let token = tx.source_token;
+            let dealer = tx.dealer_address;
+
+            // Get percent
+            let percent = match get_dealer_percent(
+                &maker_profit_db,
+                &contract,
+                dealer,
+                target_block_info.storage.block_number,
+                token,
+            )
+            .await
+            {
+                Ok(p) => {
+                    request_failures = 0;
+                    p
                 }
-                if _option.is_none() {
+                Err(err) => {
+                    // Retry
+                    request_failures += 1;
+                    let delay = retry_policy.backoff(request_failures);
                     event!(
                         Level::WARN,
-                        "Target block not found, target id: {:}",
-                        hex::encode(tx.target_id.as_slice()),
+                        "Block #{:} - get dealer {:} percent err: {:?}, retry in {:?}",
+                        target_block_info.storage.block_number,
+                        dealer,
+                        err,
+                        delay
                     );
+                    tokio::time::sleep(delay).await;
+                    tx_index -= 1;
                     continue;
                 }
-                let target_block_info = _option.unwrap();
-
-                let token = tx.source_token;
-                let dealer = tx.dealer_address;
-
-                // Get percent
-                let mut percent = 0u64;
-                if let Some(p) = maker_profit_db.get_percent(
-                    dealer,
-                    target_block_info.storage.block_number,
-                    token,
-                )? {
-                    percent = p;
-                } else {
-                    if let Ok(p) = contract
-                        .get_dealer_profit_percent_by_block(
-                            dealer,
-                            target_block_info.storage.block_number,
-                            get_mainnet_chain_id(),
-                            token,
-                        )
-                        .await
-                    {
-                        maker_profit_db.insert_percent(
-                            dealer,
-                            target_block_info.storage.block_number,
-                            token,
-                            p,
-                        )?;
-                        percent = p;
-                    } else {
-                        // Retry
-                        tx_index -= 1;
-                        continue;
-                    }
-                };
+            };
 
-                let profit = calculate_profit(percent as u64, tx.clone());
-                event!(
-                    Level::INFO,
-                    "Block #{:} - dealer {:}, profit percent: {:?}, tx: {:}, profit: {:?}",
-                    target_block_info.storage.block_number,
-                    dealer,
-                    percent,
-                    hex::encode(tx.target_id.as_slice()),
-                    profit,
-                );
+            let profit = calculate_profit(percent as u64, tx.clone());
+            event!(
+                Level::DEBUG,
+                "Block #{:} - dealer {:}, profit percent: {:?}, tx: {:}, profit: {:?}",
+                target_block_info.storage.block_number,
+                dealer,
+                percent,
+                hex::encode(tx.target_id.as_slice()),
+                profit,
+            );
 
-                if new_txs_map
-                    .get(&(target_block_info.storage.block_number))
-                    .is_none()
-                {
-                    new_txs_map.insert(target_block_info.storage.block_number, Vec::new());
-                }
-                let value = new_txs_map
-                    .get_mut(&(target_block_info.storage.block_number))
-                    .unwrap();
-                value.push((tx.clone(), profit.clone()));
-            }
+            outcomes.push((
+                tx.target_id,
+                TxOutcome::Assigned,
+                Some(target_block_info.storage.block_number),
+            ));
+            new_txs_map
+                .entry(target_block_info.storage.block_number)
+                .or_insert_with(Vec::new)
+                .push((tx.clone(), profit.clone()));
         }
 
-        // Save to db
-        for bi in block_infos {
-            let mut new_txs = vec![];
-            if let Some(_txs) = new_txs_map.get(&bi.storage.block_number) {
-                new_txs = _txs.clone();
+        // Save to db. The txs, the counts and the pending txs of the window are committed
+        // together, so a crash can not leave a block counted without its txs.
+        let last_update_block = block_info_db
+            .get_newest_block_info()?
+            .map_or(0, |bi| bi.storage.last_update_block);
+        // The txs of the blocks of this window, and the late txs of blocks saved by an earlier
+        // window.
+        let window_txs: Vec<(u64, Vec<(CrossTxData, CrossTxProfit)>)> = block_infos
+            .iter()
+            .map(|bi| {
+                let block_num = bi.storage.block_number;
+                (
+                    block_num,
+                    new_txs_map.remove(&block_num).unwrap_or_default(),
+                )
+            })
+            .collect();
+        // Held until the window is committed, so the blocks of the late txs are not archived in
+        // between.
+        let blocks = blocks_state.read().unwrap();
+        // Run again if another task wrote the same keys meanwhile, e.g. the reconciler a count.
+        let late_saved = storage.transact(|db_tx| -> anyhow::Result<_> {
+            for (block_num, new_txs) in window_txs.iter() {
+                txs_db.insert_txs_in(db_tx, new_txs.clone())?;
+                block_txs_count_db.insert_count_in(db_tx, *block_num, new_txs.len() as u64)?;
+            }
+            let mut late_saved = vec![];
+            let mut missed_ids: HashSet<H256> = HashSet::new();
+            for (block_num, late_txs) in new_txs_map.iter() {
+                let (folded, missed) = save_late_txs_in(
+                    db_tx,
+                    &blocks,
+                    &txs_db,
+                    &block_txs_count_db,
+                    &missed_txs_db,
+                    *block_num,
+                    last_update_block,
+                    late_txs.clone(),
+                )?;
+                missed_ids.extend(missed.iter().cloned());
+                late_saved.push((*block_num, folded.len(), missed.len()));
+            }
+
+            for tx in retried_txs.iter() {
+                pending_txs_db.remove_tx_in(db_tx, tx)?;
+            }
+            for tx in deferred_txs.iter() {
+                pending_txs_db.insert_tx_in(db_tx, tx)?;
             }
+            for (target_id, outcome, target_block) in outcomes.iter() {
+                let outcome = match missed_ids.contains(target_id) {
+                    true => TxOutcome::Missed,
+                    false => outcome.clone(),
+                };
+                tx_audit_db.record_in(
+                    db_tx,
+                    *target_id,
+                    raws.get(target_id).cloned(),
+                    outcome,
+                    *target_block,
+                )?;
+            }
+            Ok(late_saved)
+        })?;
+        drop(blocks);
 
-            txs_db.insert_txs(new_txs.clone())?;
-            block_txs_count_db.insert_count(bi.storage.block_number, new_txs.len() as u64)?;
+        for (block_num, new_txs) in window_txs.iter() {
             event!(
                 Level::INFO,
                 "Block #{:} - txs are saved. count: {:?}",
-                bi.storage.block_number,
+                block_num,
                 new_txs.len()
             );
         }
+        for (block_num, folded, missed) in late_saved {
+            event!(
+                Level::WARN,
+                "Block #{:} - late txs are saved. folded: {:?}, missed: {:?}",
+                block_num,
+                folded,
+                missed
+            );
+        }
 
         from_block = to_block_info.storage.block_number;
     }
 }
 
+async fn request_support_set() -> anyhow::Result<(Vec<u64>, Vec<Address>)> {
+    let support_chains = SupportChains::new(get_chains_info_source_url());
+    let chains = vec_unique(support_chains.get_support_chains().await?);
+    let tokens = vec_unique(support_chains.get_mainnet_support_tokens().await?);
+    if chains.is_empty() {
+        return Err(anyhow::anyhow!("support chains is empty."));
+    }
+    Ok((chains, tokens))
+}
+
+/// The genesis set, from `SUPPORT_SET_GENESIS_CHAINS` and `SUPPORT_SET_GENESIS_TOKENS`. It applies
+/// from block 0, and every node must be given the same one.
+fn genesis_support_set() -> anyhow::Result<SupportSet> {
+    let chains = get_support_set_genesis_chains()
+        .iter()
+        .map(|c| c.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()?;
+    let tokens = get_support_set_genesis_tokens()
+        .iter()
+        .map(|t| Address::from_str(t))
+        .collect::<Result<Vec<Address>, _>>()?;
+    if chains.is_empty() {
+        return Err(anyhow::anyhow!(
+            "SUPPORT_SET_GENESIS_CHAINS is not set, the genesis support set must be given."
+        ));
+    }
+    Ok(SupportSet {
+        from_block: 0,
+        chains: vec_unique(chains),
+        tokens: vec_unique(tokens),
+    })
+}
+
+/// Load the supported chains and tokens at startup, the set that applies at the newest known
+/// block. The genesis set is saved first if no set applies from block 0. Later changes are
+/// scheduled by `refresh_support_set`.
+pub fn load_support_set(storage: Arc<Storage>) -> anyhow::Result<SupportSet> {
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let support_set_db = SupportSetDB::new(storage)?;
+    if support_set_db.get_support_set(0)?.is_none() {
+        support_set_db.insert_support_set(genesis_support_set()?)?;
+    }
+    let newest_block_num = block_info_db
+        .get_newest_block_info()?
+        .map(|bi| bi.storage.block_number)
+        .unwrap_or_default();
+    support_set_db
+        .get_support_set(newest_block_num)?
+        .ok_or(anyhow::anyhow!(
+            "no support set applies at Block #{:}",
+            newest_block_num
+        ))
+}
+
+/// Periodically reload the supported chains and tokens. A change applies from the
+/// `SUPPORT_SET_EPOCH_BLOCKS` boundary after the mainnet head it is seen at, not after the
+/// crawled blocks, so nodes that see it within the same epoch agree on the block it starts from,
+/// syncing or not. A node started later has not seen the changes before it, so its history must
+/// be checked against `getSupportSetHistory` of a running node.
+async fn refresh_support_set(
+    storage: Arc<Storage>,
+    contract: Arc<SubmitterContract>,
+) -> anyhow::Result<()> {
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let support_set_db = SupportSetDB::new(storage.clone())?;
+
+    event!(Level::INFO, "support set refresher is ready.");
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(get_support_set_refresh_seconds())).await;
+
+        let active = block_info_db
+            .get_newest_block_info()
+            .map_err(anyhow::Error::from)
+            .and_then(|bi| match bi {
+                Some(bi) => Ok(support_set_db.get_support_set(bi.storage.block_number)?),
+                None => Ok(None),
+            });
+        match active {
+            Ok(Some(active)) => {
+                let mut tokens = contract.support_mainnet_tokens.write().await;
+                if *tokens != active.tokens {
+                    *tokens = active.tokens;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                event!(Level::WARN, "Get active support set err: {:?}", err);
+                continue;
+            }
+        }
+
+        let head_block_num = *contract.now_block_num.read().await;
+        let (chains, tokens) = match request_support_set().await {
+            Ok(r) => r,
+            Err(err) => {
+                event!(Level::WARN, "Refresh support set err: {:?}", err);
+                continue;
+            }
+        };
+        match support_set_db.get_latest_support_set() {
+            Ok(Some(latest)) if latest.chains == chains && latest.tokens == tokens => continue,
+            Ok(_) => {}
+            Err(err) => {
+                event!(Level::WARN, "Get latest support set err: {:?}", err);
+                continue;
+            }
+        }
+
+        let epoch = max(get_support_set_epoch_blocks(), 1);
+        let support_set = SupportSet {
+            from_block: (head_block_num / epoch + 1) * epoch,
+            chains,
+            tokens,
+        };
+        event!(
+            Level::INFO,
+            "Support set changed, applies from Block #{:}. chains: {:?}, tokens: {:?}",
+            support_set.from_block,
+            support_set.chains,
+            support_set.tokens,
+        );
+        if let Err(err) = support_set_db.insert_support_set(support_set) {
+            event!(Level::WARN, "Save support set err: {:?}", err);
+        }
+    }
+}
+
+/// The dealer's fee ratio at `block_number`, cached in `MakerProfitDB`.
+async fn get_dealer_percent(
+    maker_profit_db: &MakerProfitDB,
+    contract: &SubmitterContract,
+    dealer: Address,
+    block_number: u64,
+    token: Address,
+) -> anyhow::Result<u64> {
+    if let Some(p) = maker_profit_db.get_percent(dealer, block_number, token)? {
+        return Ok(p);
+    }
+    let p = contract
+        .get_dealer_profit_percent_by_block(dealer, block_number, get_mainnet_chain_id(), token)
+        .await?;
+    maker_profit_db.insert_percent(dealer, block_number, token, p)?;
+    Ok(p)
+}
+
+/// The block asked for has no archived txs and profit root yet.
+#[derive(Debug)]
+pub struct BlockNotArchived(pub u64);
+
+impl std::fmt::Display for BlockNotArchived {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Block #{:} is not archived", self.0)
+    }
+}
+
+impl std::error::Error for BlockNotArchived {}
+
+fn is_block_archived(
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    block_num: u64,
+) -> anyhow::Result<bool> {
+    let value = blocks_state
+        .read()
+        .unwrap()
+        .try_get(block_number_convert_to_h256(block_num))?;
+    Ok(value != BlocksStateData::default())
+}
+
+/// Save the late txs of `block_num`, a block whose txs were saved already, in `db_tx`. They are
+/// folded into the block unless its txs are archived, then they are recorded in `MissedTxsDB`.
+/// Txs saved or missed before are skipped. `blocks_state` must stay read-locked until `db_tx` is
+/// committed: the archiver takes the write lock, so it can not archive the block in between.
+/// Returns the target ids of the folded and of the missed txs.
+fn save_late_txs_in(
+    db_tx: &OptimisticTransaction,
+    blocks_state: &State<'static, Keccak256Hasher, BlocksStateData>,
+    txs_db: &TxsRocksDB,
+    block_txs_count_db: &BlockTxsCountDB,
+    missed_txs_db: &MissedTxsDB,
+    block_num: u64,
+    last_update_block: u64,
+    txs: Vec<(CrossTxData, CrossTxProfit)>,
+) -> anyhow::Result<(Vec<H256>, Vec<H256>)> {
+    let mut seen: HashSet<H256> = HashSet::new();
+    let mut new_txs = vec![];
+    for tx in txs {
+        if !seen.insert(tx.0.target_id)
+            || txs_db.contains_tx(&tx.0)?
+            || missed_txs_db.contains(tx.0.target_id)?
+        {
+            continue;
+        }
+        new_txs.push(tx);
+    }
+    let target_ids: Vec<H256> = new_txs.iter().map(|tx| tx.0.target_id).collect();
+    // Txs of block `n` are archived together with block `n + 1`.
+    let archived = blocks_state.try_get(block_number_convert_to_h256(block_num + 1))?
+        != BlocksStateData::default();
+    if !archived {
+        block_txs_count_db.increase_count_in(db_tx, block_num, new_txs.len() as u64)?;
+        txs_db.insert_txs_in(db_tx, new_txs)?;
+        return Ok((target_ids, vec![]));
+    }
+    for (tx, profit) in new_txs {
+        missed_txs_db.insert_missed_tx_in(
+            db_tx,
+            MissedTx {
+                tx,
+                profit,
+                block_number: block_num,
+                submitted: block_num + 1 <= last_update_block,
+            },
+        )?;
+    }
+    Ok((vec![], target_ids))
+}
+
+/// Every profit leaf that changed between the archived blocks `from_block` and `to_block`.
+pub fn get_state_diff(
+    profit_changes_db: &ProfitChangesDB,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    from_block: u64,
+    to_block: u64,
+) -> anyhow::Result<Vec<ProfitStateDiff>> {
+    if from_block > to_block {
+        return Err(anyhow::anyhow!("from block is greater than to block"));
+    }
+    for block_num in [from_block, to_block] {
+        if !is_block_archived(blocks_state, block_num)? {
+            return Err(BlockNotArchived(block_num).into());
+        }
+    }
+    profit_changes_db
+        .get_state_diff(from_block, to_block)?
+        .ok_or(anyhow::anyhow!(
+            "changes of Block #{:} - #{:} are not tracked",
+            from_block + 1,
+            to_block
+        ))
+}
+
+/// Non-zero profit leaves of `address` for `tokens` as of the archived block `block_num`. The
+/// current leaves are taken back with the old values of the changes after the block.
+pub fn get_profit_at(
+    profit_state: &Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    profit_changes_db: &ProfitChangesDB,
+    block_num: u64,
+    address: Address,
+    tokens: Vec<(u64, Address)>,
+) -> anyhow::Result<ProfitAt> {
+    let archived = blocks_state
+        .read()
+        .unwrap()
+        .try_get(block_number_convert_to_h256(block_num))?;
+    if archived == BlocksStateData::default() {
+        return Err(BlockNotArchived(block_num).into());
+    }
+
+    // Held so that no block is archived between reading the changes and the leaves.
+    let profit_state = profit_state.read().unwrap();
+    let last_block = profit_changes_db
+        .get_last_block()?
+        .unwrap_or(block_num)
+        .max(block_num);
+    let diffs = profit_changes_db
+        .get_state_diff(block_num, last_block)?
+        .ok_or(anyhow::anyhow!(
+            "changes of Block #{:} - #{:} are not tracked",
+            block_num + 1,
+            last_block
+        ))?;
+    let mut profit = ProfitAt {
+        block_num,
+        profit_root: archived.profit_root,
+        balances: vec![],
+    };
+    for (chain_id, token) in vec_unique(tokens) {
+        let key = chain_token_address_convert_to_h256(chain_id, token, address);
+        let (balance, debt) = match diffs
+            .iter()
+            .find(|d| d.chain_id == chain_id && d.token == token && d.address == address)
+        {
+            Some(d) => (d.old_balance, d.old_debt),
+            None => {
+                let data = profit_state.try_get(key)?;
+                (data.balance, data.debt)
+            }
+        };
+        if balance.is_zero() && debt.is_zero() {
+            continue;
+        }
+        profit.balances.push(ProfitBalance {
+            key,
+            chain_id,
+            token,
+            address: Some(address),
+            balance,
+            debt,
+        });
+    }
+    Ok(profit)
+}
+
+/// Non-zero profit leaves after `cursor` that match `filter`, at most `limit` per page.
+pub fn list_balances(
+    profit_state: &Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    user_tokens_db: &UserTokensDB,
+    cursor: Option<H256>,
+    limit: usize,
+    filter: BalanceFilter,
+) -> anyhow::Result<BalancePage> {
+    let mut page = BalancePage {
+        balances: vec![],
+        next_cursor: cursor,
+    };
+    while page.balances.len() < limit {
+        let (leaves, next) = profit_state
+            .read()
+            .unwrap()
+            .try_get_leaves_page(page.next_cursor, limit - page.balances.len())?;
+        for (key, data) in leaves {
+            page.next_cursor = Some(key);
+            let address = user_tokens_db.get_leaf_owner(key)?.map(|(_, _, a)| a);
+            if filter.chain_id.map_or(false, |c| c != data.token_chain_id)
+                || filter.token.map_or(false, |t| t != data.token)
+                || filter.address.map_or(false, |a| Some(a) != address)
+            {
+                continue;
+            }
+            page.balances.push(ProfitBalance {
+                key,
+                chain_id: data.token_chain_id,
+                token: data.token,
+                address,
+                balance: data.balance,
+                debt: data.debt,
+            });
+        }
+        if next.is_none() {
+            page.next_cursor = None;
+            break;
+        }
+    }
+    Ok(page)
+}
+
+/// Re-fetch the already crawled windows within the horizon, because the txs source may publish
+/// txs late (especially for ZK chains). Missed txs are folded into blocks that are not archived
+/// yet, otherwise they are recorded in `MissedTxsDB`, see `save_late_txs_in`.
+async fn reconcile_late_txs(
+    storage: Arc<Storage>,
+    txs_db: Arc<TxsRocksDB>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    start_block: Arc<RwLock<u64>>,
+    contract: Arc<SubmitterContract>,
+) -> anyhow::Result<()> {
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let block_txs_count_db = BlockTxsCountDB::new(storage.clone())?;
+    let maker_profit_db = MakerProfitDB::new(storage.clone())?;
+    let missed_txs_db = MissedTxsDB::new(storage.clone())?;
+    let support_set_db = SupportSetDB::new(storage.clone())?;
+    let pruned_txs_db = PrunedTxsDB::new(storage.clone())?;
+    let tx_audit_db = TxAuditDB::new(storage.clone())?;
+
+    event!(Level::INFO, "late txs reconciler is ready.");
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(get_reconcile_interval_seconds())).await;
+
+        let newest_block_info = match block_info_db.get_newest_block_info()? {
+            Some(bi) => bi,
+            None => continue,
+        };
+
+        let end_block = newest_block_info.storage.block_number;
+        let mut from_block = max(
+            start_block.read().unwrap().clone(),
+            end_block.saturating_sub(get_reconcile_horizon_blocks()),
+        );
+        // Pruned txs would look missed. Txs of block `n` are archived with block `n + 1`.
+        if let Some(pruned_through) = pruned_txs_db.get_pruned_through()? {
+            from_block = max(from_block, pruned_through);
+        }
+        let mut folded_count = 0u64;
+        let mut missed_count = 0u64;
+        while from_block < end_block {
+            let to_block = min(from_block + 100, end_block);
+            // Only windows that the crawler has finished.
+            if !block_txs_count_db.is_txs_completed(from_block, to_block)? {
+                break;
+            }
+            let mut block_infos = vec![];
+            for bn in from_block..=to_block {
+                match block_info_db.get_block_info(bn)? {
+                    Some(bi) => block_infos.push(bi),
+                    None => break,
+                }
+            }
+            if block_infos.len() < 2 {
+                break;
+            }
+            let from_block_info = block_infos[0].clone();
+            let to_block_info = block_infos[block_infos.len() - 1].clone();
+            let support_chains = support_set_db.get_support_chains_in_range(
+                from_block_info.storage.block_number,
+                to_block_info.storage.block_number,
+            )?;
+
+            for chain in support_chains.iter() {
+                let crawler = TxsCrawler::new(get_txs_source_url());
+                let mut request = match crawler.request_txs(
+                    *chain,
+                    from_block_info.storage.block_timestamp,
+                    to_block_info.storage.block_timestamp,
+                    get_delay_seconds_by_chain_type(get_chain_type(*chain)),
+                ) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        event!(
+                            Level::WARN,
+                            "Reconcile Block #{:} - #{:}, get txs err: {:?}. chain_id: {:?}",
+                            from_block_info.storage.block_number,
+                            to_block_info.storage.block_number,
+                            err,
+                            chain
+                        );
+                        continue;
+                    }
+                };
+                // Each window of txs is reconciled as it arrives.
+                loop {
+                    let txs = match request.next_window().await {
+                        Ok(Some(txs)) => txs,
+                        Ok(None) => break,
+                        Err(err) => {
+                            event!(
+                                Level::WARN,
+                                "Reconcile Block #{:} - #{:}, get txs err: {:?}. chain_id: {:?}",
+                                from_block_info.storage.block_number,
+                                to_block_info.storage.block_number,
+                                err,
+                                chain
+                            );
+                            break;
+                        }
+                    };
+
+                    for raw_tx in txs {
+                        let tx: CrossTxData = raw_tx.clone().into();
+                        if txs_db.contains_tx(&tx)? || missed_txs_db.contains(tx.target_id)? {
+                            continue;
+                        }
+                        let block_num = match find_target_block(&block_infos, tx.target_time) {
+                            Some(bi) => bi.storage.block_number,
+                            None => continue,
+                        };
+                        let block_support_chains = support_set_db
+                            .get_support_set(block_num)?
+                            .map(|s| s.chains)
+                            .unwrap_or_default();
+                        if !block_support_chains.contains(&tx.target_chain)
+                            || !block_support_chains.contains(&tx.source_chain)
+                        {
+                            continue;
+                        }
+                        let percent = match get_dealer_percent(
+                            &maker_profit_db,
+                            &contract,
+                            tx.dealer_address,
+                            block_num,
+                            tx.source_token,
+                        )
+                        .await
+                        {
+                            Ok(p) => p,
+                            Err(err) => {
+                                event!(
+                                    Level::WARN,
+                                    "Reconcile Block #{:}, get dealer percent err: {:?}. tx: {:}",
+                                    block_num,
+                                    err,
+                                    hex::encode(tx.target_id.as_slice()),
+                                );
+                                continue;
+                            }
+                        };
+                        let profit = calculate_profit(percent, tx.clone());
+
+                        // Held until the tx is committed, so the block is not archived in between.
+                        let blocks = blocks_state.read().unwrap();
+                        // Run again if another task wrote the same keys meanwhile, e.g. the
+                        // crawler a count.
+                        let (folded, missed) = storage.transact(|db_tx| -> anyhow::Result<_> {
+                            let (folded, missed) = save_late_txs_in(
+                                db_tx,
+                                &blocks,
+                                &txs_db,
+                                &block_txs_count_db,
+                                &missed_txs_db,
+                                block_num,
+                                newest_block_info.storage.last_update_block,
+                                vec![(tx.clone(), profit.clone())],
+                            )?;
+                            let outcome = match (folded.is_empty(), missed.is_empty()) {
+                                (false, _) => Some(TxOutcome::Assigned),
+                                (true, false) => Some(TxOutcome::Missed),
+                                (true, true) => None,
+                            };
+                            if let Some(outcome) = outcome {
+                                tx_audit_db.record_in(
+                                    db_tx,
+                                    tx.target_id,
+                                    Some(raw_tx.clone()),
+                                    outcome,
+                                    Some(block_num),
+                                )?;
+                            }
+                            Ok((folded, missed))
+                        })?;
+                        drop(blocks);
+                        if !folded.is_empty() {
+                            event!(
+                                Level::INFO,
+                                "Block #{:} - late tx {:} is folded. profit: {:?}",
+                                block_num,
+                                hex::encode(tx.target_id.as_slice()),
+                                profit,
+                            );
+                            folded_count += 1;
+                        } else if !missed.is_empty() {
+                            event!(
+                            Level::WARN,
+                            "Block #{:} - late tx {:} is missed, block is already archived. profit: {:?}",
+                            block_num,
+                            hex::encode(tx.target_id.as_slice()),
+                            profit,
+                        );
+                            missed_count += 1;
+                        }
+                    }
+                }
+            }
+
+            // Adjacent windows share the boundary block, same as the crawler.
+            from_block = to_block_info.storage.block_number;
+        }
+
+        event!(
+            Level::INFO,
+            "Late txs reconciled up to Block #{:}. folded: {:}, missed: {:}",
+            from_block,
+            folded_count,
+            missed_count,
+        );
+    }
+}
+
 async fn submit_root(
     mut newest_block_receiver: Receiver<BlockInfo>,
-    sled_db: Arc<Db>,
+    storage: Arc<Storage>,
     txs_db: Arc<TxsRocksDB>,
     profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
     blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
     contract: Arc<SubmitterContract>,
     start_block: Arc<RwLock<u64>>,
+    events: Sender<SubmitterEvent>,
 ) -> anyhow::Result<()> {
-    let block_info_db = ContractBlockInfoDB::new(sled_db.clone())?;
-    let block_txs_count_db = BlockTxsCountDB::new(sled_db.clone())?;
-    let user_tokens_db = UserTokensDB::new(sled_db.clone())?;
-    let profit_statistic_db = ProfitStatisticsDB::new(sled_db.clone())?;
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let block_txs_count_db = BlockTxsCountDB::new(storage.clone())?;
+    let archiver = Archiver::new(
+        storage.clone(),
+        txs_db.clone(),
+        profit_state.clone(),
+        blocks_state.clone(),
+    )?
+    .with_events(events.clone());
 
     let mut newest_block_info = BlockInfo::default();
     let mut now_block_num = 0;
@@ -466,6 +1308,10 @@ async fn submit_root(
     {
         now_block_num = start_block.read().unwrap().clone();
     }
+    // After a restart, the blocks archived before it are skipped.
+    while is_block_archived(&blocks_state, now_block_num)? {
+        now_block_num += 1;
+    }
 
     event!(Level::INFO, "submit root thread is ready.");
 
@@ -519,153 +1365,7 @@ async fn submit_root(
                 end_block_num,
             );
             let last_block_info = last_block_info_op.unwrap();
-            let timestamp_range = (
-                last_block_info.storage.block_timestamp,
-                now_block_info.storage.block_timestamp,
-            );
-
-            for e in now_block_info.events {
-                match e.clone() {
-                    Event::Withdraw(w_e) => {
-                        let mut profit_state = profit_state.write().unwrap();
-                        let user = chain_token_address_convert_to_h256(
-                            w_e.chain_id,
-                            w_e.token_address,
-                            w_e.address,
-                        );
-                        let mut user_profit = profit_state.try_get(user).unwrap();
-                        if user_profit == ProfitStateData::default() {
-                            user_profit.token = w_e.token_address;
-                            user_profit.token_chain_id = w_e.chain_id;
-                        }
-                        user_profit.sub_balance(w_e.balance).unwrap();
-                        profit_state.try_update_all(vec![(user.clone(), user_profit.clone())])?;
-                        event!(
-                            Level::INFO,
-                            "Block #{:?}. - withdraw event {:?} - user: {:?} - balance: {:?}",
-                            now_block_num,
-                            e,
-                            user,
-                            w_e.balance,
-                        );
-                    }
-                    Event::Deposit(d_e) => {
-                        let mut profit_state = profit_state.write().unwrap();
-                        let user = chain_token_address_convert_to_h256(
-                            d_e.chain_id,
-                            d_e.token_address,
-                            d_e.address,
-                        );
-                        let mut user_profit = profit_state.try_get(user).unwrap();
-                        if user_profit == ProfitStateData::default() {
-                            user_profit.token = d_e.token_address;
-                            user_profit.token_chain_id = d_e.chain_id;
-                        }
-                        user_profit.add_balance(d_e.balance).unwrap();
-                        profit_state.try_update_all(vec![(user.clone(), user_profit.clone())])?;
-                        event!(
-                            Level::INFO,
-                            "Block #{:?}. - deposit event {:?} - user: {:?} - balance: {:?}",
-                            now_block_num,
-                            e,
-                            user,
-                            d_e.balance,
-                        );
-                    }
-                }
-            }
-
-            let txs = txs_db.get_txs_by_timestamp_range(timestamp_range.0, timestamp_range.1)?;
-            let mut tx_hashes: Vec<H256> = vec![];
-            for tx in txs {
-                let profit = tx.1.profit;
-                if profit == U256::from(0) {
-                    event!(
-                        Level::INFO,
-                        "Block #{:?}. - tx {:?} - profit is zero.",
-                        now_block_num,
-                        hex::encode(&tx.0.target_id.as_slice()),
-                    );
-                    continue;
-                }
-                let maker = tx.1.maker_address;
-                let dealer = tx.1.dealer_address;
-                let chain_id = tx.1.chain_id;
-                let token_id = tx.1.token;
-                let maker_key = chain_token_address_convert_to_h256(chain_id, token_id, maker);
-                let dealer_key = chain_token_address_convert_to_h256(chain_id, token_id, dealer);
-                let mut maker_profit = ProfitStateData::default();
-                let mut dealer_profit = ProfitStateData::default();
-                {
-                    let b_s_r = profit_state.read().unwrap();
-                    maker_profit = b_s_r.try_get(maker_key)?;
-                    dealer_profit = b_s_r.try_get(dealer_key)?;
-                }
-                if maker_profit == ProfitStateData::default() {
-                    maker_profit.token = token_id;
-                    maker_profit.token_chain_id = chain_id;
-                }
-                maker_profit.sub_balance(profit).unwrap();
-                if dealer_profit == ProfitStateData::default() {
-                    dealer_profit.token = token_id;
-                    dealer_profit.token_chain_id = chain_id;
-                }
-                profit_statistic_db.update_total_withdraw(maker, chain_id, token_id, profit)?;
-                dealer_profit.add_balance(profit).unwrap();
-                {
-                    let mut profit_state = profit_state.write().unwrap();
-                    profit_state.try_update_all(vec![
-                        (maker_key, maker_profit.clone()),
-                        (dealer_key, dealer_profit.clone()),
-                    ])?;
-                    event!(
-                        Level::INFO,
-                        "Block #{:?}. - tx {:?} - maker: {:?} - dealer: {:?} - profit: {:?}",
-                        now_block_num,
-                        hex::encode(&tx.0.target_id.as_slice()),
-                        maker,
-                        dealer,
-                        profit,
-                    );
-                }
-                profit_statistic_db.update_total_profit(dealer, chain_id, token_id, profit)?;
-                user_tokens_db.insert_token(maker, chain_id, token_id)?;
-                user_tokens_db.insert_token(dealer, chain_id, token_id)?;
-
-                tx_hashes.push(tx.0.target_id);
-            }
-
-            let txs_hash = get_one_block_txs_hash(tx_hashes.clone());
-            event!(
-                Level::INFO,
-                "Block #{:?}. - txs hash: {:?}",
-                now_block_num,
-                hex::encode(&txs_hash.as_slice()),
-            );
-
-            if now_block_num == 0 {
-                unreachable!()
-            }
-
-            let mut b_w = blocks_state.write().unwrap();
-            let last_key = block_number_convert_to_h256(now_block_num - 1);
-            let now_key = block_number_convert_to_h256(now_block_num);
-            let profit_root = profit_state.read().unwrap().try_get_root()?;
-            let mut new_block = BlocksStateData {
-                txs: txs_hash.into(),
-                block_num: now_block_num,
-                profit_root: profit_root.into(),
-                ..Default::default()
-            };
-            let old_block = b_w.try_get(last_key)?;
-            new_block.into_chain(old_block);
-            b_w.try_update_all(vec![(now_key, new_block.clone())])?;
-            event!(
-                Level::INFO,
-                "Block #{:?}. - block state add new block: {:?}",
-                now_block_num,
-                serde_json::to_string(&new_block).unwrap(),
-            );
+            archiver.archive_block(now_block_info, &last_block_info)?;
             now_block_num += 1;
         }
 
@@ -721,6 +1421,14 @@ async fn submit_root(
                 );
                 if let Some(s) = r.1 {
                     submit_root_block_num = s.as_u64();
+                    let _ = events.send(SubmitterEvent::NewRoot(NewRoot {
+                        tx_hash: r.0,
+                        block_number: submit_root_block_num,
+                        start_block: newest_block_info.storage.last_update_block,
+                        end_block: end_block_num,
+                        profit_root: profit_root.into(),
+                        blocks_root: block_txs_root.into(),
+                    }));
                 }
             }
             Err(e) => {
@@ -804,3 +1512,76 @@ async fn update_start_block_num(
     println!("start block : {:?}", start_block.read().unwrap().clone());
     Ok(())
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_late_txs() {
+        let path = "./db_late_txs";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let txs_db = TxsRocksDB::new(storage.clone()).unwrap();
+        let block_txs_count_db = BlockTxsCountDB::new(storage.clone()).unwrap();
+        let missed_txs_db = MissedTxsDB::new(storage.clone()).unwrap();
+        let blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>> =
+            Arc::new(RwLock::new(State::new(
+                primitives::storage::BLOCKS_STATE_PREFIX,
+                storage.clone(),
+            )));
+        block_txs_count_db.insert_count(1, 0).unwrap();
+        let tx = |id: u8| {
+            (
+                CrossTxData {
+                    target_id: [id; 32].into(),
+                    target_time: 11_000,
+                    ..Default::default()
+                },
+                CrossTxProfit::default(),
+            )
+        };
+        let save = |txs: Vec<(CrossTxData, CrossTxProfit)>| {
+            let db_tx = storage.transaction();
+            let blocks = blocks_state.read().unwrap();
+            let saved = save_late_txs_in(
+                &db_tx,
+                &blocks,
+                &txs_db,
+                &block_txs_count_db,
+                &missed_txs_db,
+                1,
+                0,
+                txs,
+            )
+            .unwrap();
+            db_tx.commit().unwrap();
+            saved
+        };
+
+        // Block 1 is not archived, the tx is folded once.
+        let (folded, missed) = save(vec![tx(1), tx(1)]);
+        assert_eq!((folded.len(), missed.len()), (1, 0));
+        let (folded, missed) = save(vec![tx(1)]);
+        assert_eq!((folded.len(), missed.len()), (0, 0));
+        assert_eq!(block_txs_count_db.get_count(1).unwrap(), Some(1));
+
+        // Once block 2 is archived, with the txs of block 1, a late tx is missed.
+        blocks_state
+            .write()
+            .unwrap()
+            .try_update_all(vec![(
+                block_number_convert_to_h256(2),
+                BlocksStateData {
+                    block_num: 2,
+                    ..Default::default()
+                },
+            )])
+            .unwrap();
+        let (folded, missed) = save(vec![tx(2)]);
+        assert_eq!((folded.len(), missed.len()), (0, 1));
+        assert!(missed_txs_db.contains(tx(2).0.target_id).unwrap());
+        assert!(!txs_db.contains_tx(&tx(2).0).unwrap());
+        assert_eq!(block_txs_count_db.get_count(1).unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,303 @@
+//! Pruning of old tx bodies.
+//!
+//! Once a block is covered by `TXS_RETAIN_ROOTS` newer submitted roots, the txs archived with it
+//! are removed from `TxsRocksDB` together with their index entries. Only the hashes its
+//! `BlocksStateData.txs` is computed from are kept, in `PrunedTxsDB`. The audit records of their
+//! target blocks are removed as well. Pruned blocks can no longer be replayed, and their txs are
+//! not returned by the tx queries.
+
+use super::*;
+use primitives::{
+    env::{get_txs_prune_compact_bytes, get_txs_prune_interval_seconds, get_txs_retain_roots},
+    storage::{KV_CF, TXS_CF},
+    types::TxWithProfit,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PruneReport {
+    /// Txs archived with the blocks before this one may be pruned.
+    pub cutoff_block: Option<u64>,
+    pub pruned_through: Option<u64>,
+    pub blocks: u64,
+    pub txs: u64,
+    /// Encoded size of the removed txs and index entries.
+    pub removed_bytes: u64,
+    pub compacted: bool,
+    pub size_before: u64,
+    pub size_after: u64,
+    /// Why pruning stopped before the cutoff block.
+    pub stopped: Option<String>,
+}
+
+/// The end block of the newest submitted root that has `retain_roots` newer ones, going by the
+/// `last_update_block` of the saved block infos.
+fn cutoff_block(
+    block_info_db: &ContractBlockInfoDB,
+    retain_roots: u64,
+) -> anyhow::Result<Option<u64>> {
+    let mut ends: Vec<u64> = vec![];
+    for block_info in block_info_db.iter_block_infos_rev() {
+        let end = block_info?.storage.last_update_block;
+        if end == 0 || ends.contains(&end) {
+            continue;
+        }
+        ends.push(end);
+        if ends.len() as u64 > retain_roots {
+            return Ok(Some(end));
+        }
+    }
+    Ok(None)
+}
+
+/// Prune the txs of every block before the cutoff, one block per transaction. A root ending at
+/// block `n` covers the blocks archived before `n`, so the cutoff block itself belongs to a
+/// retained root. A block is only
+/// pruned if the hash of its txs is the archived one, otherwise pruning stops there. Compacts
+/// the storage once at least `compact_bytes` were removed.
+pub fn prune_txs(
+    storage: &Arc<Storage>,
+    txs_db: &TxsRocksDB,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    retain_roots: u64,
+    compact_bytes: u64,
+) -> anyhow::Result<PruneReport> {
+    let block_info_db = ContractBlockInfoDB::new(storage.clone())?;
+    let pruned_txs_db = PrunedTxsDB::new(storage.clone())?;
+    let tx_audit_db = TxAuditDB::new(storage.clone())?;
+    let mut report = PruneReport {
+        size_before: storage.size_on_disk()?,
+        pruned_through: pruned_txs_db.get_pruned_through()?,
+        ..Default::default()
+    };
+    report.size_after = report.size_before;
+    if retain_roots == 0 {
+        report.stopped = Some("pruning is disabled".to_string());
+        return Ok(report);
+    }
+    report.cutoff_block = cutoff_block(&block_info_db, retain_roots)?;
+    let cutoff = match report.cutoff_block {
+        Some(b) => b,
+        None => return Ok(report),
+    };
+
+    // Txs before the oldest block info are never archived, so they are left alone.
+    let mut block_num = match report.pruned_through {
+        Some(b) => b + 1,
+        None => match txs_db.first_tx()? {
+            Some(first) => match block_info_db.get_target_block_num(first.tx.target_time / 1000)? {
+                // Txs of block `n` are archived together with block `n + 1`.
+                Some(n) => n + 1,
+                None => match block_info_db.get_oldest_block_info()? {
+                    Some(bi) => bi.storage.block_number + 1,
+                    None => return Ok(report),
+                },
+            },
+            None => return Ok(report),
+        },
+    };
+
+    while block_num < cutoff {
+        let (now_block_info, last_block_info) = match (
+            block_info_db.get_block_info(block_num)?,
+            block_info_db.get_block_info(block_num - 1)?,
+        ) {
+            (Some(now), Some(last)) => (now, last),
+            _ => {
+                report.stopped = Some(format!("Block #{:} info is not saved", block_num));
+                break;
+            }
+        };
+        let archived = blocks_state
+            .read()
+            .unwrap()
+            .try_get(block_number_convert_to_h256(block_num))?;
+        if archived == BlocksStateData::default() {
+            report.stopped = Some(format!("Block #{:} is not archived", block_num));
+            break;
+        }
+
+        let txs: Vec<TxWithProfit> = txs_db
+            .get_txs_by_timestamp_range(
+                last_block_info.storage.block_timestamp,
+                now_block_info.storage.block_timestamp,
+            )?
+            .into_iter()
+            .map(|(tx, profit)| TxWithProfit { tx, profit })
+            .collect();
+        // Same as `Archiver::archive_block`.
+        let hashes: Vec<H256> = txs
+            .iter()
+            .filter(|t| t.profit.profit != U256::zero())
+            .map(|t| t.tx.target_id)
+            .collect();
+        if get_one_block_txs_hash(hashes.clone()) != H256::from(archived.txs) {
+            report.stopped = Some(format!(
+                "txs hash of Block #{:} does not match the archived one",
+                block_num
+            ));
+            break;
+        }
+
+        report.removed_bytes += storage.transact(|db_tx| -> anyhow::Result<u64> {
+            if !hashes.is_empty() {
+                pruned_txs_db.insert_hashes_in(db_tx, block_num, hashes.clone())?;
+            }
+            let removed_bytes = txs_db.remove_txs_in(db_tx, &txs)?;
+            tx_audit_db.remove_through_in(db_tx, block_num - 1)?;
+            pruned_txs_db.set_pruned_through_in(db_tx, block_num)?;
+            Ok(removed_bytes)
+        })?;
+        report.blocks += 1;
+        report.txs += txs.len() as u64;
+        report.pruned_through = Some(block_num);
+        block_num += 1;
+    }
+
+    if report.removed_bytes > 0 && report.removed_bytes >= compact_bytes {
+        storage.compact(TXS_CF)?;
+        storage.compact(KV_CF)?;
+        report.compacted = true;
+    }
+    report.size_after = storage.size_on_disk()?;
+    Ok(report)
+}
+
+pub(crate) async fn prune_txs_periodically(
+    storage: Arc<Storage>,
+    txs_db: Arc<TxsRocksDB>,
+    blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+) -> anyhow::Result<()> {
+    event!(Level::INFO, "txs pruner is ready.");
+    loop {
+        tokio::time::sleep(Duration::from_secs(get_txs_prune_interval_seconds())).await;
+        match prune_txs(
+            &storage,
+            &txs_db,
+            &blocks_state,
+            get_txs_retain_roots(),
+            get_txs_prune_compact_bytes(),
+        ) {
+            Ok(report) => event!(
+                Level::INFO,
+                "Txs pruned through Block #{:?}. blocks: {:}, txs: {:}, removed bytes: {:}, size on disk: {:} -> {:}, stopped: {:?}",
+                report.pruned_through,
+                report.blocks,
+                report.txs,
+                report.removed_bytes,
+                report.size_before,
+                report.size_after,
+                report.stopped,
+            ),
+            Err(err) => event!(Level::WARN, "Prune txs err: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::fixtures::{test_block_info, test_tx};
+
+    #[test]
+    fn test_prune_txs() {
+        let path = "./db_prune_txs";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let txs_db = TxsRocksDB::new(storage.clone()).unwrap();
+        let block_info_db = ContractBlockInfoDB::new(storage.clone()).unwrap();
+        let blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>> =
+            Arc::new(RwLock::new(State::new(
+                primitives::storage::BLOCKS_STATE_PREFIX,
+                storage.clone(),
+            )));
+
+        // Every root is submitted with the next block.
+        for n in 1u64..=6 {
+            let mut block_info = test_block_info(n);
+            block_info.storage.last_update_block = n - 1;
+            block_info_db.insert_block_info(n, block_info).unwrap();
+        }
+        txs_db
+            .insert_txs(vec![
+                test_tx(11, 1),
+                test_tx(12, 0),
+                test_tx(21, 1),
+                test_tx(31, 1),
+            ])
+            .unwrap();
+        for n in 2u64..=4 {
+            let txs = txs_db
+                .get_txs_by_timestamp_range((n - 1) * 10, n * 10)
+                .unwrap();
+            let hashes = txs
+                .into_iter()
+                .filter(|t| t.1.profit != U256::zero())
+                .map(|t| t.0.target_id)
+                .collect();
+            blocks_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(
+                    block_number_convert_to_h256(n),
+                    BlocksStateData {
+                        block_num: n,
+                        txs: get_one_block_txs_hash(hashes).into(),
+                        ..Default::default()
+                    },
+                )])
+                .unwrap();
+        }
+
+        let tx_audit_db = TxAuditDB::new(storage.clone()).unwrap();
+        let db_tx = storage.transaction();
+        for (id, block) in [(11u8, 1u64), (13, 1), (21, 2)] {
+            tx_audit_db
+                .record_in(
+                    &db_tx,
+                    [id; 32].into(),
+                    Some(CrossTxRawData::default()),
+                    TxOutcome::Assigned,
+                    Some(block),
+                )
+                .unwrap();
+        }
+        db_tx.commit().unwrap();
+
+        let report = prune_txs(&storage, &txs_db, &blocks_state, 0, 0).unwrap();
+        assert_eq!(report.blocks, 0);
+        // Roots end at blocks 5, 4, 3, 2 and 1. The one ending at 2 has three newer ones, but
+        // block 2 is covered by the retained root ending at 3.
+        let report = prune_txs(&storage, &txs_db, &blocks_state, 3, u64::MAX).unwrap();
+        assert_eq!(report.cutoff_block, Some(2));
+        assert_eq!((report.blocks, report.pruned_through), (0, None));
+        let report = prune_txs(&storage, &txs_db, &blocks_state, 2, u64::MAX).unwrap();
+        assert_eq!(report.cutoff_block, Some(3));
+        assert_eq!((report.blocks, report.txs), (1, 2));
+        assert_eq!(report.pruned_through, Some(2));
+        assert!(!report.compacted);
+
+        let pruned_txs_db = PrunedTxsDB::new(storage.clone()).unwrap();
+        assert_eq!(
+            pruned_txs_db.get_hashes(2).unwrap(),
+            Some(vec![H256::from([11u8; 32])])
+        );
+        assert_eq!(pruned_txs_db.get_hashes(3).unwrap(), None);
+        assert_eq!(
+            txs_db.get_profit_by_tx_hash([11u8; 32].into()).unwrap(),
+            None
+        );
+        assert!(txs_db
+            .get_profit_by_tx_hash([21u8; 32].into())
+            .unwrap()
+            .is_some());
+        assert_eq!(tx_audit_db.get_record([11u8; 32].into()).unwrap(), None);
+        assert_eq!(tx_audit_db.get_record([13u8; 32].into()).unwrap(), None);
+        assert!(tx_audit_db.get_record([21u8; 32].into()).unwrap().is_some());
+
+        let report = prune_txs(&storage, &txs_db, &blocks_state, 1, 0).unwrap();
+        assert_eq!(report.cutoff_block, Some(4));
+        assert_eq!(report.pruned_through, Some(3));
+        assert!(report.compacted);
+    }
+}
@@ -1,63 +1,374 @@
 use super::*;
-use core::cmp::Ordering;
+use crate::stats::TxStatsDB;
 use ethers::types::Address;
-use primitives::{error::Result, types::CrossTxData};
-use rocksdb::Direction;
-use std::fmt::format;
+use ethers::utils::keccak256;
+use primitives::{
+    error::Result,
+    storage::{Storage, TXS_CF},
+    types::{CrossTxData, TxCursor, TxPage, TxWithProfit},
+};
+use rocksdb::{
+    ops::{DeleteCF, GetCF, IterateCF, PutCF},
+    ColumnFamily, Direction, OptimisticTransaction,
+};
+use std::{collections::HashMap, fmt::format, ops::Bound};
 
+// The index key of a tx ends with its position: target time, target chain and target id.
+const CURSOR_LEN: usize = 8 + 8 + 32;
+// Indexes are rebuilt in transactions of this many txs.
+const REBUILD_BATCH_SIZE: usize = 1000;
+
+/// A secondary index of the txs. Entries are ordered by target time within each key.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TxIndex {
+    Dealer(Address),
+    Maker(Address),
+    SourceChain(u64),
+    TargetChain(u64),
+    /// The source tx hash, case insensitive.
+    SourceId(String),
+    TargetId(H256),
+}
+
+impl TxIndex {
+    fn all(tx: &CrossTxData, profit: &CrossTxProfit) -> Vec<Self> {
+        vec![
+            TxIndex::Dealer(profit.dealer_address),
+            TxIndex::Maker(profit.maker_address),
+            TxIndex::SourceChain(tx.source_chain),
+            TxIndex::TargetChain(tx.target_chain),
+            TxIndex::SourceId(tx.source_id.clone()),
+            TxIndex::TargetId(tx.target_id),
+        ]
+    }
+
+    fn prefix(&self) -> Vec<u8> {
+        let mut prefix = vec![];
+        match self {
+            TxIndex::Dealer(a) => {
+                prefix.push(0);
+                prefix.extend_from_slice(a.as_bytes());
+            }
+            TxIndex::Maker(a) => {
+                prefix.push(1);
+                prefix.extend_from_slice(a.as_bytes());
+            }
+            TxIndex::SourceChain(c) => {
+                prefix.push(2);
+                prefix.extend_from_slice(&c.to_be_bytes());
+            }
+            TxIndex::TargetChain(c) => {
+                prefix.push(3);
+                prefix.extend_from_slice(&c.to_be_bytes());
+            }
+            TxIndex::SourceId(id) => {
+                prefix.push(4);
+                prefix.extend_from_slice(&keccak256(id.to_lowercase()));
+            }
+            TxIndex::TargetId(id) => {
+                prefix.push(5);
+                prefix.extend_from_slice(id.as_slice());
+            }
+        }
+        prefix
+    }
+}
+
+fn cursor_of(tx: &CrossTxData) -> TxCursor {
+    TxCursor {
+        target_time: tx.target_time,
+        target_chain: tx.target_chain,
+        target_id: tx.target_id,
+    }
+}
+
+fn cursor_bytes(cursor: &TxCursor) -> Vec<u8> {
+    let mut bytes = cursor.target_time.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&cursor.target_chain.to_be_bytes());
+    bytes.extend_from_slice(cursor.target_id.as_slice());
+    bytes
+}
+
+fn cursor_from_index_key(key: &[u8]) -> TxCursor {
+    let bytes = &key[key.len() - CURSOR_LEN..];
+    let target_id: [u8; 32] = bytes[16..].try_into().unwrap();
+    TxCursor {
+        target_time: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+        target_chain: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        target_id: target_id.into(),
+    }
+}
+
+/// Txs with their profits in the `txs` column family of the storage, ordered by `tx_compare`,
+/// and their `TxIndex`es in the `txs-index` tree.
 pub struct TxsRocksDB {
-    inner: DB,
+    storage: Arc<Storage>,
+    index: Tree,
+    stats: TxStatsDB,
 }
 
 impl TxsRocksDB {
-    pub fn new(db_path: String) -> anyhow::Result<Self> {
-        let path = format!("{}/txs", db_path);
-        let db = open_rocksdb(path, tx_compare)?;
-        Ok(Self { inner: db })
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            index: storage.tree("txs-index"),
+            stats: TxStatsDB::new(storage.clone())?,
+            storage,
+        })
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.storage.cf(TXS_CF)
+    }
+
+    fn index_key(index: &TxIndex, cursor: &TxCursor) -> Vec<u8> {
+        let mut key = index.prefix();
+        key.extend(cursor_bytes(cursor));
+        key
     }
 
     pub fn insert_txs(&self, txs: Vec<(CrossTxData, CrossTxProfit)>) -> Result<()> {
-        let mut batch = WriteBatch::default();
+        self.storage
+            .transact(|db_tx| self.insert_txs_in(db_tx, txs.clone()))
+    }
+
+    pub fn insert_txs_in(
+        &self,
+        db_tx: &OptimisticTransaction,
+        txs: Vec<(CrossTxData, CrossTxProfit)>,
+    ) -> Result<()> {
+        // Txs written earlier in this batch, which `get_tx` does not see yet.
+        let mut batch: HashMap<TxCursor, TxWithProfit> = HashMap::new();
         for tx in txs {
+            let cursor = cursor_of(&tx.0);
+            let old = match batch.remove(&cursor) {
+                Some(old) => Some(old),
+                None => self.get_tx(&cursor)?,
+            };
+            // A tx saved again may credit someone else, so its old entries go first.
+            if let Some(old) = old {
+                for index in TxIndex::all(&old.tx, &old.profit) {
+                    self.index
+                        .remove_in(db_tx, Self::index_key(&index, &cursor))?;
+                }
+                self.stats.update_in(db_tx, &old.tx, &old.profit, true)?;
+            }
+            for index in TxIndex::all(&tx.0, &tx.1) {
+                self.index
+                    .insert_in(db_tx, Self::index_key(&index, &cursor), vec![])?;
+            }
+            self.stats.update_in(db_tx, &tx.0, &tx.1, false)?;
             let key = bincode::serialize(&tx.0)?;
             let value = bincode::serialize(&tx.1)?;
-            batch.put(key, value)?;
+            db_tx.put_cf(self.cf(), key, value)?;
+            batch.insert(
+                cursor,
+                TxWithProfit {
+                    tx: tx.0,
+                    profit: tx.1,
+                },
+            );
         }
-        self.inner.write(&batch)?;
-        self.inner.flush()?;
         Ok(())
     }
 
-    pub fn get_profit_by_tx_hash(&self, tx_hash: H256) -> Result<Option<CrossTxProfit>> {
-        let lower_bound = CrossTxData {
-            target_time: 0u64,
-            target_chain: 0u64,
-            target_id: tx_hash,
+    /// Remove the txs and their index entries. Returns the encoded size of what was removed.
+    pub fn remove_txs_in(
+        &self,
+        db_tx: &OptimisticTransaction,
+        txs: &[TxWithProfit],
+    ) -> Result<u64> {
+        let mut removed = 0u64;
+        for tx in txs {
+            let cursor = cursor_of(&tx.tx);
+            for index in TxIndex::all(&tx.tx, &tx.profit) {
+                let key = Self::index_key(&index, &cursor);
+                removed += key.len() as u64;
+                self.index.remove_in(db_tx, key)?;
+            }
+            let key = bincode::serialize(&tx.tx)?;
+            removed += key.len() as u64 + bincode::serialized_size(&tx.profit)?;
+            db_tx.delete_cf(self.cf(), key)?;
+        }
+        Ok(removed)
+    }
+
+    /// The saved tx with the lowest target time.
+    pub fn first_tx(&self) -> Result<Option<TxWithProfit>> {
+        match self
+            .storage
+            .db()
+            .iterator_cf(self.cf(), IteratorMode::Start)?
+            .next()
+        {
+            Some((k, v)) => Ok(Some(TxWithProfit {
+                tx: bincode::deserialize(&k)?,
+                profit: bincode::deserialize(&v)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Index every saved tx again. Used by the schema migration that added the indexes.
+    pub(crate) fn rebuild_indexes(&self) -> Result<()> {
+        self.index.clear()?;
+        let mut count = 0u64;
+        let mut db_tx = self.storage.transaction();
+        for (key, value) in self
+            .storage
+            .db()
+            .iterator_cf(self.cf(), IteratorMode::Start)?
+        {
+            let tx: CrossTxData = bincode::deserialize(&key)?;
+            let profit: CrossTxProfit = bincode::deserialize(&value)?;
+            let cursor = cursor_of(&tx);
+            for index in TxIndex::all(&tx, &profit) {
+                self.index
+                    .insert_in(&db_tx, Self::index_key(&index, &cursor), vec![])?;
+            }
+            count += 1;
+            if count % REBUILD_BATCH_SIZE as u64 == 0 {
+                db_tx.commit()?;
+                db_tx = self.storage.transaction();
+            }
+        }
+        db_tx.commit()?;
+        event!(Level::INFO, "Txs indexes rebuilt. count: {:?}", count);
+        Ok(())
+    }
+
+    /// Roll up every saved tx again. Used by the schema migration that added the rollups, so
+    /// txs pruned before it are not counted.
+    pub(crate) fn rebuild_stats(&self) -> Result<()> {
+        self.stats.clear()?;
+        let mut count = 0u64;
+        let mut db_tx = self.storage.transaction();
+        for (key, value) in self
+            .storage
+            .db()
+            .iterator_cf(self.cf(), IteratorMode::Start)?
+        {
+            let tx: CrossTxData = bincode::deserialize(&key)?;
+            let profit: CrossTxProfit = bincode::deserialize(&value)?;
+            self.stats.update_in(&db_tx, &tx, &profit, false)?;
+            count += 1;
+            if count % REBUILD_BATCH_SIZE as u64 == 0 {
+                db_tx.commit()?;
+                db_tx = self.storage.transaction();
+            }
+        }
+        db_tx.commit()?;
+        event!(Level::INFO, "Txs stats rebuilt. count: {:?}", count);
+        Ok(())
+    }
+
+    /// The comparator only looks at target time, chain and id, so a partial key is enough.
+    pub fn contains_tx(&self, tx: &CrossTxData) -> Result<bool> {
+        let key = CrossTxData {
+            target_time: tx.target_time,
+            target_chain: tx.target_chain,
+            target_id: tx.target_id,
             ..Default::default()
         };
-        let upper_bound = CrossTxData {
-            target_time: u64::MAX,
-            target_chain: u64::MAX,
-            target_id: tx_hash,
+        Ok(self
+            .storage
+            .db()
+            .get_cf(self.cf(), bincode::serialize(&key)?)?
+            .is_some())
+    }
+
+    /// The saved tx at `cursor`. Seeking to the partial key lands on the full one.
+    pub fn get_tx(&self, cursor: &TxCursor) -> Result<Option<TxWithProfit>> {
+        let key = CrossTxData {
+            target_time: cursor.target_time,
+            target_chain: cursor.target_chain,
+            target_id: cursor.target_id,
             ..Default::default()
         };
-        let mut read_opts = ReadOptions::default();
-        read_opts.set_iterate_upper_bound(bincode::serialize(&upper_bound)?);
-        let iter = self.inner.iterator_opt(
-            IteratorMode::From(&bincode::serialize(&lower_bound)?, Direction::Forward),
-            &read_opts,
-        );
-        let mut profit: Option<CrossTxProfit> = None;
-        for (key, value) in iter {
-            let k: CrossTxData = bincode::deserialize(&key)?;
-            let v: CrossTxProfit = bincode::deserialize(&value)?;
-            if k.target_id != tx_hash {
-                profit = Some(v);
+        let mut iter = self.storage.db().iterator_cf(
+            self.cf(),
+            IteratorMode::From(&bincode::serialize(&key)?, Direction::Forward),
+        )?;
+        match iter.next() {
+            Some((k, v)) => {
+                let tx: CrossTxData = bincode::deserialize(&k)?;
+                if &cursor_of(&tx) != cursor {
+                    return Ok(None);
+                }
+                Ok(Some(TxWithProfit {
+                    tx,
+                    profit: bincode::deserialize(&v)?,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Txs matching `index` with a target time in [start_timestamp, end_timestamp) seconds,
+    /// after `cursor` if given, at most `limit` per page.
+    pub fn get_txs_by_index(
+        &self,
+        index: &TxIndex,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        cursor: Option<TxCursor>,
+        limit: usize,
+    ) -> Result<TxPage> {
+        let mut start = index.prefix();
+        let mut end = index.prefix();
+        end.extend_from_slice(&end_timestamp.saturating_mul(1000).to_be_bytes());
+        let start = match cursor {
+            Some(cursor) => {
+                start.extend(cursor_bytes(&cursor));
+                Bound::Excluded(start)
+            }
+            None => {
+                start.extend_from_slice(&start_timestamp.saturating_mul(1000).to_be_bytes());
+                Bound::Included(start)
+            }
+        };
+        let mut page = TxPage::default();
+        for item in self.index.range((start, Bound::Excluded(end))) {
+            let (k, _) = item?;
+            if page.txs.len() == limit {
+                page.next_cursor = page.txs.last().map(|t| cursor_of(&t.tx));
                 break;
             }
+            // Only missing if the index is ahead of the txs, which a transaction rules out.
+            if let Some(tx) = self.get_tx(&cursor_from_index_key(&k))? {
+                page.txs.push(tx);
+            }
         }
+        Ok(page)
+    }
 
-        Ok(profit)
+    /// Every saved tx with the source tx hash `source_id`.
+    pub fn get_txs_by_source_id(&self, source_id: &str) -> Result<Vec<TxWithProfit>> {
+        let mut txs = vec![];
+        for item in self
+            .index
+            .scan_prefix(TxIndex::SourceId(source_id.to_string()).prefix())
+        {
+            if let Some(tx) = self.get_tx(&cursor_from_index_key(&item?.0))? {
+                txs.push(tx);
+            }
+        }
+        Ok(txs)
+    }
+
+    /// The saved tx with the target tx hash `tx_hash`.
+    pub fn get_tx_by_target_id(&self, tx_hash: H256) -> Result<Option<TxWithProfit>> {
+        match self
+            .index
+            .scan_prefix(TxIndex::TargetId(tx_hash).prefix())
+            .next()
+        {
+            Some(item) => self.get_tx(&cursor_from_index_key(&item?.0)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_profit_by_tx_hash(&self, tx_hash: H256) -> Result<Option<CrossTxProfit>> {
+        Ok(self.get_tx_by_target_id(tx_hash)?.map(|t| t.profit))
     }
 
     pub fn get_txs_by_timestamp_range(
@@ -81,10 +392,11 @@ impl TxsRocksDB {
             ..Default::default()
         };
         read_opts.set_iterate_upper_bound(bincode::serialize(&upper_bound)?);
-        let iter = self.inner.iterator_opt(
+        let iter = self.storage.db().iterator_cf_opt(
+            self.cf(),
             IteratorMode::From(&bincode::serialize(&lower_bound)?, Direction::Forward),
             &read_opts,
-        );
+        )?;
         let mut txs = Vec::new();
         for (key, value) in iter {
             let k: CrossTxData = bincode::deserialize(&key)?;
@@ -100,20 +412,94 @@ impl TxsRocksDB {
     }
 }
 
-type FF = fn(&[u8], &[u8]) -> Ordering;
-pub fn open_rocksdb(path: String, callback: FF) -> anyhow::Result<DB> {
-    let mut opts = Options::default();
-    opts.create_if_missing(true);
-    opts.set_comparator("custom", callback);
-    let db = DB::open(&opts, path)?;
-    Ok(db)
-}
-
 #[cfg(test)]
 pub mod test {
     use super::*;
     use crate::TxsRocksDB;
 
+    #[test]
+    fn test_txs_indexes() {
+        let path = "./db_txs_indexes";
+        let _ = std::fs::remove_dir_all(path);
+        let db = TxsRocksDB::new(Arc::new(Storage::open(path).unwrap())).unwrap();
+        let dealer = Address::from_low_u64_be(1);
+        let tx = |i: u64, maker: Address| {
+            (
+                CrossTxData {
+                    dealer_address: dealer,
+                    source_chain: 5,
+                    source_id: format!("0xAB{:?}", i),
+                    target_chain: 10,
+                    target_id: [i as u8; 32].into(),
+                    target_time: i * 1000,
+                    ..Default::default()
+                },
+                CrossTxProfit {
+                    maker_address: maker,
+                    dealer_address: dealer,
+                    profit: U256::from(i),
+                    chain_id: 5,
+                    token: Address::zero(),
+                },
+            )
+        };
+        let maker = Address::from_low_u64_be(2);
+        db.insert_txs((1..=5).map(|i| tx(i, maker)).collect())
+            .unwrap();
+
+        let page = db
+            .get_txs_by_index(&TxIndex::Dealer(dealer), 2, 6, None, 2)
+            .unwrap();
+        assert_eq!(
+            page.txs
+                .iter()
+                .map(|t| t.tx.target_time)
+                .collect::<Vec<_>>(),
+            vec![2000, 3000]
+        );
+        let page = db
+            .get_txs_by_index(&TxIndex::Dealer(dealer), 2, 6, page.next_cursor, 2)
+            .unwrap();
+        assert_eq!(
+            page.txs
+                .iter()
+                .map(|t| t.tx.target_time)
+                .collect::<Vec<_>>(),
+            vec![4000, 5000]
+        );
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(
+            db.get_txs_by_index(&TxIndex::TargetChain(5), 0, 10, None, 10)
+                .unwrap()
+                .txs
+                .len(),
+            0
+        );
+        assert_eq!(
+            db.get_txs_by_source_id("0xab3").unwrap()[0].tx.target_time,
+            3000
+        );
+        assert_eq!(
+            db.get_profit_by_tx_hash([4u8; 32].into()).unwrap(),
+            Some(tx(4, maker).1)
+        );
+
+        // Saved again with another maker, the old maker loses the tx.
+        let other = Address::from_low_u64_be(3);
+        db.insert_txs(vec![tx(4, other)]).unwrap();
+        let by_maker = |maker| {
+            db.get_txs_by_index(&TxIndex::Maker(maker), 0, 10, None, 10)
+                .unwrap()
+                .txs
+                .len()
+        };
+        assert_eq!((by_maker(maker), by_maker(other)), (4, 1));
+
+        db.index.clear().unwrap();
+        db.rebuild_indexes().unwrap();
+        assert_eq!((by_maker(maker), by_maker(other)), (4, 1));
+    }
+
     // #[test]
     // pub fn test() {
     //     let db: TxsRocksDB = TxsRocksDB::new(String::from("./db")).unwrap();
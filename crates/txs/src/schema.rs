@@ -0,0 +1,293 @@
+//! Schema version of the storage and the migrations between versions.
+//!
+//! The version covers the tree names, the key and value encodings of every tree (e.g. the
+//! bincode encoding of `BlockInfo` and `ProfitStatistics`), the state prefixes and the key layout
+//! of `TxsRocksDB`. Bump `SCHEMA_VERSION` and add a migration whenever one of them changes.
+
+use super::*;
+use primitives::storage::{BLOCKS_STATE_PREFIX, PROFIT_STATE_PREFIX, TXS_CF};
+use rocksdb::{
+    ops::{Put, PutCF},
+    Direction, OptimisticTransactionDB,
+};
+use std::path::Path;
+
+pub const SCHEMA_VERSION: u32 = 6;
+
+const META_TREE: &str = "meta";
+const SCHEMA_VERSION_KEY: &[u8] = b"schema-version";
+
+pub struct Migration {
+    /// The version after the migration.
+    pub version: u32,
+    pub description: &'static str,
+    pub run: fn(&Arc<Storage>) -> Result<(), Error>,
+}
+
+/// Databases created before the schema version existed are at version 1.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        description: "index block infos by timestamp",
+        run: |storage| ContractBlockInfoDB::new(storage.clone())?.rebuild_timestamp_index(),
+    },
+    Migration {
+        version: 3,
+        description: "map profit leaf keys to their owners",
+        run: |storage| UserTokensDB::new(storage.clone())?.rebuild_leaf_keys(),
+    },
+    Migration {
+        version: 4,
+        description: "index txs by dealer, maker, chain and tx hash",
+        run: |storage| TxsRocksDB::new(storage.clone())?.rebuild_indexes(),
+    },
+    Migration {
+        version: 5,
+        description: "roll up txs by hour for the statistics",
+        run: |storage| TxsRocksDB::new(storage.clone())?.rebuild_stats(),
+    },
+    Migration {
+        version: 6,
+        description: "index tx audit records by target block",
+        run: |storage| TxAuditDB::new(storage.clone())?.rebuild_block_index(),
+    },
+];
+
+pub fn get_schema_version(storage: &Arc<Storage>) -> Result<Option<u32>, Error> {
+    match storage.tree(META_TREE).get(SCHEMA_VERSION_KEY)? {
+        Some(v) => Ok(Some(bincode::deserialize::<u32>(&v)?)),
+        None => Ok(None),
+    }
+}
+
+fn set_schema_version(storage: &Arc<Storage>, version: u32) -> Result<(), Error> {
+    let meta = storage.tree(META_TREE);
+    meta.insert(SCHEMA_VERSION_KEY, bincode::serialize(&version)?)?;
+    meta.flush()?;
+    Ok(())
+}
+
+/// Copy a db path written before the storage was shared (sled at the db path, and a RocksDB each
+/// at `profit`, `blocks` and `txs`) into the empty `storage`. The old files are left in place.
+/// Returns false if there is nothing to copy.
+pub fn import_legacy_layout(storage: &Arc<Storage>, db_path: &str) -> Result<bool, Error> {
+    // Every sled db has a `conf` file.
+    if !Path::new(db_path).join("conf").exists() {
+        return Ok(false);
+    }
+    event!(
+        Level::INFO,
+        "Copying the db at {} into the shared storage.",
+        db_path
+    );
+
+    let sled_db = sled::open(db_path)?;
+    for name in sled_db.tree_names() {
+        let sled_tree = sled_db.open_tree(&name)?;
+        let name = String::from_utf8_lossy(&name).to_string();
+        if name == "__sled__default" {
+            continue;
+        }
+        let tree = storage.tree(&name);
+        for item in sled_tree.iter() {
+            let (k, v) = item?;
+            tree.insert(k, v)?;
+        }
+        event!(
+            Level::INFO,
+            "Tree {} copied. count: {:?}",
+            name,
+            sled_tree.len()
+        );
+    }
+
+    // The state trees were prefixed with their own db path.
+    for (dir, prefix) in [
+        ("profit", PROFIT_STATE_PREFIX),
+        ("blocks", BLOCKS_STATE_PREFIX),
+    ] {
+        let path = format!("{}/{}", db_path, dir);
+        if !Path::new(&path).exists() {
+            continue;
+        }
+        let old_db = OptimisticTransactionDB::open_default(&path)?;
+        let old_prefix = path.as_bytes();
+        let mut count = 0u64;
+        for (k, v) in old_db
+            .iterator(IteratorMode::From(old_prefix, Direction::Forward))
+            .take_while(|(k, _)| k.starts_with(old_prefix))
+        {
+            let mut key = prefix.to_vec();
+            key.extend_from_slice(&k[old_prefix.len()..]);
+            storage.db().put(key, v)?;
+            count += 1;
+        }
+        event!(Level::INFO, "State {} copied. keys: {:?}", dir, count);
+    }
+
+    let path = format!("{}/txs", db_path);
+    if Path::new(&path).exists() {
+        let mut opts = Options::default();
+        opts.set_comparator("custom", tx_compare);
+        let old_db = DB::open(&opts, path)?;
+        let mut count = 0u64;
+        for (k, v) in old_db.iterator(IteratorMode::Start) {
+            storage.db().put_cf(storage.cf(TXS_CF), k, v)?;
+            count += 1;
+        }
+        event!(Level::INFO, "Txs copied. count: {:?}", count);
+    }
+
+    storage.flush()?;
+    event!(
+        Level::INFO,
+        "The db at {} is copied, its old files can be removed once the submitter runs fine.",
+        db_path
+    );
+    Ok(true)
+}
+
+/// Refuse a storage written by a newer build, and bring older ones up to `SCHEMA_VERSION`. An
+/// empty storage is first filled from the old layout at `db_path`, if there is one. Must run
+/// before any tree is used. Returns the version the storage was at.
+pub fn check_and_migrate(storage: &Arc<Storage>, db_path: &str) -> Result<u32, Error> {
+    let version = match get_schema_version(storage)? {
+        Some(v) => v,
+        None if storage.is_empty_except(&[META_TREE])? => {
+            if !import_legacy_layout(storage, db_path)? {
+                set_schema_version(storage, SCHEMA_VERSION)?;
+                event!(
+                    Level::INFO,
+                    "New db, schema version set to {:?}",
+                    SCHEMA_VERSION
+                );
+                return Ok(SCHEMA_VERSION);
+            }
+            get_schema_version(storage)?.unwrap_or(1)
+        }
+        None => 1,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::IncompatibleSchema(version, SCHEMA_VERSION));
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        event!(
+            Level::INFO,
+            "Migrating db to schema version {:?}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.run)(storage)?;
+        storage.flush()?;
+        set_schema_version(storage, migration.version)?;
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn new_storage(path: &str) -> Arc<Storage> {
+        let _ = std::fs::remove_dir_all(path);
+        Arc::new(Storage::open(path).unwrap())
+    }
+
+    #[test]
+    fn test_migrate_unversioned_db() {
+        let storage = new_storage("./db_schema_unversioned");
+        // Written as a build without the timestamp index and the leaf keys did.
+        let mut block_info = BlockInfo::default();
+        block_info.storage.block_number = 7;
+        block_info.storage.block_timestamp = 1000;
+        storage
+            .tree("contract-block-info")
+            .insert(
+                bincode::serialize(&7u64).unwrap(),
+                bincode::serialize(&block_info).unwrap(),
+            )
+            .unwrap();
+        let user = Address::from_low_u64_be(1);
+        let token = Address::from_low_u64_be(2);
+        storage
+            .tree("user-tokens")
+            .insert(user, bincode::serialize(&vec![(5u64, token)]).unwrap())
+            .unwrap();
+
+        assert_eq!(check_and_migrate(&storage, "").unwrap(), 1);
+        assert_eq!(get_schema_version(&storage).unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(
+            ContractBlockInfoDB::new(storage.clone())
+                .unwrap()
+                .get_newest_block_info()
+                .unwrap(),
+            Some(block_info)
+        );
+        assert_eq!(
+            UserTokensDB::new(storage.clone())
+                .unwrap()
+                .get_leaf_owner(chain_token_address_convert_to_h256(5, token, user))
+                .unwrap(),
+            Some((5, token, user))
+        );
+        // Already migrated.
+        assert_eq!(check_and_migrate(&storage, "").unwrap(), SCHEMA_VERSION);
+
+        set_schema_version(&storage, SCHEMA_VERSION + 1).unwrap();
+        assert!(matches!(
+            check_and_migrate(&storage, ""),
+            Err(Error::IncompatibleSchema(v, _)) if v == SCHEMA_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_import_legacy_layout() {
+        let db_path = "./db_schema_legacy";
+        let _ = std::fs::remove_dir_all(db_path);
+        {
+            let sled_db = sled::open(db_path).unwrap();
+            sled_db
+                .open_tree(META_TREE)
+                .unwrap()
+                .insert(SCHEMA_VERSION_KEY, bincode::serialize(&2u32).unwrap())
+                .unwrap();
+            let user = Address::from_low_u64_be(1);
+            sled_db
+                .open_tree("user-tokens")
+                .unwrap()
+                .insert(
+                    user,
+                    bincode::serialize(&vec![(5u64, Address::zero())]).unwrap(),
+                )
+                .unwrap();
+            sled_db.flush().unwrap();
+        }
+        let storage = Arc::new(Storage::open(&format!("{}/store", db_path)).unwrap());
+
+        assert_eq!(check_and_migrate(&storage, db_path).unwrap(), 2);
+        assert_eq!(get_schema_version(&storage).unwrap(), Some(SCHEMA_VERSION));
+        let user_tokens_db = UserTokensDB::new(storage.clone()).unwrap();
+        assert_eq!(
+            user_tokens_db
+                .get_tokens(Address::from_low_u64_be(1))
+                .unwrap(),
+            vec![(5, Address::zero())]
+        );
+        // Migration 3 ran on the copied tree.
+        assert!(user_tokens_db
+            .get_leaf_owner(chain_token_address_convert_to_h256(
+                5,
+                Address::zero(),
+                Address::from_low_u64_be(1)
+            ))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_new_db() {
+        let storage = new_storage("./db_schema_new");
+        assert_eq!(check_and_migrate(&storage, "").unwrap(), SCHEMA_VERSION);
+        assert_eq!(get_schema_version(&storage).unwrap(), Some(SCHEMA_VERSION));
+    }
+}
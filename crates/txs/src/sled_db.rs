@@ -1,8 +1,15 @@
 use super::*;
 use bincode;
 use ethers::types::Address;
-use primitives::{error::Result, types::ProfitStatistics};
-use sled::Db;
+use primitives::{
+    error::Result,
+    storage::{Storage, Tree},
+    types::{
+        CrossTxRawData, MissedTx, ProfitStateDiff, ProfitStatistics, SupportSet, TxAuditRecord,
+        TxOutcome,
+    },
+};
+use rocksdb::OptimisticTransaction;
 
 #[derive(Clone)]
 pub struct MakerProfitDB {
@@ -10,9 +17,9 @@ pub struct MakerProfitDB {
 }
 
 impl MakerProfitDB {
-    pub fn new(db: Arc<Db>) -> Result<Self> {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
         Ok(Self {
-            inner: db.open_tree("maker-profit-percent")?,
+            inner: storage.tree("maker-profit-percent"),
         })
     }
 
@@ -43,29 +50,86 @@ impl MakerProfitDB {
     }
 }
 
+/// Tokens of each user, and the owner of each profit leaf (the leaf key is a hash of
+/// `(chain_id, token, address)`, so it can not be decoded).
 #[derive(Clone)]
 pub struct UserTokensDB {
     inner: Tree,
+    leaf_keys: Tree,
 }
 
 impl UserTokensDB {
-    pub fn new(db: Arc<Db>) -> Result<Self> {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
         Ok(Self {
-            inner: db.open_tree("user-tokens")?,
+            inner: storage.tree("user-tokens"),
+            leaf_keys: storage.tree("profit-leaf-keys"),
         })
     }
 
+    /// Databases created before the leaf keys existed only have the user tokens.
+    pub(crate) fn rebuild_leaf_keys(&self) -> Result<()> {
+        if !self.leaf_keys.is_empty() || self.inner.is_empty() {
+            return Ok(());
+        }
+        for (user, tokens) in self.get_all_tokens()? {
+            for (chain_id, token) in tokens {
+                self.insert_leaf_key(user, chain_id, token)?;
+            }
+        }
+        event!(
+            Level::INFO,
+            "Profit leaf keys rebuilt. count: {:?}",
+            self.leaf_keys.len()
+        );
+        Ok(())
+    }
+
+    fn insert_leaf_key(&self, user: Address, chain_id: u64, token: Address) -> Result<()> {
+        self.inner
+            .storage()
+            .transact(|tx| self.insert_leaf_key_in(tx, user, chain_id, token))
+    }
+
+    fn insert_leaf_key_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+    ) -> Result<()> {
+        let path = chain_token_address_convert_to_h256(chain_id, token, user);
+        self.leaf_keys.insert_in(
+            tx,
+            path.as_slice(),
+            bincode::serialize(&(chain_id, token, user))?,
+        )?;
+        Ok(())
+    }
+
     pub fn insert_token(&self, user: Address, chain_id: u64, token: Address) -> Result<()> {
-        if let Some(v) = self.inner.get(user)? {
+        self.inner
+            .storage()
+            .transact(|tx| self.insert_token_in(tx, user, chain_id, token))
+    }
+
+    pub fn insert_token_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+    ) -> Result<()> {
+        if let Some(v) = self.inner.get_in(tx, user)? {
             let mut res: Vec<(u64, Address)> = bincode::deserialize(&v)?;
             res.retain(|i| i != &(chain_id, token));
             res.push((chain_id, token));
             let r = bincode::serialize(&res)?;
-            self.inner.insert(user, r)?;
+            self.inner.insert_in(tx, user, r)?;
         } else {
             let r = bincode::serialize(&vec![(chain_id, token)])?;
-            self.inner.insert(user, r)?;
+            self.inner.insert_in(tx, user, r)?;
         }
+        self.insert_leaf_key_in(tx, user, chain_id, token)?;
         Ok(())
     }
 
@@ -76,6 +140,35 @@ impl UserTokensDB {
         }
         return Ok(vec![]);
     }
+
+    pub fn get_all_tokens(&self) -> Result<Vec<(Address, Vec<(u64, Address)>)>> {
+        let mut res = vec![];
+        for item in self.inner.iter() {
+            let (k, v) = item?;
+            res.push((
+                Address::from_slice(&k),
+                bincode::deserialize::<Vec<(u64, Address)>>(&v)?,
+            ));
+        }
+        Ok(res)
+    }
+
+    pub fn insert_tokens(&self, user: Address, tokens: Vec<(u64, Address)>) -> Result<()> {
+        for (chain_id, token) in tokens.iter() {
+            self.insert_leaf_key(user, *chain_id, *token)?;
+        }
+        let r = bincode::serialize(&tokens)?;
+        self.inner.insert(user, r)?;
+        Ok(())
+    }
+
+    /// `(chain_id, token, address)` of the profit leaf at `path`.
+    pub fn get_leaf_owner(&self, path: H256) -> Result<Option<(u64, Address, Address)>> {
+        if let Some(v) = self.leaf_keys.get(path.as_slice())? {
+            return Ok(Some(bincode::deserialize::<(u64, Address, Address)>(&v)?));
+        }
+        Ok(None)
+    }
 }
 
 #[derive(Clone)]
@@ -84,9 +177,9 @@ pub struct BlockTxsCountDB {
 }
 
 impl BlockTxsCountDB {
-    pub fn new(db: Arc<Db>) -> Result<Self> {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
         Ok(Self {
-            inner: db.open_tree("block-txs-count")?,
+            inner: storage.tree("block-txs-count"),
         })
     }
 
@@ -97,6 +190,18 @@ impl BlockTxsCountDB {
         Ok(())
     }
 
+    pub fn insert_count_in(
+        &self,
+        tx: &OptimisticTransaction,
+        block_num: u64,
+        count: u64,
+    ) -> Result<()> {
+        let k = bincode::serialize(&block_num)?;
+        let v = bincode::serialize(&count)?;
+        self.inner.insert_in(tx, k, v)?;
+        Ok(())
+    }
+
     pub fn get_count(&self, block_num: u64) -> Result<Option<u64>> {
         let k = bincode::serialize(&block_num)?;
         if let Some(v) = self.inner.get(k)? {
@@ -105,6 +210,39 @@ impl BlockTxsCountDB {
         Ok(None)
     }
 
+    pub fn increase_count(&self, block_num: u64, count: u64) -> Result<()> {
+        self.inner
+            .storage()
+            .transact(|tx| self.increase_count_in(tx, block_num, count))
+    }
+
+    pub fn increase_count_in(
+        &self,
+        tx: &OptimisticTransaction,
+        block_num: u64,
+        count: u64,
+    ) -> Result<()> {
+        let k = bincode::serialize(&block_num)?;
+        let old_count = match self.inner.get_in(tx, &k)? {
+            Some(v) => bincode::deserialize::<u64>(&v)?,
+            None => 0,
+        };
+        self.inner
+            .insert_in(tx, k, bincode::serialize(&(old_count + count))?)?;
+        Ok(())
+    }
+
+    /// All `(block_num, count)` pairs, not in block order.
+    pub fn iter_counts(&self) -> impl Iterator<Item = Result<(u64, u64)>> + '_ {
+        self.inner.iter().map(|item| {
+            let (k, v) = item?;
+            Ok((
+                bincode::deserialize::<u64>(&k)?,
+                bincode::deserialize::<u64>(&v)?,
+            ))
+        })
+    }
+
     pub fn is_txs_completed(&self, start_block: u64, end_block: u64) -> Result<bool> {
         let mut is_completed = true;
         for i in start_block..end_block {
@@ -126,19 +264,74 @@ impl BlockTxsCountDB {
 #[derive(Clone)]
 pub struct ContractBlockInfoDB {
     inner: Tree,
+    timestamp_index: Tree,
 }
 
 impl ContractBlockInfoDB {
-    pub fn new(db: Arc<Db>) -> Result<Self> {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
         Ok(Self {
-            inner: db.open_tree("contract-block-info")?,
+            inner: storage.tree("contract-block-info"),
+            timestamp_index: storage.tree("contract-block-timestamp-index"),
         })
     }
 
+    /// The index key is big-endian so that the tree keeps it ordered by timestamp.
+    fn timestamp_index_key(timestamp: u64, block_number: u64) -> Vec<u8> {
+        let mut k = timestamp.to_be_bytes().to_vec();
+        k.extend_from_slice(&block_number.to_be_bytes());
+        k
+    }
+
+    fn decode_timestamp_index_key(k: &[u8]) -> (u64, u64) {
+        let timestamp = u64::from_be_bytes(k[..8].try_into().expect("checked 8 bytes"));
+        let block_number = u64::from_be_bytes(k[8..16].try_into().expect("checked 8 bytes"));
+        (timestamp, block_number)
+    }
+
+    /// Databases created before the index existed only have the block infos.
+    pub(crate) fn rebuild_timestamp_index(&self) -> Result<()> {
+        if !self.timestamp_index.is_empty() || self.inner.is_empty() {
+            return Ok(());
+        }
+        for item in self.inner.iter() {
+            let (_, v) = item?;
+            let block_info = bincode::deserialize::<BlockInfo>(&v)?;
+            self.timestamp_index.insert(
+                Self::timestamp_index_key(
+                    block_info.storage.block_timestamp,
+                    block_info.storage.block_number,
+                ),
+                vec![],
+            )?;
+        }
+        event!(
+            Level::INFO,
+            "Block timestamp index rebuilt. count: {:?}",
+            self.timestamp_index.len()
+        );
+        Ok(())
+    }
+
     pub fn insert_block_info(&self, block_number: u64, info: BlockInfo) -> Result<()> {
+        self.inner
+            .storage()
+            .transact(|tx| self.insert_block_info_in(tx, block_number, &info))
+    }
+
+    pub fn insert_block_info_in(
+        &self,
+        tx: &OptimisticTransaction,
+        block_number: u64,
+        info: &BlockInfo,
+    ) -> Result<()> {
         let k = bincode::serialize(&block_number)?;
-        let v = bincode::serialize(&info)?;
-        self.inner.insert(k, v)?;
+        let v = bincode::serialize(info)?;
+        self.inner.insert_in(tx, k, v)?;
+        self.timestamp_index.insert_in(
+            tx,
+            Self::timestamp_index_key(info.storage.block_timestamp, block_number),
+            vec![],
+        )?;
         Ok(())
     }
 
@@ -150,26 +343,360 @@ impl ContractBlockInfoDB {
         Ok(None)
     }
 
+    /// All saved block infos, not in block order.
+    pub fn iter_block_infos(&self) -> impl Iterator<Item = Result<BlockInfo>> + '_ {
+        self.inner
+            .iter()
+            .map(|item| Ok(bincode::deserialize::<BlockInfo>(&item?.1)?))
+    }
+
+    pub fn get_newest_block_info(&self) -> Result<Option<BlockInfo>> {
+        match self.timestamp_index.last()? {
+            Some((k, _)) => self.get_block_info(Self::decode_timestamp_index_key(&k).1),
+            None => Ok(None),
+        }
+    }
+
+    /// The newest block (not above `newest_block_num`) whose timestamp is <= `timestamp`.
     pub fn get_block_num_by_timestamp(
         &self,
         timestamp: u64,
         newest_block_num: u64,
     ) -> Result<Option<u64>> {
-        let mut num = newest_block_num;
-        if self.inner.is_empty() {
+        let upper = Self::timestamp_index_key(timestamp, u64::MAX);
+        for item in self.timestamp_index.range_rev(..=upper) {
+            let (k, _) = item?;
+            let (_, block_number) = Self::decode_timestamp_index_key(&k);
+            if block_number <= newest_block_num {
+                return Ok(Some(block_number));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Saved block infos from the newest back, in timestamp order.
+    pub fn iter_block_infos_rev(&self) -> impl Iterator<Item = Result<BlockInfo>> + '_ {
+        self.timestamp_index
+            .range_rev::<&[u8], _>(..)
+            .filter_map(move |item| match item {
+                Ok((k, _)) => self
+                    .get_block_info(Self::decode_timestamp_index_key(&k).1)
+                    .transpose(),
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    /// Saved block infos from `timestamp` on, in timestamp order.
+    pub fn iter_block_infos_from(
+        &self,
+        timestamp: u64,
+    ) -> impl Iterator<Item = Result<BlockInfo>> + '_ {
+        self.timestamp_index
+            .range(Self::timestamp_index_key(timestamp, 0)..)
+            .filter_map(move |item| match item {
+                Ok((k, _)) => self
+                    .get_block_info(Self::decode_timestamp_index_key(&k).1)
+                    .transpose(),
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    pub fn get_oldest_block_info(&self) -> Result<Option<BlockInfo>> {
+        match self.timestamp_index.first()? {
+            Some((k, _)) => self.get_block_info(Self::decode_timestamp_index_key(&k).1),
+            None => Ok(None),
+        }
+    }
+
+    /// The block `n` with `timestamp(n) <= timestamp < timestamp(n + 1)`.
+    /// Returns None while block `n + 1` has not been crawled, because the window is still open.
+    pub fn get_target_block_num(&self, timestamp: u64) -> Result<Option<u64>> {
+        let upper = Self::timestamp_index_key(timestamp, u64::MAX);
+        let block_number = match self.timestamp_index.range_rev(..=upper).next() {
+            Some(item) => Self::decode_timestamp_index_key(&item?.0).1,
+            None => return Ok(None),
+        };
+        if self.get_block_info(block_number + 1)?.is_none() {
             return Ok(None);
         }
-        while num > 0 {
-            let k = bincode::serialize(&num)?;
-            if let Some(b) = self.inner.get(k)? {
-                let block_info = bincode::deserialize::<BlockInfo>(&b)?;
-                if block_info.storage.block_timestamp <= timestamp {
-                    return Ok(Some(block_info.storage.block_number));
+        Ok(Some(block_number))
+    }
+}
+
+/// Txs whose target block has not been crawled yet, ordered by target time.
+#[derive(Clone)]
+pub struct PendingTxsDB {
+    inner: Tree,
+}
+
+impl PendingTxsDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("pending-txs"),
+        })
+    }
+
+    fn key(tx: &CrossTxData) -> Vec<u8> {
+        let mut k = tx.target_time.to_be_bytes().to_vec();
+        k.extend_from_slice(tx.target_id.as_slice());
+        k
+    }
+
+    pub fn insert_tx(&self, tx: &CrossTxData) -> Result<()> {
+        let v = bincode::serialize(tx)?;
+        self.inner.insert(Self::key(tx), v)?;
+        Ok(())
+    }
+
+    pub fn insert_tx_in(&self, db_tx: &OptimisticTransaction, tx: &CrossTxData) -> Result<()> {
+        let v = bincode::serialize(tx)?;
+        self.inner.insert_in(db_tx, Self::key(tx), v)?;
+        Ok(())
+    }
+
+    pub fn remove_tx(&self, tx: &CrossTxData) -> Result<()> {
+        self.inner.remove(Self::key(tx))?;
+        Ok(())
+    }
+
+    pub fn remove_tx_in(&self, db_tx: &OptimisticTransaction, tx: &CrossTxData) -> Result<()> {
+        self.inner.remove_in(db_tx, Self::key(tx))?;
+        Ok(())
+    }
+
+    /// Txs with `target_time` < `timestamp`, in milliseconds.
+    pub fn get_txs_before(&self, timestamp: u64) -> Result<Vec<CrossTxData>> {
+        let mut txs = vec![];
+        for item in self.inner.range(..timestamp.to_be_bytes().to_vec()) {
+            let (_, v) = item?;
+            txs.push(bincode::deserialize::<CrossTxData>(&v)?);
+        }
+        Ok(txs)
+    }
+}
+
+/// Late txs that could not be folded into a block any more.
+#[derive(Clone)]
+pub struct MissedTxsDB {
+    inner: Tree,
+}
+
+impl MissedTxsDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("missed-txs"),
+        })
+    }
+
+    pub fn insert_missed_tx(&self, missed_tx: MissedTx) -> Result<()> {
+        let v = bincode::serialize(&missed_tx)?;
+        self.inner.insert(missed_tx.tx.target_id.as_slice(), v)?;
+        Ok(())
+    }
+
+    pub fn insert_missed_tx_in(
+        &self,
+        tx: &OptimisticTransaction,
+        missed_tx: MissedTx,
+    ) -> Result<()> {
+        let v = bincode::serialize(&missed_tx)?;
+        self.inner
+            .insert_in(tx, missed_tx.tx.target_id.as_slice(), v)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, tx_hash: H256) -> Result<bool> {
+        Ok(self.inner.contains_key(tx_hash.as_slice())?)
+    }
+
+    pub fn get_missed_tx(&self, tx_hash: H256) -> Result<Option<MissedTx>> {
+        match self.inner.get(tx_hash.as_slice())? {
+            Some(v) => Ok(Some(bincode::deserialize::<MissedTx>(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_missed_txs(&self) -> Result<Vec<MissedTx>> {
+        let mut missed_txs = vec![];
+        for item in self.inner.iter() {
+            let (_, v) = item?;
+            missed_txs.push(bincode::deserialize::<MissedTx>(&v)?);
+        }
+        Ok(missed_txs)
+    }
+}
+
+/// History of the supported chains and tokens, keyed by the block they apply from.
+#[derive(Clone)]
+pub struct SupportSetDB {
+    inner: Tree,
+}
+
+impl SupportSetDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("support-set-history"),
+        })
+    }
+
+    pub fn insert_support_set(&self, support_set: SupportSet) -> Result<()> {
+        let v = bincode::serialize(&support_set)?;
+        self.inner.insert(support_set.from_block.to_be_bytes(), v)?;
+        Ok(())
+    }
+
+    /// The set that applies at `block_num`, the newest one from `block_num` or before. None
+    /// before the first set.
+    pub fn get_support_set(&self, block_num: u64) -> Result<Option<SupportSet>> {
+        if let Some(item) = self.inner.range_rev(..=block_num.to_be_bytes()).next() {
+            let (_, v) = item?;
+            return Ok(Some(bincode::deserialize::<SupportSet>(&v)?));
+        }
+        Ok(None)
+    }
+
+    /// The newest set, which may not apply yet.
+    pub fn get_latest_support_set(&self) -> Result<Option<SupportSet>> {
+        if let Some((_, v)) = self.inner.last()? {
+            return Ok(Some(bincode::deserialize::<SupportSet>(&v)?));
+        }
+        Ok(None)
+    }
+
+    /// Union of the chains of every set that applies within `[from_block, to_block]`.
+    pub fn get_support_chains_in_range(&self, from_block: u64, to_block: u64) -> Result<Vec<u64>> {
+        let mut chains = vec![];
+        if let Some(s) = self.get_support_set(from_block)? {
+            chains.extend(s.chains);
+        }
+        if from_block < to_block {
+            for item in self
+                .inner
+                .range((from_block + 1).to_be_bytes()..=to_block.to_be_bytes())
+            {
+                let (_, v) = item?;
+                chains.extend(bincode::deserialize::<SupportSet>(&v)?.chains);
+            }
+        }
+        Ok(vec_unique(chains))
+    }
+
+    pub fn get_history(&self) -> Result<Vec<SupportSet>> {
+        let mut history = vec![];
+        for item in self.inner.iter() {
+            let (_, v) = item?;
+            history.push(bincode::deserialize::<SupportSet>(&v)?);
+        }
+        Ok(history)
+    }
+}
+
+/// Profit leaves changed by each archived block. Key: block number (big endian) ++ leaf path.
+/// A key of the block number alone marks the block as tracked, even if nothing changed.
+#[derive(Clone)]
+pub struct ProfitChangesDB {
+    inner: Tree,
+}
+
+impl ProfitChangesDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("profit-changes"),
+        })
+    }
+
+    pub fn insert_changes(
+        &self,
+        block_num: u64,
+        changes: Vec<(H256, ProfitStateDiff)>,
+    ) -> Result<()> {
+        self.inner
+            .storage()
+            .transact(|tx| self.insert_changes_in(tx, block_num, changes.clone()))
+    }
+
+    pub fn insert_changes_in(
+        &self,
+        tx: &OptimisticTransaction,
+        block_num: u64,
+        changes: Vec<(H256, ProfitStateDiff)>,
+    ) -> Result<()> {
+        self.inner.insert_in(tx, block_num.to_be_bytes(), vec![])?;
+        for (path, diff) in changes {
+            let mut k = block_num.to_be_bytes().to_vec();
+            k.extend_from_slice(path.as_slice());
+            self.inner.insert_in(tx, k, bincode::serialize(&diff)?)?;
+        }
+        Ok(())
+    }
+
+    /// The last block whose changes are tracked.
+    pub fn get_last_block(&self) -> Result<Option<u64>> {
+        Ok(self
+            .inner
+            .last()?
+            .map(|(k, _)| u64::from_be_bytes(k[..8].try_into().expect("checked 8 bytes"))))
+    }
+
+    /// Changes of a single block.
+    pub fn get_changes(&self, block_num: u64) -> Result<Vec<ProfitStateDiff>> {
+        let mut changes = vec![];
+        for item in self.inner.scan_prefix(block_num.to_be_bytes()) {
+            let (k, v) = item?;
+            if k.len() == 8 {
+                continue;
+            }
+            changes.push(bincode::deserialize::<ProfitStateDiff>(&v)?);
+        }
+        Ok(changes)
+    }
+
+    /// Leaves changed between the end of `from_block` and the end of `to_block`, merged so each
+    /// leaf keeps its first old value and last new value. Returns None if a block in the range
+    /// was not tracked.
+    pub fn get_state_diff(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Option<Vec<ProfitStateDiff>>> {
+        if from_block >= to_block {
+            return Ok(Some(vec![]));
+        }
+        let mut tracked = 0u64;
+        let mut paths: Vec<Vec<u8>> = vec![];
+        let mut diffs: HashMap<Vec<u8>, ProfitStateDiff> = HashMap::new();
+        for item in self
+            .inner
+            .range((from_block + 1).to_be_bytes()..(to_block + 1).to_be_bytes())
+        {
+            let (k, v) = item?;
+            if k.len() == 8 {
+                tracked += 1;
+                continue;
+            }
+            let path = k[8..].to_vec();
+            let diff = bincode::deserialize::<ProfitStateDiff>(&v)?;
+            match diffs.get_mut(&path) {
+                Some(d) => {
+                    d.new_balance = diff.new_balance;
+                    d.new_debt = diff.new_debt;
+                }
+                None => {
+                    paths.push(path.clone());
+                    diffs.insert(path, diff);
                 }
             }
-            num -= 1;
         }
-        Ok(None)
+        if tracked != to_block - from_block {
+            return Ok(None);
+        }
+        Ok(Some(
+            paths
+                .into_iter()
+                .filter_map(|p| diffs.remove(&p))
+                .filter(|d| d.old_balance != d.new_balance || d.old_debt != d.new_debt)
+                .collect(),
+        ))
     }
 }
 
@@ -179,12 +706,44 @@ pub struct ProfitStatisticsDB {
 }
 
 impl ProfitStatisticsDB {
-    pub fn new(db: Arc<Db>) -> Result<Self> {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
         Ok(Self {
-            inner: db.open_tree("profit-statistics")?,
+            inner: storage.tree("profit-statistics"),
         })
     }
 
+    // Read, update and write back the statistics of a user's token through `tx`.
+    fn update_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        f: impl FnOnce(&mut ProfitStatistics),
+    ) -> Result<()> {
+        let k = bincode::serialize(&(user, chain_id, token))?;
+        let mut profit_statistics = match self.inner.get_in(tx, &k)? {
+            Some(v) => bincode::deserialize::<ProfitStatistics>(&v)?,
+            None => ProfitStatistics::default(),
+        };
+        f(&mut profit_statistics);
+        let r = bincode::serialize(&profit_statistics)?;
+        self.inner.insert_in(tx, k, r)?;
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        f: impl Fn(&mut ProfitStatistics),
+    ) -> Result<()> {
+        self.inner
+            .storage()
+            .transact(|tx| self.update_in(tx, user, chain_id, token, &f))
+    }
+
     pub fn update_total_profit(
         &self,
         user: Address,
@@ -192,23 +751,18 @@ impl ProfitStatisticsDB {
         token: Address,
         amount: U256,
     ) -> Result<()> {
-        let k = bincode::serialize(&(user, chain_id, token))?;
-        if let Some(v) = self.inner.get(k.clone())? {
-            let mut profit_statistics = bincode::deserialize::<ProfitStatistics>(&v)?;
-            profit_statistics.total_profit += amount;
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        } else {
-            let profit_statistics = ProfitStatistics {
-                total_profit: amount,
-                total_withdrawn: U256::zero(),
-                total_deposit: U256::zero(),
-            };
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        }
+        self.update(user, chain_id, token, |s| s.total_profit += amount)
+    }
 
-        Ok(())
+    pub fn update_total_profit_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        amount: U256,
+    ) -> Result<()> {
+        self.update_in(tx, user, chain_id, token, |s| s.total_profit += amount)
     }
 
     pub fn update_total_withdraw(
@@ -218,23 +772,18 @@ impl ProfitStatisticsDB {
         token: Address,
         amount: U256,
     ) -> Result<()> {
-        let k = bincode::serialize(&(user, chain_id, token))?;
-        if let Some(v) = self.inner.get(k.clone())? {
-            let mut profit_statistics = bincode::deserialize::<ProfitStatistics>(&v)?;
-            profit_statistics.total_withdrawn += amount;
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        } else {
-            let profit_statistics = ProfitStatistics {
-                total_profit: U256::zero(),
-                total_withdrawn: amount,
-                total_deposit: U256::zero(),
-            };
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        }
+        self.update(user, chain_id, token, |s| s.total_withdrawn += amount)
+    }
 
-        Ok(())
+    pub fn update_total_withdraw_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        amount: U256,
+    ) -> Result<()> {
+        self.update_in(tx, user, chain_id, token, |s| s.total_withdrawn += amount)
     }
 
     pub fn update_total_deposit(
@@ -244,23 +793,18 @@ impl ProfitStatisticsDB {
         token: Address,
         amount: U256,
     ) -> Result<()> {
-        let k = bincode::serialize(&(user, chain_id, token))?;
-        if let Some(v) = self.inner.get(k.clone())? {
-            let mut profit_statistics = bincode::deserialize::<ProfitStatistics>(&v)?;
-            profit_statistics.total_deposit += amount;
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        } else {
-            let profit_statistics = ProfitStatistics {
-                total_profit: U256::zero(),
-                total_withdrawn: U256::zero(),
-                total_deposit: amount,
-            };
-            let r = bincode::serialize(&profit_statistics)?;
-            self.inner.insert(k, r)?;
-        }
+        self.update(user, chain_id, token, |s| s.total_deposit += amount)
+    }
 
-        Ok(())
+    pub fn update_total_deposit_in(
+        &self,
+        tx: &OptimisticTransaction,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        amount: U256,
+    ) -> Result<()> {
+        self.update_in(tx, user, chain_id, token, |s| s.total_deposit += amount)
     }
 
     pub fn get_profit_statistics(
@@ -275,4 +819,280 @@ impl ProfitStatisticsDB {
         }
         Ok(None)
     }
+
+    pub fn get_all_profit_statistics(
+        &self,
+    ) -> Result<Vec<((Address, u64, Address), ProfitStatistics)>> {
+        let mut res = vec![];
+        for item in self.inner.iter() {
+            let (k, v) = item?;
+            res.push((
+                bincode::deserialize::<(Address, u64, Address)>(&k)?,
+                bincode::deserialize::<ProfitStatistics>(&v)?,
+            ));
+        }
+        Ok(res)
+    }
+
+    pub fn insert_profit_statistics(
+        &self,
+        user: Address,
+        chain_id: u64,
+        token: Address,
+        profit_statistics: ProfitStatistics,
+    ) -> Result<()> {
+        let k = bincode::serialize(&(user, chain_id, token))?;
+        let r = bincode::serialize(&profit_statistics)?;
+        self.inner.insert(k, r)?;
+        Ok(())
+    }
+}
+
+/// Hashes of the txs archived with each block whose tx bodies were pruned, the ones
+/// `BlocksStateData.txs` is computed from. Blocks without txs have no entry.
+pub struct PrunedTxsDB {
+    hashes: Tree,
+    meta: Tree,
+}
+
+impl PrunedTxsDB {
+    const PRUNED_THROUGH_KEY: &'static [u8] = b"pruned-through";
+
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            hashes: storage.tree("pruned-txs-hashes"),
+            meta: storage.tree("pruned-txs-meta"),
+        })
+    }
+
+    pub fn insert_hashes_in(
+        &self,
+        tx: &OptimisticTransaction,
+        block_num: u64,
+        hashes: Vec<H256>,
+    ) -> Result<()> {
+        self.hashes
+            .insert_in(tx, block_num.to_be_bytes(), bincode::serialize(&hashes)?)?;
+        Ok(())
+    }
+
+    /// The hashes of the txs archived with `block_num`, or None if its txs are not pruned.
+    pub fn get_hashes(&self, block_num: u64) -> Result<Option<Vec<H256>>> {
+        if self.get_pruned_through()?.map_or(true, |b| block_num > b) {
+            return Ok(None);
+        }
+        match self.hashes.get(block_num.to_be_bytes())? {
+            Some(v) => Ok(Some(bincode::deserialize::<Vec<H256>>(&v)?)),
+            None => Ok(Some(vec![])),
+        }
+    }
+
+    pub fn set_pruned_through_in(&self, tx: &OptimisticTransaction, block_num: u64) -> Result<()> {
+        self.meta.insert_in(
+            tx,
+            Self::PRUNED_THROUGH_KEY,
+            bincode::serialize(&block_num)?,
+        )?;
+        Ok(())
+    }
+
+    /// The txs archived with this block and every earlier one are pruned.
+    pub fn get_pruned_through(&self) -> Result<Option<u64>> {
+        match self.meta.get(Self::PRUNED_THROUGH_KEY)? {
+            Some(v) => Ok(Some(bincode::deserialize::<u64>(&v)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The source record of each crawled tx and what the crawler did with it, keyed by target id.
+/// Records with a target block are also indexed by it, so the records of pruned blocks can be
+/// removed with their txs.
+#[derive(Clone)]
+pub struct TxAuditDB {
+    inner: Tree,
+    block_index: Tree,
+}
+
+impl TxAuditDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("tx-audit"),
+            block_index: storage.tree("tx-audit-blocks"),
+        })
+    }
+
+    fn block_index_key(target_block: u64, target_id: H256) -> Vec<u8> {
+        let mut k = target_block.to_be_bytes().to_vec();
+        k.extend_from_slice(target_id.as_slice());
+        k
+    }
+
+    /// Databases created before the index existed only have the records.
+    pub(crate) fn rebuild_block_index(&self) -> Result<()> {
+        if !self.block_index.is_empty() || self.inner.is_empty() {
+            return Ok(());
+        }
+        for item in self.inner.iter() {
+            let (k, v) = item?;
+            let record = bincode::deserialize::<TxAuditRecord>(&v)?;
+            if let Some(b) = record.target_block {
+                self.block_index
+                    .insert(Self::block_index_key(b, H256::from_slice(&k)), vec![])?;
+            }
+        }
+        event!(
+            Level::INFO,
+            "Tx audit block index rebuilt. count: {:?}",
+            self.block_index.len()
+        );
+        Ok(())
+    }
+
+    /// Record the outcome of a tx. Without `raw` only a tx recorded before is updated, e.g. a
+    /// pending tx that is retried.
+    pub fn record_in(
+        &self,
+        tx: &OptimisticTransaction,
+        target_id: H256,
+        raw: Option<CrossTxRawData>,
+        outcome: TxOutcome,
+        target_block: Option<u64>,
+    ) -> Result<()> {
+        let old = match self.inner.get_in(tx, target_id.as_slice())? {
+            Some(v) => Some(bincode::deserialize::<TxAuditRecord>(&v)?),
+            None => None,
+        };
+        let old_block = old.as_ref().and_then(|r| r.target_block);
+        let mut record = match (raw, old) {
+            (Some(raw), _) => TxAuditRecord {
+                raw,
+                ..Default::default()
+            },
+            (None, Some(record)) => record,
+            (None, None) => return Ok(()),
+        };
+        record.outcome = outcome;
+        record.target_block = target_block;
+        self.inner
+            .insert_in(tx, target_id.as_slice(), bincode::serialize(&record)?)?;
+        if old_block != target_block {
+            if let Some(b) = old_block {
+                self.block_index
+                    .remove_in(tx, Self::block_index_key(b, target_id))?;
+            }
+            if let Some(b) = target_block {
+                self.block_index
+                    .insert_in(tx, Self::block_index_key(b, target_id), vec![])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_record(&self, target_id: H256) -> Result<Option<TxAuditRecord>> {
+        match self.inner.get(target_id.as_slice())? {
+            Some(v) => Ok(Some(bincode::deserialize::<TxAuditRecord>(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove the records of the txs with a target block up to `target_block`.
+    pub fn remove_through_in(&self, tx: &OptimisticTransaction, target_block: u64) -> Result<()> {
+        for item in self
+            .block_index
+            .range(..=Self::block_index_key(target_block, H256::repeat_byte(0xff)))
+        {
+            let (k, _) = item?;
+            self.inner.remove_in(tx, &k[8..])?;
+            self.block_index.remove_in(tx, &k)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_state_diff() {
+        let path = "./db_state_diff";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let profit_changes_db = ProfitChangesDB::new(storage).unwrap();
+        let path = H256::from([1u8; 32]);
+        let diff = |old: u64, new: u64| ProfitStateDiff {
+            old_balance: U256::from(old),
+            new_balance: U256::from(new),
+            ..Default::default()
+        };
+        profit_changes_db
+            .insert_changes(10, vec![(path, diff(0, 5))])
+            .unwrap();
+        profit_changes_db.insert_changes(11, vec![]).unwrap();
+        profit_changes_db
+            .insert_changes(12, vec![(path, diff(5, 0))])
+            .unwrap();
+
+        assert_eq!(
+            profit_changes_db.get_state_diff(9, 11).unwrap(),
+            Some(vec![diff(0, 5)])
+        );
+        assert_eq!(
+            profit_changes_db.get_state_diff(9, 12).unwrap(),
+            Some(vec![])
+        );
+        assert_eq!(
+            profit_changes_db.get_state_diff(10, 12).unwrap(),
+            Some(vec![diff(5, 0)])
+        );
+        // Block 13 is not tracked.
+        assert_eq!(profit_changes_db.get_state_diff(10, 13).unwrap(), None);
+    }
+
+    #[test]
+    fn test_support_set_history() {
+        let path = "./db_support_set";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let support_set_db = SupportSetDB::new(storage).unwrap();
+        assert_eq!(support_set_db.get_support_set(100).unwrap(), None);
+
+        let set = |from_block: u64, chains: Vec<u64>| SupportSet {
+            from_block,
+            chains,
+            tokens: vec![],
+        };
+        support_set_db
+            .insert_support_set(set(100, vec![1]))
+            .unwrap();
+        support_set_db
+            .insert_support_set(set(200, vec![1, 2]))
+            .unwrap();
+
+        // Not applied before the first set.
+        assert_eq!(support_set_db.get_support_set(99).unwrap(), None);
+        assert_eq!(
+            support_set_db.get_support_set(199).unwrap(),
+            Some(set(100, vec![1]))
+        );
+        assert_eq!(
+            support_set_db.get_support_set(200).unwrap(),
+            Some(set(200, vec![1, 2]))
+        );
+        assert_eq!(
+            support_set_db.get_support_set(300).unwrap(),
+            Some(set(200, vec![1, 2]))
+        );
+        assert_eq!(
+            support_set_db.get_support_chains_in_range(50, 150).unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            support_set_db
+                .get_support_chains_in_range(150, 250)
+                .unwrap(),
+            vec![1, 2]
+        );
+    }
 }
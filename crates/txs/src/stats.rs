@@ -0,0 +1,323 @@
+//! Hourly rollups of the saved txs, behind the statistics rpcs.
+//!
+//! Every tx is counted in the hour of its target time, for its dealer, its maker and its source
+//! chain. `TxsRocksDB` updates the rollups in the transaction that saves the tx, and takes the old
+//! profit out when a tx is saved again. Pruning leaves them alone, so pruned txs are still counted.
+//! A tx whose source amount is not a decimal is counted in its chain rollup without volume, and in
+//! the `UNREAD_VOLUME` rollup of the same chain.
+
+use super::*;
+use primitives::{
+    error::Result,
+    types::{AddressTxStats, ChainTxStats},
+};
+use rocksdb::OptimisticTransaction;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const STATS_BUCKET_SECONDS: u64 = 3600;
+
+const DEALER: u8 = 0;
+const MAKER: u8 = 1;
+const CHAIN: u8 = 2;
+// Only counts txs, keyed like `CHAIN`.
+const UNREAD_VOLUME: u8 = 3;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Rollup {
+    txs: u64,
+    // Credited to the dealer, or paid by the maker.
+    profit: U256,
+    // Sum of the source amounts, in token units.
+    volume: String,
+}
+
+impl Default for Rollup {
+    fn default() -> Self {
+        Self {
+            txs: 0,
+            profit: U256::zero(),
+            volume: Decimal::ZERO.to_string(),
+        }
+    }
+}
+
+// Rollup volumes are written from a `Decimal`, so one that is not is a corrupt value.
+fn parse_volume(volume: &str) -> Result<Decimal> {
+    Decimal::from_str(volume).map_err(|e| {
+        Box::new(bincode::ErrorKind::Custom(format!(
+            "rollup volume {:?}: {:?}",
+            volume, e
+        )))
+        .into()
+    })
+}
+
+/// Key: kind ++ hour (big endian) ++ dealer/maker ++ token, or kind ++ hour ++ chain ++ token.
+#[derive(Clone)]
+pub struct TxStatsDB {
+    inner: Tree,
+}
+
+impl TxStatsDB {
+    pub fn new(storage: Arc<Storage>) -> Result<Self> {
+        Ok(Self {
+            inner: storage.tree("tx-stats"),
+        })
+    }
+
+    fn key(kind: u8, bucket: u64, entity: &[u8], token: Address) -> Vec<u8> {
+        let mut k = vec![kind];
+        k.extend_from_slice(&bucket.to_be_bytes());
+        k.extend_from_slice(entity);
+        k.extend_from_slice(token.as_bytes());
+        k
+    }
+
+    /// Add a saved tx to its rollups, or take it out with `remove`.
+    pub fn update_in(
+        &self,
+        db_tx: &OptimisticTransaction,
+        tx: &CrossTxData,
+        profit: &CrossTxProfit,
+        remove: bool,
+    ) -> Result<()> {
+        let bucket = tx.target_time / 1000 / STATS_BUCKET_SECONDS;
+        let chain_key = |kind| {
+            Self::key(
+                kind,
+                bucket,
+                &tx.source_chain.to_be_bytes(),
+                tx.source_token,
+            )
+        };
+        let mut entries = vec![
+            (
+                Self::key(
+                    DEALER,
+                    bucket,
+                    profit.dealer_address.as_bytes(),
+                    profit.token,
+                ),
+                profit.profit,
+                Decimal::ZERO,
+            ),
+            (
+                Self::key(MAKER, bucket, profit.maker_address.as_bytes(), profit.token),
+                profit.profit,
+                Decimal::ZERO,
+            ),
+        ];
+        match Decimal::from_str(&tx.source_amount) {
+            Ok(volume) => entries.push((chain_key(CHAIN), U256::zero(), volume)),
+            Err(e) => {
+                if !remove {
+                    event!(
+                        Level::WARN,
+                        "Source amount {:?} of tx {:?} is not a decimal, its volume is not counted: {:?}",
+                        tx.source_amount,
+                        tx.target_id,
+                        e
+                    );
+                }
+                entries.push((chain_key(CHAIN), U256::zero(), Decimal::ZERO));
+                entries.push((chain_key(UNREAD_VOLUME), U256::zero(), Decimal::ZERO));
+            }
+        }
+        for (key, amount, volume) in entries {
+            let mut rollup = match self.inner.get_in(db_tx, &key)? {
+                Some(v) => bincode::deserialize::<Rollup>(&v)?,
+                None => Rollup::default(),
+            };
+            let old_volume = parse_volume(&rollup.volume)?;
+            if remove {
+                rollup.txs = rollup.txs.saturating_sub(1);
+                rollup.profit = rollup.profit.saturating_sub(amount);
+                rollup.volume = max(old_volume.saturating_sub(volume), Decimal::ZERO).to_string();
+            } else {
+                rollup.txs += 1;
+                rollup.profit = rollup.profit.saturating_add(amount);
+                rollup.volume = old_volume.saturating_add(volume).to_string();
+            }
+            if rollup.txs == 0 {
+                self.inner.remove_in(db_tx, &key)?;
+            } else {
+                self.inner
+                    .insert_in(db_tx, &key, bincode::serialize(&rollup)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn clear(&self) -> Result<()> {
+        self.inner.clear()
+    }
+
+    /// Rollups of `kind` in the hours overlapping [start_timestamp, end_timestamp) seconds,
+    /// merged by entity and token.
+    fn sum(
+        &self,
+        kind: u8,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> Result<BTreeMap<Vec<u8>, Rollup>> {
+        let from = start_timestamp / STATS_BUCKET_SECONDS;
+        let to = end_timestamp.saturating_add(STATS_BUCKET_SECONDS - 1) / STATS_BUCKET_SECONDS;
+        let mut start = vec![kind];
+        start.extend_from_slice(&from.to_be_bytes());
+        let mut end = vec![kind];
+        end.extend_from_slice(&to.to_be_bytes());
+        let mut sums: BTreeMap<Vec<u8>, Rollup> = BTreeMap::new();
+        for item in self.inner.range(start..end) {
+            let (k, v) = item?;
+            let rollup = bincode::deserialize::<Rollup>(&v)?;
+            let sum = sums.entry(k[9..].to_vec()).or_default();
+            sum.txs += rollup.txs;
+            sum.profit = sum.profit.saturating_add(rollup.profit);
+            sum.volume = parse_volume(&sum.volume)?
+                .saturating_add(parse_volume(&rollup.volume)?)
+                .to_string();
+        }
+        Ok(sums)
+    }
+
+    fn address_stats(
+        &self,
+        kind: u8,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        address: Option<Address>,
+        token: Option<Address>,
+    ) -> Result<Vec<AddressTxStats>> {
+        Ok(self
+            .sum(kind, start_timestamp, end_timestamp)?
+            .into_iter()
+            .map(|(k, rollup)| AddressTxStats {
+                address: Address::from_slice(&k[..20]),
+                token: Address::from_slice(&k[20..]),
+                txs: rollup.txs,
+                profit: rollup.profit,
+            })
+            .filter(|s| address.map_or(true, |a| a == s.address))
+            .filter(|s| token.map_or(true, |t| t == s.token))
+            .collect())
+    }
+
+    /// Profit credited to dealers per token, for every dealer without `dealer`.
+    pub fn get_dealer_stats(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        dealer: Option<Address>,
+        token: Option<Address>,
+    ) -> Result<Vec<AddressTxStats>> {
+        self.address_stats(DEALER, start_timestamp, end_timestamp, dealer, token)
+    }
+
+    /// The `limit` dealers and tokens with the most profit, most first.
+    pub fn get_top_dealers(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        token: Option<Address>,
+        limit: usize,
+    ) -> Result<Vec<AddressTxStats>> {
+        let mut stats = self.get_dealer_stats(start_timestamp, end_timestamp, None, token)?;
+        stats.sort_by(|a, b| b.profit.cmp(&a.profit));
+        stats.truncate(limit);
+        Ok(stats)
+    }
+
+    /// Fees paid by makers to dealers per token, for every maker without `maker`.
+    pub fn get_maker_stats(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+        maker: Option<Address>,
+    ) -> Result<Vec<AddressTxStats>> {
+        self.address_stats(MAKER, start_timestamp, end_timestamp, maker, None)
+    }
+
+    /// Txs and volume per source chain and token.
+    pub fn get_chain_stats(
+        &self,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> Result<Vec<ChainTxStats>> {
+        let unread = self.sum(UNREAD_VOLUME, start_timestamp, end_timestamp)?;
+        self.sum(CHAIN, start_timestamp, end_timestamp)?
+            .into_iter()
+            .map(|(k, rollup)| {
+                Ok(ChainTxStats {
+                    chain_id: u64::from_be_bytes(k[..8].try_into().expect("checked 8 bytes")),
+                    token: Address::from_slice(&k[8..]),
+                    txs: rollup.txs,
+                    volume: parse_volume(&rollup.volume)?.to_string(),
+                    unread_volume_txs: unread.get(&k).map_or(0, |r| r.txs),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::fixtures::{test_tx, DEALER, MAKER};
+
+    #[test]
+    fn test_tx_stats() {
+        let path = "./db_tx_stats";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let txs_db = TxsRocksDB::new(storage.clone()).unwrap();
+        let stats_db = TxStatsDB::new(storage.clone()).unwrap();
+        let tx = |time: u64, profit: u64| {
+            let (mut tx, profit) = test_tx(time, profit);
+            tx.source_chain = 5;
+            tx.source_amount = "0.5".to_string();
+            (tx, profit)
+        };
+        txs_db
+            .insert_txs(vec![tx(10, 1), tx(20, 2), tx(3600, 4)])
+            .unwrap();
+        // Saved again with another profit.
+        txs_db.insert_txs(vec![tx(20, 3)]).unwrap();
+
+        let stats = stats_db
+            .get_dealer_stats(0, 3600, Some(DEALER), None)
+            .unwrap();
+        assert_eq!((stats[0].txs, stats[0].profit), (2, U256::from(4)));
+        let stats = stats_db.get_top_dealers(0, 7200, None, 10).unwrap();
+        assert_eq!((stats[0].txs, stats[0].profit), (3, U256::from(8)));
+        let stats = stats_db.get_maker_stats(3600, 7200, None).unwrap();
+        assert_eq!((stats[0].address, stats[0].profit), (MAKER, U256::from(4)));
+        let stats = stats_db.get_chain_stats(0, 7200).unwrap();
+        assert_eq!(
+            (stats[0].chain_id, stats[0].txs, stats[0].volume.as_str()),
+            (5, 3, "1.5")
+        );
+        assert_eq!(stats[0].unread_volume_txs, 0);
+        let (mut unread, profit) = tx(30, 0);
+        unread.source_amount = "1e40".to_string();
+        txs_db.insert_txs(vec![(unread, profit)]).unwrap();
+        let stats = stats_db.get_chain_stats(0, 7200).unwrap();
+        assert_eq!(
+            (
+                stats[0].txs,
+                stats[0].volume.as_str(),
+                stats[0].unread_volume_txs
+            ),
+            (4, "1.5", 1)
+        );
+        assert!(stats_db
+            .get_dealer_stats(7200, 10800, None, None)
+            .unwrap()
+            .is_empty());
+        // Twice in one batch, counted once.
+        txs_db.insert_txs(vec![tx(7200, 1), tx(7200, 1)]).unwrap();
+        let stats = stats_db.get_dealer_stats(7200, 10800, None, None).unwrap();
+        assert_eq!((stats[0].txs, stats[0].profit), (1, U256::from(1)));
+    }
+}
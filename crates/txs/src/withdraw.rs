@@ -0,0 +1,280 @@
+//! Withdraw calls to the FeeManager contract, with proofs against the last submitted root.
+
+use super::*;
+use contract::{get_submitted_root, simulate_withdraw, WithdrawArgs};
+use ethers::providers::{Http, Provider};
+use primitives::{
+    env::get_fee_manager_contract_address,
+    types::{ProfitProof, WithdrawCall},
+};
+use state::{SmtValue, StateProofs};
+use utils::get_no1_merge_value;
+
+/// Proofs of `keys` against the profit root archived with `block_num`. The changes of the later
+/// blocks are undone in a transaction that is never committed, so the state is left as it is.
+pub fn get_profit_proofs_at(
+    storage: &Arc<Storage>,
+    profit_state: &Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    profit_changes_db: &ProfitChangesDB,
+    block_num: u64,
+    keys: Vec<H256>,
+) -> anyhow::Result<StateProofs<ProfitStateData>> {
+    let archived = blocks_state
+        .read()
+        .unwrap()
+        .try_get(block_number_convert_to_h256(block_num))?;
+    if archived == BlocksStateData::default() {
+        return Err(BlockNotArchived(block_num).into());
+    }
+
+    // Held so that no block is archived while the state is rewound.
+    let profit_state = profit_state.read().unwrap();
+    let last_block = profit_changes_db
+        .get_last_block()?
+        .unwrap_or(block_num)
+        .max(block_num);
+    let db_tx = storage.transaction();
+    if last_block > block_num {
+        let leaves: Vec<(H256, ProfitStateData)> = profit_changes_db
+            .get_state_diff(block_num, last_block)?
+            .ok_or(anyhow::anyhow!(
+                "changes of Block #{:} - #{:} are not tracked",
+                block_num + 1,
+                last_block
+            ))?
+            .into_iter()
+            .map(|d| {
+                let mut leaf = ProfitStateData {
+                    token: d.token,
+                    token_chain_id: d.chain_id,
+                    balance: d.old_balance,
+                    debt: d.old_debt,
+                };
+                leaf.try_clear().map_err(|e| anyhow::anyhow!(e))?;
+                Ok((
+                    chain_token_address_convert_to_h256(d.chain_id, d.token, d.address),
+                    leaf,
+                ))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        profit_state.try_update_all_in(&db_tx, leaves)?;
+    }
+    // `db_tx` is dropped without a commit.
+    let proofs = profit_state.try_get_proofs_in(&db_tx, keys, false)?;
+    if <[u8; 32]>::from(proofs.root) != archived.profit_root {
+        return Err(anyhow::anyhow!(
+            "profit root rewound to Block #{:} does not match the archived one",
+            block_num
+        ));
+    }
+    Ok(proofs)
+}
+
+/// Build the `withdrawVerification` call of `address` for `tokens` against the last submitted
+/// root, and simulate it. The whole balance of each leaf is withdrawn unless `amounts` is given.
+pub async fn build_withdraw_call(
+    provider: &Provider<Http>,
+    storage: &Arc<Storage>,
+    profit_state: &Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>>,
+    blocks_state: &Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>>,
+    profit_changes_db: &ProfitChangesDB,
+    address: Address,
+    tokens: Vec<(u64, Address)>,
+    amounts: Option<Vec<U256>>,
+) -> anyhow::Result<WithdrawCall> {
+    if tokens.is_empty() {
+        return Err(anyhow::anyhow!("no tokens to withdraw"));
+    }
+    if amounts.as_ref().map_or(false, |a| a.len() != tokens.len()) {
+        return Err(anyhow::anyhow!("one amount per token is required"));
+    }
+    // The submitted root covers the blocks before its end block.
+    let (_, end_block, profit_root) = get_submitted_root(provider).await?;
+    let block_number = end_block
+        .checked_sub(1)
+        .ok_or(anyhow::anyhow!("no root is submitted"))?;
+    let paths: Vec<H256> = tokens
+        .iter()
+        .map(|(chain_id, token)| chain_token_address_convert_to_h256(*chain_id, *token, address))
+        .collect();
+    let state_proofs = get_profit_proofs_at(
+        storage,
+        profit_state,
+        blocks_state,
+        profit_changes_db,
+        block_number,
+        paths.clone(),
+    )?;
+    if <[u8; 32]>::from(state_proofs.root) != profit_root {
+        return Err(anyhow::anyhow!(
+            "the submitted root does not match the one archived with Block #{:}",
+            block_number
+        ));
+    }
+
+    let mut call = WithdrawCall {
+        from: address,
+        to: get_fee_manager_contract_address(),
+        profit_root,
+        block_number,
+        ..Default::default()
+    };
+    let mut args = WithdrawArgs::default();
+    for (i, ((path, leaf), (leave_bitmap, siblings))) in paths
+        .into_iter()
+        .zip(state_proofs.leaves)
+        .zip(state_proofs.paths)
+        .enumerate()
+    {
+        if leaf == ProfitStateData::default() {
+            return Err(anyhow::anyhow!(
+                "no profit of token {:?} on chain {:}",
+                tokens[i].1,
+                tokens[i].0
+            ));
+        }
+        let no1_merge_value = get_no1_merge_value(path, SmtValue::new(leaf.clone())?, leave_bitmap);
+        args.leaves.push((
+            leaf.token_chain_id,
+            leaf.token,
+            address,
+            leaf.balance,
+            leaf.debt,
+        ));
+        args.siblings.push(
+            siblings
+                .iter()
+                .map(|s| s.hash::<Keccak256Hasher>().into())
+                .collect(),
+        );
+        args.start_index.push(no1_merge_value.0);
+        args.first_zero_bits.push(no1_merge_value.1.into());
+        // Bit i of the bitmap is the height i.
+        args.bitmaps
+            .push(U256::from_little_endian(leave_bitmap.as_slice()));
+        args.withdraw_amount
+            .push(amounts.as_ref().map_or(leaf.balance, |a| a[i]));
+        call.proofs.push(ProfitProof {
+            path: path.into(),
+            leave_bitmap: leave_bitmap.into(),
+            token: leaf,
+            siblings,
+            root: profit_root,
+            no1_merge_value,
+        });
+    }
+    call.withdraw_amount = args.withdraw_amount.clone();
+    let (data, simulation_error) = simulate_withdraw(provider, address, args).await?;
+    call.data = data;
+    call.simulation_error = simulation_error;
+    Ok(call)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_profit_proofs_at() {
+        let path = "./db_profit_proofs_at";
+        let _ = std::fs::remove_dir_all(path);
+        let storage = Arc::new(Storage::open(path).unwrap());
+        let profit_state: Arc<RwLock<State<'static, Keccak256Hasher, ProfitStateData>>> =
+            Arc::new(RwLock::new(State::new(
+                primitives::storage::PROFIT_STATE_PREFIX,
+                storage.clone(),
+            )));
+        let blocks_state: Arc<RwLock<State<'static, Keccak256Hasher, BlocksStateData>>> =
+            Arc::new(RwLock::new(State::new(
+                primitives::storage::BLOCKS_STATE_PREFIX,
+                storage.clone(),
+            )));
+        let profit_changes_db = ProfitChangesDB::new(storage.clone()).unwrap();
+        let token = Address::from_low_u64_be(1);
+        let user = Address::from_low_u64_be(2);
+        let key = chain_token_address_convert_to_h256(1, token, user);
+        let leaf = |balance: u64| {
+            let mut leaf = ProfitStateData {
+                token,
+                token_chain_id: 1,
+                balance: U256::from(balance),
+                debt: U256::zero(),
+            };
+            leaf.try_clear().unwrap();
+            leaf
+        };
+
+        // Block 1 credits 100, block 2 another 50.
+        for (block_num, old, new) in [(1u64, 0u64, 100u64), (2, 100, 150)] {
+            let root = profit_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(key, leaf(new))])
+                .unwrap();
+            blocks_state
+                .write()
+                .unwrap()
+                .try_update_all(vec![(
+                    block_number_convert_to_h256(block_num),
+                    BlocksStateData {
+                        block_num,
+                        profit_root: root.into(),
+                        ..Default::default()
+                    },
+                )])
+                .unwrap();
+            profit_changes_db
+                .insert_changes(
+                    block_num,
+                    vec![(
+                        key,
+                        ProfitStateDiff {
+                            chain_id: 1,
+                            token,
+                            address: user,
+                            old_balance: U256::from(old),
+                            new_balance: U256::from(new),
+                            ..Default::default()
+                        },
+                    )],
+                )
+                .unwrap();
+        }
+
+        let proofs = get_profit_proofs_at(
+            &storage,
+            &profit_state,
+            &blocks_state,
+            &profit_changes_db,
+            1,
+            vec![key],
+        )
+        .unwrap();
+        assert_eq!(proofs.leaves, vec![leaf(100)]);
+        // The state itself is not rewound.
+        assert_eq!(
+            profit_state.read().unwrap().try_get(key).unwrap(),
+            leaf(150)
+        );
+        let proofs = get_profit_proofs_at(
+            &storage,
+            &profit_state,
+            &blocks_state,
+            &profit_changes_db,
+            2,
+            vec![key],
+        )
+        .unwrap();
+        assert_eq!(proofs.leaves, vec![leaf(150)]);
+        assert!(get_profit_proofs_at(
+            &storage,
+            &profit_state,
+            &blocks_state,
+            &profit_changes_db,
+            3,
+            vec![key],
+        )
+        .is_err());
+    }
+}